@@ -123,13 +123,14 @@ pub fn main() -> Result<(), ()> {
     let input = DragonflyInput::new(packets);
     let mut buf = vec![0; PACKET_CHANNEL_SIZE];
     
-    let len = input.serialize_dragonfly_format(&mut buf);
+    let result = input.serialize_dragonfly_format(&mut buf);
     
-    if len == buf.len() {
+    if result.is_truncated() {
+        eprintln!("Input does not fit into the packet channel: only {} packets could be serialized", result.packets());
         return Err(());
     }
     
-    std::io::stdout().write_all(&buf[..len]).unwrap();
+    std::io::stdout().write_all(&buf[..result.len()]).unwrap();
     
     Ok(())
 }
@@ -0,0 +1,39 @@
+use clap::Parser;
+use dragonfly::pcap::{PcapImporter, RawPacket};
+
+/// Converts pcap/pcapng captures into corpus entries of RawPackets, one per capture
+#[derive(clap::Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Only import traffic of this server port
+    #[arg(short, long)]
+    port: Option<u16>,
+    
+    /// Ignore TCP streams
+    #[arg(long)]
+    no_tcp: bool,
+    
+    /// Ignore UDP datagrams
+    #[arg(long)]
+    no_udp: bool,
+    
+    /// Directory the inputs are written to
+    #[arg(short, long)]
+    output: String,
+    
+    captures: Vec<String>,
+}
+
+pub fn main() {
+    let args = Args::parse();
+    let mut importer = PcapImporter::new()
+        .tcp(!args.no_tcp)
+        .udp(!args.no_udp);
+    
+    if let Some(port) = args.port {
+        importer = importer.server_port(port);
+    }
+    
+    let count = importer.convert(&args.captures, &args.output, RawPacket::from).unwrap();
+    println!("Wrote {} inputs to {}", count, args.output);
+}
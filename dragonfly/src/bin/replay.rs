@@ -0,0 +1,83 @@
+use clap::Parser;
+use dragonfly::{
+    components::{TargetCommand, load_input, replay_input, debug_input, trace_input},
+    pcap::RawPacket,
+};
+use std::time::Duration;
+
+/// Replays an input of RawPackets, e.g. created by the pcap importer, in an arbitrary target
+#[derive(clap::Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Run the target under gdb
+    #[arg(long, conflicts_with = "trace")]
+    gdb: bool,
+    
+    /// Write a transcript of requests, responses and timings to this file
+    #[arg(long)]
+    trace: Option<String>,
+    
+    /// Program to run under gdb instead of the target
+    #[arg(long)]
+    debug_program: Option<String>,
+    
+    /// Template directory of the filesystem sandbox
+    #[arg(long)]
+    sandbox: Option<String>,
+    
+    /// Environment variables of the target in the form KEY=VALUE
+    #[arg(short, long)]
+    env: Vec<String>,
+    
+    /// Whether the target uses a deferred forkserver
+    #[arg(long)]
+    deferred: bool,
+    
+    /// Timeout in milliseconds
+    #[arg(short, long)]
+    timeout: Option<u64>,
+    
+    /// The input in binary or text format
+    file: String,
+    
+    /// The target command line
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+}
+
+pub fn main() {
+    let args = Args::parse();
+    let mut target = TargetCommand::new(&args.command[0])
+        .args(&args.command[1..])
+        .is_deferred_forkserver(args.deferred);
+    
+    for var in &args.env {
+        let (key, value) = var.split_once('=').unwrap_or_else(|| panic!("Invalid environment variable: {}", var));
+        target = target.env(key, value);
+    }
+    
+    if let Some(program) = &args.debug_program {
+        target = target.debug_program(program);
+    }
+    
+    if let Some(sandbox) = &args.sandbox {
+        target = target.sandbox(sandbox);
+    }
+    
+    if let Some(timeout) = args.timeout {
+        target = target.timeout(Duration::from_millis(timeout));
+    }
+    
+    let input = load_input::<RawPacket, _>(&args.file).unwrap();
+    
+    if args.gdb {
+        debug_input(&input, &target).unwrap();
+    } else if let Some(output) = &args.trace {
+        let trace = trace_input(&input, &target).unwrap();
+        std::fs::write(output, trace.transcript()).unwrap();
+        println!("{:?}", trace.exit_kind());
+    } else {
+        let exit_kind = replay_input(&input, &target).unwrap();
+        println!("{:?}", exit_kind);
+    }
+}
@@ -0,0 +1,3 @@
+mod token;
+pub mod mutators;
+pub use token::*;
@@ -0,0 +1,126 @@
+use crate::binary::{BinaryStream, BinaryToken, mutators::common::{random_leaf, random_range}};
+use libafl_bolts::prelude::Rand;
+
+fn has_nonempty_data(token: &BinaryToken) -> bool {
+    matches!(token.data(), Some(data) if !data.is_empty())
+}
+
+fn can_grow(token: &BinaryToken) -> bool {
+    matches!(token.data(), Some(data) if data.len() < token.max_data_len())
+}
+
+pub fn mutate_bytes_flip<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let bit = rand.below(8);
+    let pos = rand.next();
+    
+    match random_leaf(rand, stream, has_nonempty_data).and_then(BinaryToken::data_mut) {
+        Some(data) => {
+            let idx = (pos % data.len() as u64) as usize;
+            data[idx] ^= 1 << bit;
+            true
+        },
+        None => false,
+    }
+}
+
+pub fn mutate_bytes_random_insert<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let len = 1 + rand.below(16) as usize;
+    let mut new_bytes = vec![0; len];
+    
+    for byte in &mut new_bytes {
+        *byte = rand.next() as u8;
+    }
+    
+    let pos = rand.next();
+    
+    let Some(token) = random_leaf(rand, stream, can_grow) else {
+        return false;
+    };
+    let room = token.max_data_len() - token.data().map_or(0, <[u8]>::len);
+    new_bytes.truncate(room);
+    
+    match token.data_mut() {
+        Some(data) => {
+            let idx = (pos % (data.len() as u64 + 1)) as usize;
+            data.splice(idx..idx, new_bytes);
+            true
+        },
+        None => false,
+    }
+}
+
+pub fn mutate_bytes_delete<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    match random_leaf(rand, stream, has_nonempty_data).and_then(BinaryToken::data_mut) {
+        Some(data) => {
+            let range = random_range(rand, data.len(), data.len());
+            data.splice(range, []);
+            true
+        },
+        None => false,
+    }
+}
+
+pub fn mutate_bytes_repeat<R: Rand, const AMNT: usize>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let Some(token) = random_leaf(rand, stream, |x| has_nonempty_data(x) && can_grow(x)) else {
+        return false;
+    };
+    let max_len = std::cmp::min(AMNT, token.max_data_len());
+    
+    match token.data_mut() {
+        Some(data) => {
+            if data.len() >= max_len {
+                return false;
+            }
+            
+            let n = max_len - data.len();
+            let idx = rand.below(data.len() as u64) as usize;
+            let c = data[idx];
+            data.splice(idx..idx, vec![c; n]);
+            true
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    
+    #[test]
+    fn test_bytes_mutators() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let stream = "u8:0 \"GET \" blob16be:\"/index.html\" xor8{ hex:00 }".parse::<BinaryStream>().unwrap();
+        
+        for _ in 0..100 {
+            let mut stream = stream.clone();
+            
+            match rand.below(4) {
+                0 => mutate_bytes_flip(&mut rand, &mut stream),
+                1 => mutate_bytes_random_insert(&mut rand, &mut stream),
+                2 => mutate_bytes_delete(&mut rand, &mut stream),
+                3 => mutate_bytes_repeat::<_, 32>(&mut rand, &mut stream),
+                _ => unreachable!(),
+            };
+            
+            assert_eq!(stream.len(), 4);
+            println!("{}", stream);
+        }
+        
+        let mut stream = "u8:0".parse::<BinaryStream>().unwrap();
+        assert!(!mutate_bytes_flip(&mut rand, &mut stream));
+        assert!(!mutate_bytes_random_insert(&mut rand, &mut stream));
+        
+        /* Blobs never outgrow their length prefix */
+        let mut stream = format!("blob8:hex:{}", "00".repeat(250)).parse::<BinaryStream>().unwrap();
+        
+        for _ in 0..100 {
+            mutate_bytes_random_insert(&mut rand, &mut stream);
+            mutate_bytes_repeat::<_, 4096>(&mut rand, &mut stream);
+            assert!(stream.tokens()[0].data().unwrap().len() <= 255);
+        }
+        
+        assert!(!mutate_bytes_random_insert(&mut rand, &mut stream));
+        assert!(!mutate_bytes_repeat::<_, 4096>(&mut rand, &mut stream));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::binary::{BinaryStream, BinaryToken};
+use libafl_bolts::prelude::Rand;
+use std::ops::Range;
+
+#[inline]
+pub(crate) fn random_range<R: Rand>(rand: &mut R, limit: usize, max_size: usize) -> Range<usize> {
+    debug_assert!(limit > 0);
+    debug_assert!(max_size > 0);
+    let start = rand.below(limit as u64) as usize;
+    let rem_len = std::cmp::min(limit - start, max_size);
+    let len = 1 + rand.below(rem_len as u64) as usize;
+    debug_assert!(len <= max_size);
+    start..start + len
+}
+
+fn count_leaves<F: Fn(&BinaryToken) -> bool>(tokens: &[BinaryToken], pred: &F) -> usize {
+    let mut count = 0;
+    
+    for token in tokens {
+        if let BinaryToken::Checksummed { tokens, .. } = token {
+            count += count_leaves(tokens, pred);
+        } else if pred(token) {
+            count += 1;
+        }
+    }
+    
+    count
+}
+
+fn find_leaf<'a, F: Fn(&BinaryToken) -> bool>(tokens: &'a mut [BinaryToken], pred: &F, n: &mut usize) -> Option<&'a mut BinaryToken> {
+    for token in tokens {
+        if let BinaryToken::Checksummed { tokens, .. } = token {
+            if let Some(leaf) = find_leaf(tokens, pred, n) {
+                return Some(leaf);
+            }
+        } else if pred(token) {
+            if *n == 0 {
+                return Some(token);
+            }
+            
+            *n -= 1;
+        }
+    }
+    
+    None
+}
+
+/// Choose a random non-container token that satisfies `pred` from anywhere in the stream
+pub(crate) fn random_leaf<'a, R: Rand, F: Fn(&BinaryToken) -> bool>(rand: &mut R, stream: &'a mut BinaryStream, pred: F) -> Option<&'a mut BinaryToken> {
+    let count = count_leaves(stream.tokens(), &pred);
+    
+    if count == 0 {
+        return None;
+    }
+    
+    let mut n = rand.below(count as u64) as usize;
+    find_leaf(stream.tokens_mut(), &pred, &mut n)
+}
+
+fn count_lists(tokens: &[BinaryToken]) -> usize {
+    let mut count = 1;
+    
+    for token in tokens {
+        if let BinaryToken::Checksummed { tokens, .. } = token {
+            count += count_lists(tokens);
+        }
+    }
+    
+    count
+}
+
+fn find_list<'a>(tokens: &'a mut Vec<BinaryToken>, n: &mut usize) -> Option<&'a mut Vec<BinaryToken>> {
+    if *n == 0 {
+        return Some(tokens);
+    }
+    
+    *n -= 1;
+    
+    for token in tokens.iter_mut() {
+        if let BinaryToken::Checksummed { tokens, .. } = token {
+            if let Some(list) = find_list(tokens, n) {
+                return Some(list);
+            }
+        }
+    }
+    
+    None
+}
+
+/// Choose a random token list, i.e. either the top-level tokens or the content of a checksummed region
+pub(crate) fn random_list<'a, R: Rand>(rand: &mut R, stream: &'a mut BinaryStream) -> &'a mut Vec<BinaryToken> {
+    let count = count_lists(stream.tokens());
+    let mut n = rand.below(count as u64) as usize;
+    let list = find_list(stream.tokens_mut(), &mut n);
+    debug_assert!(list.is_some());
+    unsafe { list.unwrap_unchecked() }
+}
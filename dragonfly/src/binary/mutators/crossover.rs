@@ -0,0 +1,71 @@
+use crate::binary::{BinaryStream, mutators::common::random_list};
+use libafl_bolts::prelude::Rand;
+
+pub fn mutate_token_crossover_insert<R: Rand>(rand: &mut R, stream: &mut BinaryStream, other: &BinaryStream, max_tokens: usize) -> bool {
+    if other.is_empty() {
+        return false;
+    }
+    
+    let count = stream.count();
+    let token = &other.tokens()[rand.below(other.len() as u64) as usize];
+    
+    if count + token.count() > max_tokens {
+        return false;
+    }
+    
+    let list = random_list(rand, stream);
+    let idx = rand.below(list.len() as u64 + 1) as usize;
+    list.insert(idx, token.clone());
+    
+    true
+}
+
+pub fn mutate_token_crossover_replace<R: Rand>(rand: &mut R, stream: &mut BinaryStream, other: &BinaryStream, max_tokens: usize) -> bool {
+    if other.is_empty() {
+        return false;
+    }
+    
+    let count = stream.count();
+    let token = &other.tokens()[rand.below(other.len() as u64) as usize];
+    let list = random_list(rand, stream);
+    
+    if list.is_empty() {
+        return false;
+    }
+    
+    let idx = rand.below(list.len() as u64) as usize;
+    
+    if count - list[idx].count() + token.count() > max_tokens {
+        return false;
+    }
+    
+    list[idx] = token.clone();
+    
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    
+    #[test]
+    fn test_crossover() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let stream1 = "u8:1 crc32be{ u8:2 } u8:3".parse::<BinaryStream>().unwrap();
+        let stream2 = "\"abc\" xor8{ \"def\" u16le:4 }".parse::<BinaryStream>().unwrap();
+        
+        for _ in 0..20 {
+            let mut stream = stream1.clone();
+            
+            if rand.below(2) == 0 {
+                mutate_token_crossover_insert(&mut rand, &mut stream, &stream2, 8);
+            } else {
+                mutate_token_crossover_replace(&mut rand, &mut stream, &stream2, 8);
+            }
+            
+            assert!(stream.count() <= 8);
+            println!("{}", stream);
+        }
+    }
+}
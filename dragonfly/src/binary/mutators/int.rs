@@ -0,0 +1,125 @@
+use crate::binary::{BinaryStream, BinaryToken, mutators::common::random_leaf};
+use libafl_bolts::prelude::Rand;
+
+const ARITH_MAX: u64 = 35;
+
+const INTERESTING: [u64; 18] = [
+    0,
+    1,
+    2,
+    16,
+    32,
+    64,
+    100,
+    127,
+    128,
+    255,
+    256,
+    512,
+    1000,
+    1024,
+    4096,
+    32767,
+    32768,
+    65535,
+];
+
+pub fn mutate_int_arith<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let delta = 1 + rand.below(ARITH_MAX);
+    let add = rand.below(2) == 0;
+    
+    match random_leaf(rand, stream, BinaryToken::is_int) {
+        Some(BinaryToken::Int { value, width, .. }) => {
+            let new_value = if add {
+                value.wrapping_add(delta)
+            } else {
+                value.wrapping_sub(delta)
+            };
+            *value = new_value & width.max_value();
+            true
+        },
+        _ => false,
+    }
+}
+
+pub fn mutate_int_interesting<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let boundary = rand.below(6);
+    let interesting = rand.choose(INTERESTING);
+    
+    match random_leaf(rand, stream, BinaryToken::is_int) {
+        Some(BinaryToken::Int { value, width, .. }) => {
+            let max = width.max_value();
+            let new_value = match boundary {
+                0 => max,
+                1 => max - 1,
+                2 => max >> 1,
+                3 => (max >> 1) + 1,
+                4 ..= 5 => interesting,
+                _ => unreachable!(),
+            };
+            *value = new_value & max;
+            true
+        },
+        _ => false,
+    }
+}
+
+pub fn mutate_int_random<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let random = rand.next();
+    
+    match random_leaf(rand, stream, BinaryToken::is_int) {
+        Some(BinaryToken::Int { value, width, .. }) => {
+            *value = random & width.max_value();
+            true
+        },
+        _ => false,
+    }
+}
+
+pub fn mutate_int_flip<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let bit = rand.below(64);
+    
+    match random_leaf(rand, stream, BinaryToken::is_int) {
+        Some(BinaryToken::Int { value, width, .. }) => {
+            let bit = bit % (width.bytes() as u64 * 8);
+            *value ^= 1 << bit;
+            true
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    
+    #[test]
+    fn test_int_mutators() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let stream = "u8:0 u16be:1 crc32le{ u32le:2 } u64be:3".parse::<BinaryStream>().unwrap();
+        
+        for _ in 0..100 {
+            let mut stream = stream.clone();
+            
+            assert!(match rand.below(4) {
+                0 => mutate_int_arith(&mut rand, &mut stream),
+                1 => mutate_int_interesting(&mut rand, &mut stream),
+                2 => mutate_int_random(&mut rand, &mut stream),
+                3 => mutate_int_flip(&mut rand, &mut stream),
+                _ => unreachable!(),
+            });
+            
+            for token in stream.tokens() {
+                if let BinaryToken::Int { value, width, .. } = token {
+                    assert!(*value <= width.max_value());
+                }
+            }
+            
+            println!("{}", stream);
+        }
+        
+        let mut stream = "\"no ints here\"".parse::<BinaryStream>().unwrap();
+        assert!(!mutate_int_arith(&mut rand, &mut stream));
+    }
+}
@@ -0,0 +1,78 @@
+mod common;
+mod int;
+mod bytes;
+mod structure;
+mod crossover;
+
+pub use int::*;
+pub use bytes::*;
+pub use structure::*;
+pub use crossover::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{BinaryStream, BinaryToken};
+    use libafl_bolts::prelude::{StdRand, current_nanos, Rand};
+    
+    fn verify(tokens: &[BinaryToken]) {
+        for token in tokens {
+            match token {
+                BinaryToken::Int { value, width, .. } => assert!(*value <= width.max_value()),
+                BinaryToken::Blob { data, prefix, .. } => assert!(data.len() as u64 <= prefix.max_value()),
+                BinaryToken::Checksummed { tokens, .. } => verify(tokens),
+                _ => {},
+            }
+        }
+    }
+    
+    #[test]
+    fn fuzz_mutators() {
+        let stream = "u16be:0x1234 u16be:0x0100 blob8:\"example\" crc32le{ u8:0 hex:0001 inet16be{ \"abc\" } }".parse::<BinaryStream>().unwrap();
+        let mut rand = StdRand::with_seed(current_nanos());
+        const MAX_TOKENS: usize = 64;
+        
+        for _ in 0..100 {
+            let mut stream = stream.clone();
+            
+            for _ in 0..1000 {
+                let mutation = rand.below(13);
+                
+                let mutated = match mutation {
+                    0 => mutate_int_arith(&mut rand, &mut stream),
+                    1 => mutate_int_interesting(&mut rand, &mut stream),
+                    2 => mutate_int_random(&mut rand, &mut stream),
+                    3 => mutate_int_flip(&mut rand, &mut stream),
+                    4 => mutate_bytes_flip(&mut rand, &mut stream),
+                    5 => mutate_bytes_random_insert(&mut rand, &mut stream),
+                    6 => mutate_bytes_delete(&mut rand, &mut stream),
+                    7 => mutate_bytes_repeat::<_, 64>(&mut rand, &mut stream),
+                    8 => mutate_token_delete(&mut rand, &mut stream),
+                    9 => mutate_token_copy(&mut rand, &mut stream, MAX_TOKENS),
+                    10 => mutate_token_swap(&mut rand, &mut stream),
+                    11 => {
+                        let other = stream.clone();
+                        mutate_token_crossover_insert(&mut rand, &mut stream, &other, MAX_TOKENS)
+                    },
+                    12 => {
+                        let other = stream.clone();
+                        mutate_token_crossover_replace(&mut rand, &mut stream, &other, MAX_TOKENS)
+                    },
+                    _ => unreachable!(),
+                };
+                
+                if mutated {
+                    if stream.count() > MAX_TOKENS {
+                        panic!("Mutation #{} went out of bounds for number of tokens", mutation);
+                    }
+                    
+                    verify(stream.tokens());
+                }
+            }
+            
+            let mut buffer = vec![0; stream.serialized_len()];
+            assert_eq!(stream.serialize_into_buffer(&mut buffer), buffer.len());
+            assert_eq!(stream.to_string().parse::<BinaryStream>().unwrap(), stream);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::binary::{BinaryStream, mutators::common::{random_list, random_range}};
+use libafl_bolts::prelude::Rand;
+
+pub fn mutate_token_delete<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let list = random_list(rand, stream);
+    
+    if list.is_empty() {
+        return false;
+    }
+    
+    let range = random_range(rand, list.len(), list.len());
+    list.splice(range, []);
+    
+    true
+}
+
+pub fn mutate_token_copy<R: Rand>(rand: &mut R, stream: &mut BinaryStream, max_tokens: usize) -> bool {
+    let count = stream.count();
+    
+    if count >= max_tokens {
+        return false;
+    }
+    
+    let list = random_list(rand, stream);
+    
+    if list.is_empty() {
+        return false;
+    }
+    
+    let from = rand.below(list.len() as u64) as usize;
+    let to = rand.below(list.len() as u64 + 1) as usize;
+    
+    if count + list[from].count() > max_tokens {
+        return false;
+    }
+    
+    let token = list[from].clone();
+    list.insert(to, token);
+    
+    true
+}
+
+pub fn mutate_token_swap<R: Rand>(rand: &mut R, stream: &mut BinaryStream) -> bool {
+    let list = random_list(rand, stream);
+    
+    if list.len() < 2 {
+        return false;
+    }
+    
+    let from = rand.below(list.len() as u64) as usize;
+    let to = rand.below(list.len() as u64) as usize;
+    
+    if from == to {
+        return false;
+    }
+    
+    list.swap(from, to);
+    
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    
+    #[test]
+    fn test_structure_mutators() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let stream = "u8:1 u8:2 crc32be{ u8:3 u8:4 } u8:5".parse::<BinaryStream>().unwrap();
+        
+        for _ in 0..100 {
+            let mut stream = stream.clone();
+            
+            match rand.below(3) {
+                0 => mutate_token_delete(&mut rand, &mut stream),
+                1 => mutate_token_copy(&mut rand, &mut stream, 8),
+                2 => mutate_token_swap(&mut rand, &mut stream),
+                _ => unreachable!(),
+            };
+            
+            assert!(stream.count() <= 8);
+            println!("{}", stream);
+        }
+    }
+}
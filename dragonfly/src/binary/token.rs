@@ -0,0 +1,781 @@
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+use std::fmt;
+use crate::components::{Packet, fixup::Field};
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Hash, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Hash, PartialEq, Eq)]
+pub enum IntWidth {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntWidth {
+    #[inline]
+    pub fn bytes(&self) -> usize {
+        match self {
+            IntWidth::U8 => 1,
+            IntWidth::U16 => 2,
+            IntWidth::U32 => 4,
+            IntWidth::U64 => 8,
+        }
+    }
+    
+    #[inline]
+    pub fn max_value(&self) -> u64 {
+        match self {
+            IntWidth::U8 => u8::MAX as u64,
+            IntWidth::U16 => u16::MAX as u64,
+            IntWidth::U32 => u32::MAX as u64,
+            IntWidth::U64 => u64::MAX,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Hash, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC-32 (IEEE 802.3), 4 bytes
+    Crc32,
+    /// Ones' complement sum of 16-bit words as used by IP, TCP and UDP, 2 bytes
+    Internet,
+    /// XOR of all bytes, 1 byte
+    Xor8,
+}
+
+impl ChecksumAlgorithm {
+    #[inline]
+    pub fn width(&self) -> IntWidth {
+        match self {
+            ChecksumAlgorithm::Crc32 => IntWidth::U32,
+            ChecksumAlgorithm::Internet => IntWidth::U16,
+            ChecksumAlgorithm::Xor8 => IntWidth::U8,
+        }
+    }
+    
+    pub fn compute(&self, data: &[u8]) -> u64 {
+        match self {
+            ChecksumAlgorithm::Crc32 => {
+                let mut crc = 0xFFFFFFFFu32;
+                
+                for byte in data {
+                    crc ^= *byte as u32;
+                    
+                    for _ in 0..8 {
+                        let mask = (crc & 1).wrapping_neg();
+                        crc = (crc >> 1) ^ (0xEDB88320 & mask);
+                    }
+                }
+                
+                !crc as u64
+            },
+            ChecksumAlgorithm::Internet => {
+                let mut sum = 0u64;
+                
+                for chunk in data.chunks(2) {
+                    let word = if chunk.len() == 2 {
+                        ((chunk[0] as u64) << 8) | chunk[1] as u64
+                    } else {
+                        (chunk[0] as u64) << 8
+                    };
+                    sum += word;
+                }
+                
+                while (sum >> 16) != 0 {
+                    sum = (sum & 0xFFFF) + (sum >> 16);
+                }
+                
+                !sum & 0xFFFF
+            },
+            ChecksumAlgorithm::Xor8 => {
+                let mut x = 0u8;
+                
+                for byte in data {
+                    x ^= *byte;
+                }
+                
+                x as u64
+            },
+        }
+    }
+}
+
+#[inline]
+fn write_int(buffer: &mut [u8], value: u64, width: IntWidth, endian: Endianness) -> usize {
+    let w = width.bytes();
+    let bytes = match endian {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    };
+    let bytes = match endian {
+        Endianness::Little => &bytes[..w],
+        Endianness::Big => &bytes[8 - w..],
+    };
+    let len = std::cmp::min(buffer.len(), w);
+    buffer[..len].copy_from_slice(&bytes[..len]);
+    len
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Hash, PartialEq, Eq)]
+pub enum BinaryToken {
+    /// A fixed-width unsigned integer
+    Int {
+        value: u64,
+        width: IntWidth,
+        endian: Endianness,
+    },
+    /// Raw bytes without any structure
+    Bytes(Vec<u8>),
+    /// Bytes that are prefixed with their length
+    Blob {
+        data: Vec<u8>,
+        prefix: IntWidth,
+        endian: Endianness,
+    },
+    /// A region of tokens that is followed by a checksum over its serialized content
+    Checksummed {
+        tokens: Vec<BinaryToken>,
+        algorithm: ChecksumAlgorithm,
+        endian: Endianness,
+    },
+}
+
+impl BinaryToken {
+    #[inline]
+    pub fn is_int(&self) -> bool {
+        matches!(self, BinaryToken::Int { .. })
+    }
+    
+    #[inline]
+    pub fn is_bytes(&self) -> bool {
+        matches!(self, BinaryToken::Bytes(_))
+    }
+    
+    #[inline]
+    pub fn is_blob(&self) -> bool {
+        matches!(self, BinaryToken::Blob { .. })
+    }
+    
+    #[inline]
+    pub fn is_checksummed(&self) -> bool {
+        matches!(self, BinaryToken::Checksummed { .. })
+    }
+    
+    #[inline]
+    pub fn data(&self) -> Option<&[u8]> {
+        match self {
+            BinaryToken::Bytes(data) |
+            BinaryToken::Blob { data, .. } => Some(data),
+            _ => None,
+        }
+    }
+    
+    #[inline]
+    pub(crate) fn data_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match self {
+            BinaryToken::Bytes(data) |
+            BinaryToken::Blob { data, .. } => Some(data),
+            _ => None,
+        }
+    }
+    
+    /// Maximum number of bytes the token can hold. Blobs are limited by the width of their length prefix.
+    #[inline]
+    pub fn max_data_len(&self) -> usize {
+        match self {
+            BinaryToken::Blob { prefix, .. } => usize::try_from(prefix.max_value()).unwrap_or(usize::MAX),
+            _ => usize::MAX,
+        }
+    }
+    
+    /// Number of tokens including this one and all nested tokens
+    pub fn count(&self) -> usize {
+        match self {
+            BinaryToken::Checksummed { tokens, .. } => 1 + tokens.iter().map(BinaryToken::count).sum::<usize>(),
+            _ => 1,
+        }
+    }
+    
+    pub fn serialize_into_buffer(&self, buffer: &mut [u8]) -> usize {
+        self.write(buffer, true)
+    }
+    
+    /// Serializes the token, writing 0 for length prefixes and checksums unless `fixups` is set
+    fn write(&self, buffer: &mut [u8], fixups: bool) -> usize {
+        match self {
+            BinaryToken::Int { value, width, endian } => write_int(buffer, *value, *width, *endian),
+            BinaryToken::Bytes(data) => {
+                let len = std::cmp::min(buffer.len(), data.len());
+                buffer[..len].copy_from_slice(&data[..len]);
+                len
+            },
+            BinaryToken::Blob { data, prefix, endian } => {
+                let len = if fixups { data.len() as u64 } else { 0 };
+                let mut cursor = write_int(buffer, len, *prefix, *endian);
+                let len = std::cmp::min(buffer.len() - cursor, data.len());
+                buffer[cursor..cursor + len].copy_from_slice(&data[..len]);
+                cursor += len;
+                cursor
+            },
+            BinaryToken::Checksummed { tokens, algorithm, endian } => {
+                let mut cursor = 0;
+                
+                for token in tokens {
+                    cursor += token.write(&mut buffer[cursor..], fixups);
+                }
+                
+                let checksum = if fixups { algorithm.compute(&buffer[..cursor]) } else { 0 };
+                cursor += write_int(&mut buffer[cursor..], checksum, algorithm.width(), *endian);
+                cursor
+            },
+        }
+    }
+    
+    /// Fills in the length prefixes and checksums of a token that was written without them at `offset` of `content`.
+    /// Returns the offset after the token.
+    fn fixup(&self, content: &mut [u8], offset: usize) -> usize {
+        match self {
+            BinaryToken::Blob { data, prefix, endian } => {
+                field(offset, *prefix, *endian).write(content, data.len() as u64);
+                offset + self.serialized_len()
+            },
+            BinaryToken::Checksummed { tokens, algorithm, endian } => {
+                let mut cursor = offset;
+                
+                /* Inner fields first, they are part of the checksummed region */
+                for token in tokens {
+                    cursor = token.fixup(content, cursor);
+                }
+                
+                let end = std::cmp::min(cursor, content.len());
+                let checksum = algorithm.compute(&content[std::cmp::min(offset, end)..end]);
+                field(cursor, algorithm.width(), *endian).write(content, checksum);
+                cursor + algorithm.width().bytes()
+            },
+            _ => offset + self.serialized_len(),
+        }
+    }
+    
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            BinaryToken::Int { width, .. } => width.bytes(),
+            BinaryToken::Bytes(data) => data.len(),
+            BinaryToken::Blob { data, prefix, .. } => prefix.bytes() + data.len(),
+            BinaryToken::Checksummed { tokens, algorithm, .. } => {
+                tokens.iter().map(BinaryToken::serialized_len).sum::<usize>() + algorithm.width().bytes()
+            },
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct BinaryStream(Vec<BinaryToken>);
+
+impl BinaryStream {
+    pub fn new(tokens: Vec<BinaryToken>) -> Self {
+        Self(tokens)
+    }
+    
+    #[inline]
+    pub fn tokens(&self) -> &[BinaryToken] {
+        &self.0
+    }
+    
+    #[inline]
+    pub(crate) fn tokens_mut(&mut self) -> &mut Vec<BinaryToken> {
+        &mut self.0
+    }
+    
+    /// Number of top-level tokens
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    
+    /// Number of tokens including all nested tokens
+    pub fn count(&self) -> usize {
+        self.0.iter().map(BinaryToken::count).sum()
+    }
+    
+    pub fn serialize_into_buffer(&self, buffer: &mut [u8]) -> usize {
+        let mut cursor = 0;
+        
+        for token in &self.0 {
+            cursor += token.serialize_into_buffer(&mut buffer[cursor..]);
+        }
+        
+        cursor
+    }
+    
+    pub fn serialized_len(&self) -> usize {
+        self.0.iter().map(BinaryToken::serialized_len).sum()
+    }
+}
+
+fn field(offset: usize, width: IntWidth, endian: Endianness) -> Field {
+    match endian {
+        Endianness::Little => Field::le(offset, width.bytes()),
+        Endianness::Big => Field::be(offset, width.bytes()),
+    }
+}
+
+/// The length prefixes of blobs and the checksums are written as 0 and filled in by the fixup,
+/// so that they stay wrong when the fixups are skipped.
+impl Packet for BinaryStream {
+    fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
+        let mut cursor = 0;
+        
+        for token in &self.0 {
+            cursor += token.write(&mut buffer[cursor..], false);
+        }
+        
+        Some(cursor)
+    }
+    
+    fn fixup_content(&self, content: &mut [u8]) {
+        let mut cursor = 0;
+        
+        for token in &self.0 {
+            cursor = token.fixup(content, cursor);
+        }
+    }
+}
+
+pub trait HasBinaryStream {
+    fn binary_stream(&self) -> &BinaryStream;
+    fn binary_stream_mut(&mut self) -> &mut BinaryStream;
+    fn has_binary_stream(&self) -> bool {
+        true
+    }
+}
+
+impl HasBinaryStream for BinaryStream {
+    fn binary_stream(&self) -> &BinaryStream {
+        self
+    }
+    
+    fn binary_stream_mut(&mut self) -> &mut BinaryStream {
+        self
+    }
+}
+
+/*** Textual notation ***/
+
+/*
+ * A BinaryStream can be written down as a whitespace separated list of tokens:
+ *   - integers:           u8:1  u16be:0x1234  u32le:7  u64be:0
+ *   - raw bytes:          "GET \x00\r\n"  or  hex:deadbeef
+ *   - length-prefixed:    blob16be:"hello"  or  blob8:hex:0102
+ *   - checksummed region: crc32le{ u8:1 "abc" }  inet16be{ ... }  xor8{ ... }
+ * The error value of the parser is the offset of the offending character.
+ */
+
+struct Parser<'a> {
+    s: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.cursor < self.s.len() && self.s[self.cursor].is_ascii_whitespace() {
+            self.cursor += 1;
+        }
+    }
+    
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.cursor).copied()
+    }
+    
+    fn eat(&mut self, c: u8) -> Result<(), usize> {
+        if self.peek() == Some(c) {
+            self.cursor += 1;
+            Ok(())
+        } else {
+            Err(self.cursor)
+        }
+    }
+    
+    fn ident(&mut self) -> &'a [u8] {
+        let start = self.cursor;
+        
+        while self.cursor < self.s.len() && self.s[self.cursor].is_ascii_alphanumeric() {
+            self.cursor += 1;
+        }
+        
+        &self.s[start..self.cursor]
+    }
+    
+    fn number(&mut self) -> Result<u64, usize> {
+        let start = self.cursor;
+        let (radix, digits_start) = if self.s[start..].starts_with(b"0x") {
+            (16, start + 2)
+        } else {
+            (10, start)
+        };
+        self.cursor = digits_start;
+        
+        while self.cursor < self.s.len() && (self.s[self.cursor] as char).is_digit(radix) {
+            self.cursor += 1;
+        }
+        
+        let digits = std::str::from_utf8(&self.s[digits_start..self.cursor]).map_err(|_| start)?;
+        u64::from_str_radix(digits, radix).map_err(|_| start)
+    }
+    
+    fn hex_digit(&mut self) -> Result<u8, usize> {
+        let c = self.peek().ok_or(self.cursor)?;
+        let value = (c as char).to_digit(16).ok_or(self.cursor)?;
+        self.cursor += 1;
+        Ok(value as u8)
+    }
+    
+    fn bytes(&mut self) -> Result<Vec<u8>, usize> {
+        let mut data = Vec::new();
+        
+        if self.peek() == Some(b'"') {
+            self.cursor += 1;
+            
+            loop {
+                match self.peek() {
+                    None => return Err(self.cursor),
+                    Some(b'"') => {
+                        self.cursor += 1;
+                        break;
+                    },
+                    Some(b'\\') => {
+                        self.cursor += 1;
+                        let c = self.peek().ok_or(self.cursor)?;
+                        self.cursor += 1;
+                        
+                        match c {
+                            b'\\' | b'"' => data.push(c),
+                            b'n' => data.push(b'\n'),
+                            b'r' => data.push(b'\r'),
+                            b't' => data.push(b'\t'),
+                            b'x' => {
+                                let upper = self.hex_digit()?;
+                                let lower = self.hex_digit()?;
+                                data.push((upper << 4) | lower);
+                            },
+                            _ => return Err(self.cursor - 1),
+                        }
+                    },
+                    Some(c) => {
+                        data.push(c);
+                        self.cursor += 1;
+                    },
+                }
+            }
+        } else {
+            let start = self.cursor;
+            
+            if self.ident() != b"hex" {
+                return Err(start);
+            }
+            
+            self.eat(b':')?;
+            
+            while matches!(self.peek(), Some(c) if c.is_ascii_hexdigit()) {
+                let upper = self.hex_digit()?;
+                let lower = self.hex_digit()?;
+                data.push((upper << 4) | lower);
+            }
+        }
+        
+        Ok(data)
+    }
+    
+    fn tokens(&mut self, nested: bool) -> Result<Vec<BinaryToken>, usize> {
+        let mut tokens = Vec::new();
+        
+        loop {
+            self.skip_whitespace();
+            
+            match self.peek() {
+                None => {
+                    if nested {
+                        return Err(self.cursor);
+                    }
+                    break;
+                },
+                Some(b'}') => {
+                    if !nested {
+                        return Err(self.cursor);
+                    }
+                    self.cursor += 1;
+                    break;
+                },
+                Some(b'"') => tokens.push(BinaryToken::Bytes(self.bytes()?)),
+                Some(_) => tokens.push(self.token()?),
+            }
+        }
+        
+        Ok(tokens)
+    }
+    
+    fn token(&mut self) -> Result<BinaryToken, usize> {
+        let start = self.cursor;
+        let ident = self.ident();
+        
+        if ident == b"hex" {
+            self.cursor = start;
+            return Ok(BinaryToken::Bytes(self.bytes()?));
+        }
+        
+        let (kind, width, endian) = parse_type_name(ident).ok_or(start)?;
+        
+        match kind {
+            TypeName::Int => {
+                self.eat(b':')?;
+                let value = self.number()?;
+                
+                if value > width.max_value() {
+                    return Err(start);
+                }
+                
+                Ok(BinaryToken::Int { value, width, endian })
+            },
+            TypeName::Blob => {
+                self.eat(b':')?;
+                let data = self.bytes()?;
+                
+                if data.len() as u64 > width.max_value() {
+                    return Err(start);
+                }
+                
+                Ok(BinaryToken::Blob { data, prefix: width, endian })
+            },
+            TypeName::Checksum(algorithm) => {
+                if algorithm.width() != width {
+                    return Err(start);
+                }
+                
+                self.skip_whitespace();
+                self.eat(b'{')?;
+                let tokens = self.tokens(true)?;
+                Ok(BinaryToken::Checksummed { tokens, algorithm, endian })
+            },
+        }
+    }
+}
+
+enum TypeName {
+    Int,
+    Blob,
+    Checksum(ChecksumAlgorithm),
+}
+
+fn parse_type_name(ident: &[u8]) -> Option<(TypeName, IntWidth, Endianness)> {
+    let (kind, rest) = if let Some(rest) = ident.strip_prefix(b"blob") {
+        (TypeName::Blob, rest)
+    } else if let Some(rest) = ident.strip_prefix(b"crc") {
+        (TypeName::Checksum(ChecksumAlgorithm::Crc32), rest)
+    } else if let Some(rest) = ident.strip_prefix(b"inet") {
+        (TypeName::Checksum(ChecksumAlgorithm::Internet), rest)
+    } else if let Some(rest) = ident.strip_prefix(b"xor") {
+        (TypeName::Checksum(ChecksumAlgorithm::Xor8), rest)
+    } else if let Some(rest) = ident.strip_prefix(b"u") {
+        (TypeName::Int, rest)
+    } else {
+        return None;
+    };
+    
+    let (width, rest) = if let Some(rest) = rest.strip_prefix(b"8") {
+        (IntWidth::U8, rest)
+    } else if let Some(rest) = rest.strip_prefix(b"16") {
+        (IntWidth::U16, rest)
+    } else if let Some(rest) = rest.strip_prefix(b"32") {
+        (IntWidth::U32, rest)
+    } else if let Some(rest) = rest.strip_prefix(b"64") {
+        (IntWidth::U64, rest)
+    } else {
+        return None;
+    };
+    
+    let endian = match rest {
+        b"le" => Endianness::Little,
+        b"be" => Endianness::Big,
+        b"" if width == IntWidth::U8 => Endianness::Big,
+        _ => return None,
+    };
+    
+    Some((kind, width, endian))
+}
+
+impl FromStr for BinaryStream {
+    type Err = usize;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            s: s.as_bytes(),
+            cursor: 0,
+        };
+        let tokens = parser.tokens(false)?;
+        Ok(BinaryStream(tokens))
+    }
+}
+
+fn type_suffix(width: IntWidth, endian: Endianness) -> &'static str {
+    match (width, endian) {
+        (IntWidth::U8, _) => "8",
+        (IntWidth::U16, Endianness::Little) => "16le",
+        (IntWidth::U16, Endianness::Big) => "16be",
+        (IntWidth::U32, Endianness::Little) => "32le",
+        (IntWidth::U32, Endianness::Big) => "32be",
+        (IntWidth::U64, Endianness::Little) => "64le",
+        (IntWidth::U64, Endianness::Big) => "64be",
+    }
+}
+
+fn fmt_bytes(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    
+    for byte in data {
+        match *byte {
+            b'\\' => write!(f, "\\\\")?,
+            b'"' => write!(f, "\\\"")?,
+            b'\n' => write!(f, "\\n")?,
+            b'\r' => write!(f, "\\r")?,
+            b'\t' => write!(f, "\\t")?,
+            0x20..=0x7e => write!(f, "{}", *byte as char)?,
+            _ => write!(f, "\\x{:02x}", byte)?,
+        }
+    }
+    
+    write!(f, "\"")
+}
+
+impl fmt::Display for BinaryToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryToken::Int { value, width, endian } => write!(f, "u{}:{}", type_suffix(*width, *endian), value),
+            BinaryToken::Bytes(data) => fmt_bytes(f, data),
+            BinaryToken::Blob { data, prefix, endian } => {
+                write!(f, "blob{}:", type_suffix(*prefix, *endian))?;
+                fmt_bytes(f, data)
+            },
+            BinaryToken::Checksummed { tokens, algorithm, endian } => {
+                let name = match algorithm {
+                    ChecksumAlgorithm::Crc32 => "crc",
+                    ChecksumAlgorithm::Internet => "inet",
+                    ChecksumAlgorithm::Xor8 => "xor",
+                };
+                write!(f, "{}{}{{", name, type_suffix(algorithm.width(), *endian))?;
+                
+                for token in tokens {
+                    write!(f, " {}", token)?;
+                }
+                
+                write!(f, " }}")
+            },
+        }
+    }
+}
+
+impl fmt::Display for BinaryStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, token) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            
+            write!(f, "{}", token)?;
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn serialize(stream: &BinaryStream) -> Vec<u8> {
+        let mut buffer = vec![0; stream.serialized_len()];
+        let len = stream.serialize_into_buffer(&mut buffer);
+        assert_eq!(len, buffer.len());
+        buffer
+    }
+    
+    #[test]
+    fn test_notation_roundtrip() {
+        for s in [
+            "",
+            "u8:1 u16be:4660 u32le:7 u64be:0",
+            "\"GET \\x00\\r\\n\" blob16be:\"hello\"",
+            "crc32le{ u8:1 blob8:\"abc\" xor8{ \"\\\\\\\"\" } }",
+        ] {
+            let stream = s.parse::<BinaryStream>().unwrap();
+            let printed = stream.to_string();
+            assert_eq!(printed.parse::<BinaryStream>().unwrap(), stream);
+        }
+    }
+    
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("u8:256".parse::<BinaryStream>(), Err(0));
+        assert_eq!("u16:1".parse::<BinaryStream>(), Err(0));
+        assert_eq!("u8:1 crc16be{ }".parse::<BinaryStream>(), Err(5));
+        assert_eq!("xor8{ u8:1".parse::<BinaryStream>(), Err(10));
+        assert_eq!("u8:1 }".parse::<BinaryStream>(), Err(5));
+        
+        /* The length must fit into the prefix */
+        let long = format!("u8:1 blob8:hex:{}", "00".repeat(256));
+        assert_eq!(long.parse::<BinaryStream>(), Err(5));
+        assert!(format!("blob8:hex:{}", "00".repeat(255)).parse::<BinaryStream>().is_ok());
+    }
+    
+    #[test]
+    fn test_serialize() {
+        /* DNS query header for example.com */
+        let stream = "u16be:0x1234 u16be:0x0100 u16be:1 u16be:0 u16be:0 u16be:0 blob8:\"example\" blob8:\"com\" u8:0 u16be:1 u16be:1".parse::<BinaryStream>().unwrap();
+        assert_eq!(
+            serialize(&stream),
+            b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01",
+        );
+        
+        let stream = "crc32be{ hex:313233343536373839 }".parse::<BinaryStream>().unwrap();
+        assert_eq!(serialize(&stream), b"123456789\xcb\xf4\x39\x26");
+        
+        let stream = "inet16be{ hex:0001f203f4f5f6f7 }".parse::<BinaryStream>().unwrap();
+        assert_eq!(serialize(&stream), b"\x00\x01\xf2\x03\xf4\xf5\xf6\xf7\x22\x0d");
+        
+        let stream = "u32le:0x01020304 u16le:0x0506".parse::<BinaryStream>().unwrap();
+        assert_eq!(serialize(&stream), b"\x04\x03\x02\x01\x06\x05");
+    }
+    
+    #[test]
+    fn test_truncation() {
+        let stream = "blob16be:\"hello\" u32be:1".parse::<BinaryStream>().unwrap();
+        let mut buffer = [0; 4];
+        assert_eq!(stream.serialize_into_buffer(&mut buffer), 4);
+        assert_eq!(&buffer, b"\x00\x05he");
+    }    
+    #[test]
+    fn test_fixups() {
+        let stream = "u8:1 crc32be{ blob8:\"abc\" xor8{ blob16le:\"de\" } }".parse::<BinaryStream>().unwrap();
+        let mut content = vec![0xff; stream.serialized_len()];
+        assert_eq!(stream.serialize_content(&mut content), Some(content.len()));
+        assert_eq!(&content, b"\x01\x00abc\x00\x00de\x00\x00\x00\x00\x00");
+        
+        stream.fixup_content(&mut content);
+        assert_eq!(content, serialize(&stream));
+        
+        /* Content that was cut off keeps the fields that still fit */
+        let mut content = vec![0; 7];
+        stream.serialize_content(&mut content);
+        stream.fixup_content(&mut content);
+        assert_eq!(&content, b"\x01\x03abc\x02\x00");
+    }
+}
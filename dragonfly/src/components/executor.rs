@@ -3,7 +3,10 @@ use libafl_bolts::prelude::{
     shmem::{ShMem, ShMemProvider},
 };
 use libafl::prelude::{
+    AggregatorOps,
     Error,
+    Event,
+    EventFirer,
     Executor,
     ExitKind,
     Forkserver,
@@ -14,6 +17,8 @@ use libafl::prelude::{
     UsesState,
     State,
     HasExecutions,
+    UserStats,
+    UserStatsValue,
 };
 use nix::{
     sys::{
@@ -38,11 +43,31 @@ use std::{
 };
 use std::ffi::CString;
 use crate::components::{
-    DragonflyInput, Packet,
+    DragonflyInput, Packet, DragonflyPersistentExecutor, SerializationResult,
+    snapshot::{SnapshotChannel, SNAPSHOT_FDS_ENV_VAR, hash_prefix},
+    FilesystemSandbox, SANDBOX_ENV_VAR,
+    sanitizer::{StderrChannel, STDERR_FD_ENV_VAR},
 };
 
 pub const PACKET_CHANNEL_SIZE: usize = 16 * 1024 * 1024;
 const PACKET_CHANNEL_ENV_VAR: &str = "__LIBDRAGONFLY_PACKET_CHANNEL";
+const MIN_PACKET_CHANNEL_SIZE: usize = 4096;
+
+/// Name of the monitor statistic that counts the inputs that did not fit into the packet channel
+pub const TRUNCATIONS_STAT: &str = "truncated inputs";
+
+/// Sends the number of truncated inputs to the monitor
+pub(crate) fn report_truncations<S, EM>(state: &mut S, mgr: &mut EM, truncations: usize) -> Result<(), Error>
+where
+    S: State,
+    EM: EventFirer<State = S>,
+{
+    mgr.fire(state, Event::UpdateUserStats {
+        name: TRUNCATIONS_STAT.to_string(),
+        value: UserStats::new(UserStatsValue::Number(truncations as u64), AggregatorOps::Sum),
+        phantom: PhantomData,
+    })
+}
 
 #[derive(Debug)]
 pub struct DragonflyForkserverExecutor<OT, S, SP, P>
@@ -57,6 +82,10 @@ where
     timeout: TimeSpec,
     signal: Signal,
     forkserver: Forkserver,
+    snapshots: Option<(usize, SnapshotChannel)>,
+    sandbox: Option<FilesystemSandbox>,
+    stderr: Option<StderrChannel>,
+    truncations: usize,
     phantom: PhantomData<S>,
 }
 
@@ -67,13 +96,18 @@ where
     SP: ShMemProvider,
     P: Packet,
 {
-    fn new(observers: OT, packet_channel: SP::ShMem, timeout: TimeSpec, signal: Signal, forkserver: Forkserver) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    fn new(observers: OT, packet_channel: SP::ShMem, timeout: TimeSpec, signal: Signal, forkserver: Forkserver, snapshots: Option<(usize, SnapshotChannel)>, sandbox: Option<FilesystemSandbox>, stderr: Option<StderrChannel>) -> Self {
         Self {
             observers,
             packet_channel,
             timeout,
             signal,
             forkserver,
+            snapshots,
+            sandbox,
+            stderr,
+            truncations: 0,
             phantom: PhantomData,
         }
     }
@@ -81,6 +115,132 @@ where
     pub fn builder() -> DragonflyForkserverExecutorBuilder<'a, OT, S, SP, P> {
         DragonflyForkserverExecutorBuilder::new()
     }
+    
+    pub(crate) fn packet_channel_mut(&mut self) -> &mut [u8] {
+        self.packet_channel.as_mut_slice()
+    }
+    
+    pub(crate) fn forkserver(&self) -> &Forkserver {
+        &self.forkserver
+    }
+    
+    pub(crate) fn forkserver_mut(&mut self) -> &mut Forkserver {
+        &mut self.forkserver
+    }
+    
+    /// Executes a single input outside of a fuzzing loop, e.g. to check whether a crash reproduces
+    pub fn run_input(&mut self, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
+        self.clear_stderr();
+        let exit_kind = self.execute(input)?;
+        self.observe_stderr();
+        self.update_sandbox(&exit_kind)?;
+        Ok(exit_kind)
+    }
+    
+    pub(crate) fn clear_stderr(&mut self) {
+        if let Some(stderr) = &mut self.stderr {
+            stderr.clear();
+        }
+    }
+    
+    /// Hands the output of the target to the observers that want it
+    pub(crate) fn observe_stderr(&mut self) {
+        if let Some(stderr) = &mut self.stderr {
+            if self.observers.observes_stderr() {
+                let output = stderr.read();
+                self.observers.observe_stderr(&output);
+            }
+        }
+    }
+    
+    /// The signal that killed the target in the last execution that did not resume from a snapshot
+    pub fn last_signal(&self) -> Option<Signal> {
+        let status = self.forkserver.status();
+        
+        if libc::WIFSIGNALED(status) {
+            Signal::try_from(libc::WTERMSIG(status)).ok()
+        } else {
+            None
+        }
+    }
+    
+    /// The number of inputs that were cut off because they did not fit into the packet channel
+    pub fn truncations(&self) -> usize {
+        self.truncations
+    }
+    
+    pub(crate) fn record_serialization(&mut self, result: &SerializationResult) {
+        if result.is_truncated() {
+            self.truncations += 1;
+        }
+    }
+    
+    /// The private directory of the target, if any
+    pub fn sandbox(&self) -> Option<&FilesystemSandbox> {
+        self.sandbox.as_ref()
+    }
+    
+    /// Applies the reset policies of the sandbox after an execution. Returns whether the sandbox has been reset.
+    pub(crate) fn update_sandbox(&mut self, exit_kind: &ExitKind) -> Result<bool, Error> {
+        let Some(sandbox) = &mut self.sandbox else {
+            return Ok(false);
+        };
+        
+        let reset = sandbox.after_execution(exit_kind)?;
+        
+        /* Snapshots may depend on files that no longer exist */
+        if reset {
+            if let Some((_, snapshots)) = &mut self.snapshots {
+                snapshots.invalidate();
+            }
+        }
+        
+        Ok(reset)
+    }
+    
+    pub(crate) fn run_forkserver(&mut self) -> Result<ExitKind, Error> {
+        let mut exit_kind = ExitKind::Ok;
+        let last_run_timed_out = self.forkserver.last_run_timed_out_raw();
+        let send_len = self.forkserver.write_ctl(last_run_timed_out)?;
+        self.forkserver.set_last_run_timed_out(false);
+
+        if send_len != 4 {
+            return Err(Error::unknown("Unable to request new process from fork server (OOM?)"));
+        }
+
+        let (recv_pid_len, pid) = self.forkserver.read_st()?;
+
+        if recv_pid_len != 4 {
+            return Err(Error::unknown("Unable to request new process from fork server (OOM?)"));
+        }
+        if pid <= 0 {
+            return Err(Error::unknown("Fork server is misbehaving (OOM?)"));
+        }
+
+        self.forkserver.set_child_pid(Pid::from_raw(pid));
+
+        if let Some(status) = self.forkserver.read_st_timed(&self.timeout)? {
+            self.forkserver.set_status(status);
+
+            if libc::WIFSIGNALED(self.forkserver.status()) {
+                exit_kind = ExitKind::Crash;
+            }
+        } else {
+            self.forkserver.set_last_run_timed_out(true);
+            let _ = kill(self.forkserver.child_pid(), self.signal);
+            let (recv_status_len, _) = self.forkserver.read_st()?;
+            if recv_status_len != 4 {
+                return Err(Error::unknown("Could not kill timed-out child"));
+            }
+            exit_kind = ExitKind::Timeout;
+        }
+        
+        if !libc::WIFSTOPPED(self.forkserver.status()) {
+            self.forkserver.reset_child_pid();
+        }
+
+        Ok(exit_kind)
+    }
 }
 
 impl<OT, S, SP, P> UsesState for DragonflyForkserverExecutor<OT, S, SP, P>
@@ -125,58 +285,62 @@ where
     S:  State + UsesInput<Input = DragonflyInput<P>> + HasExecutions,
     SP: ShMemProvider,
     P: Packet,
-    EM: UsesState<State = S>,
+    EM: EventFirer<State = S>,
     Z: UsesState<State = S>,
 {
-    fn run_target(&mut self, _fuzzer: &mut Z, state: &mut S, _mgr: &mut EM, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
+    fn run_target(&mut self, _fuzzer: &mut Z, state: &mut S, mgr: &mut EM, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
         *state.executions_mut() += 1;
         
-        /* Serialize input into packet channel */
-        let buffer = self.packet_channel.as_mut_slice();
-        input.serialize_dragonfly_format(buffer);
-
-        /* Launch the client */
-        let mut exit_kind = ExitKind::Ok;
-        let last_run_timed_out = self.forkserver.last_run_timed_out_raw();
-        let send_len = self.forkserver.write_ctl(last_run_timed_out)?;
-        self.forkserver.set_last_run_timed_out(false);
-
-        if send_len != 4 {
-            return Err(Error::unknown("Unable to request new process from fork server (OOM?)"));
-        }
-
-        let (recv_pid_len, pid) = self.forkserver.read_st()?;
-
-        if recv_pid_len != 4 {
-            return Err(Error::unknown("Unable to request new process from fork server (OOM?)"));
-        }
-        if pid <= 0 {
-            return Err(Error::unknown("Fork server is misbehaving (OOM?)"));
+        let truncations = self.truncations;
+        let exit_kind = self.run_input(input)?;
+        
+        if self.truncations != truncations {
+            report_truncations(state, mgr, self.truncations)?;
         }
+        
+        Ok(exit_kind)
+    }
+}
 
-        self.forkserver.set_child_pid(Pid::from_raw(pid));
-
-        if let Some(status) = self.forkserver.read_st_timed(&self.timeout)? {
-            self.forkserver.set_status(status);
-
-            if libc::WIFSIGNALED(self.forkserver.status()) {
-                exit_kind = ExitKind::Crash;
+impl<OT, S, SP, P> DragonflyForkserverExecutor<OT, S, SP, P>
+where
+    OT: ObserversTuple<S>,
+    S:  State + UsesInput<Input = DragonflyInput<P>>,
+    SP: ShMemProvider,
+    P: Packet,
+{
+    fn execute(&mut self, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
+        /* Serialize input into packet channel */
+        let buffer = self.packet_channel.as_mut_slice();
+        
+        if let Some(snapshot_group) = self.snapshots.as_ref().map(|(groups, _)| *groups) {
+            let result = input.serialize_dragonfly_format_with_snapshot(buffer, snapshot_group);
+            self.record_serialization(&result);
+            
+            if let Some(prefix) = result.snapshot() {
+                let hash = hash_prefix(&self.packet_channel.as_mut_slice()[..prefix]);
+                let (_, snapshots) = self.snapshots.as_mut().unwrap();
+                
+                /* Resume from the snapshot if the prefix did not change */
+                if let Some(exit_kind) = snapshots.run(hash, &self.timeout, self.signal) {
+                    return Ok(exit_kind);
+                }
+                
+                /* Otherwise the target creates a new snapshot during a regular run */
+                let exit_kind = self.run_forkserver()?;
+                
+                if let Some((_, snapshots)) = &mut self.snapshots {
+                    snapshots.collect(hash);
+                }
+                
+                return Ok(exit_kind);
             }
         } else {
-            self.forkserver.set_last_run_timed_out(true);
-            let _ = kill(self.forkserver.child_pid(), self.signal);
-            let (recv_status_len, _) = self.forkserver.read_st()?;
-            if recv_status_len != 4 {
-                return Err(Error::unknown("Could not kill timed-out child"));
-            }
-            exit_kind = ExitKind::Timeout;
+            let result = input.serialize_dragonfly_format(buffer);
+            self.record_serialization(&result);
         }
         
-        if !libc::WIFSTOPPED(self.forkserver.status()) {
-            self.forkserver.reset_child_pid();
-        }
-
-        Ok(exit_kind)
+        self.run_forkserver()
     }
 }
 
@@ -248,6 +412,10 @@ where
     envs: Vec<(OsString, OsString)>,
     is_deferred: bool,
     debug_child: bool,
+    snapshot_group: Option<usize>,
+    sandbox: Option<FilesystemSandbox>,
+    capture_stderr: bool,
+    packet_channel_size: usize,
     phantom: PhantomData<(S, P)>,
 }
 
@@ -270,6 +438,10 @@ where
             envs: Vec::new(),
             is_deferred: false,
             debug_child: false,
+            snapshot_group: None,
+            sandbox: None,
+            capture_stderr: false,
+            packet_channel_size: PACKET_CHANNEL_SIZE,
             phantom: PhantomData,
         }
     }
@@ -346,7 +518,53 @@ where
         self
     }
 
+    /// Let the target take a snapshot after the first `groups` packet groups of an input.
+    /// Subsequent inputs with the same prefix only execute the remaining packets, starting from the snapshot.
+    /// Note that coverage of the prefix is not reported again when executing from a snapshot.
+    pub fn snapshot_group(mut self, groups: usize) -> Self {
+        self.snapshot_group = Some(groups);
+        self
+    }
+
+    /// Give the target its own copy of a directory. The path of the copy is exported in the
+    /// environment variable [`SANDBOX_ENV_VAR`] and the copy is restored according to the reset policies of the sandbox.
+    pub fn sandbox(mut self, sandbox: FilesystemSandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// Capture the stderr of the target and pass it to the observers, e.g. a [`SanitizerObserver`](crate::components::SanitizerObserver).
+    /// This overrides `debug_child` for stderr.
+    pub fn capture_stderr(mut self, capture_stderr: bool) -> Self {
+        self.capture_stderr = capture_stderr;
+        self
+    }
+
+    /// Size of the shared memory the inputs are serialized into. Defaults to [`PACKET_CHANNEL_SIZE`].
+    pub fn packet_channel_size(mut self, size: usize) -> Self {
+        self.packet_channel_size = size;
+        self
+    }
+
     pub fn build(self) -> Result<DragonflyForkserverExecutor<OT, S, SP, P>, Error> {
+        self.build_forkserver(false)
+    }
+    
+    /// Build a [`DragonflyPersistentExecutor`] that executes up to `iterations` inputs in the same process
+    pub fn build_persistent(self, iterations: usize) -> Result<DragonflyPersistentExecutor<OT, S, SP, P>, Error> {
+        if iterations == 0 {
+            return Err(Error::illegal_argument("DragonflyExecutorBuilder: iterations must be at least 1"));
+        }
+        
+        if self.snapshot_group.is_some() {
+            return Err(Error::illegal_argument("DragonflyExecutorBuilder: snapshots are not supported in persistent mode"));
+        }
+        
+        let executor = self.build_forkserver(true)?;
+        Ok(DragonflyPersistentExecutor::new(executor, iterations))
+    }
+    
+    fn build_forkserver(mut self, is_persistent: bool) -> Result<DragonflyForkserverExecutor<OT, S, SP, P>, Error> {
         macro_rules! get_value {
             ($name:ident) => {
                 self.$name.ok_or(Error::illegal_argument(format!("DragonflyExecutorBuilder: {} was not set", stringify!($name))))?
@@ -358,15 +576,45 @@ where
         let timeout = get_value!(timeout);
         let program = get_value!(program);
 
-        let packet_channel = shmem_provider.new_shmem(PACKET_CHANNEL_SIZE)?;
+        if self.packet_channel_size < MIN_PACKET_CHANNEL_SIZE {
+            return Err(Error::illegal_argument(format!("DragonflyExecutorBuilder: packet_channel_size must be at least {}", MIN_PACKET_CHANNEL_SIZE)));
+        }
+
+        let packet_channel = shmem_provider.new_shmem(self.packet_channel_size)?;
         packet_channel.write_to_env(PACKET_CHANNEL_ENV_VAR)?;
 
         let timeout = TimeSpec::milliseconds(timeout.as_millis() as i64);
+        
+        let mut snapshots = match self.snapshot_group {
+            Some(0) => return Err(Error::illegal_argument("DragonflyExecutorBuilder: snapshot_group must be at least 1")),
+            Some(groups) => {
+                let channel = SnapshotChannel::new()?;
+                self.envs.push((SNAPSHOT_FDS_ENV_VAR.into(), channel.env_value().into()));
+                Some((groups, channel))
+            },
+            None => None,
+        };
+        
+        if let Some(sandbox) = &self.sandbox {
+            self.envs.push((SANDBOX_ENV_VAR.into(), sandbox.path().into()));
+        }
+        
+        let stderr = if self.capture_stderr {
+            let channel = StderrChannel::new()?;
+            self.envs.push((STDERR_FD_ENV_VAR.into(), channel.env_value().into()));
+            Some(channel)
+        } else {
+            None
+        };
 
-        let mut forkserver = Forkserver::new(program, self.arguments, self.envs, -1, false, 0, false, self.is_deferred, self.debug_child)?;
+        let mut forkserver = Forkserver::new(program, self.arguments, self.envs, -1, false, 0, is_persistent, self.is_deferred, self.debug_child)?;
         do_forkserver_handshake(&mut forkserver)?;
+        
+        if let Some((_, channel)) = &mut snapshots {
+            channel.close_child_ends();
+        }
 
-        Ok(DragonflyForkserverExecutor::new(observers, packet_channel, timeout, self.signal, forkserver))
+        Ok(DragonflyForkserverExecutor::new(observers, packet_channel, timeout, self.signal, forkserver, snapshots, self.sandbox, stderr))
     }
 }
 
@@ -427,6 +675,23 @@ where
         let env_str = CString::new(env_str).unwrap();
         self.envs.push(env_str);
     }
+    
+    /// Replaces the current process with the target. Only returns if `execve` fails.
+    pub fn run_input(&mut self, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
+        self.env(PACKET_CHANNEL_ENV_VAR, self.packet_channel.id().as_str());
+        
+        let buffer = self.packet_channel.as_mut_slice();
+        input.serialize_dragonfly_format(buffer);
+        
+        let program = self.args[0].clone();
+        let err = execve(
+            &program,
+            &self.args,
+            &self.envs,
+        ).unwrap_err();
+        
+        Err(Error::unknown(format!("execve failed: {}", err)))
+    }
 }
 
 impl<S, SP, P> UsesState for DragonflyDebugExecutor<S, SP, P>
@@ -471,18 +736,6 @@ where
     Z: UsesState<State = S>,
 {
     fn run_target(&mut self, _fuzzer: &mut Z, _state: &mut S, _mgr: &mut EM, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
-        self.env(PACKET_CHANNEL_ENV_VAR, self.packet_channel.id().as_str());
-        
-        let buffer = self.packet_channel.as_mut_slice();
-        input.serialize_dragonfly_format(buffer);
-        
-        let program = self.args[0].clone();
-        execve(
-            &program,
-            &self.args,
-            &self.envs,
-        ).unwrap();
-
-        unreachable!()
+        self.run_input(input)
     }
 }
@@ -0,0 +1,183 @@
+//! Helpers for [`Packet::fixup_content`](crate::components::Packet::fixup_content) that recompute
+//! length fields and checksums in the serialized content of a packet.
+//!
+//! All helpers tolerate content that was cut off at the end of the packet channel:
+//! fields outside of the content are left alone and ranges are clamped to the content.
+
+use std::ops::Range;
+
+/// Byte order of a [`Field`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Big,
+    Little,
+}
+
+/// An unsigned integer of 1 to 8 bytes at a fixed offset in the content of a packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+    offset: usize,
+    width: usize,
+    endianness: Endianness,
+}
+
+impl Field {
+    pub fn new(offset: usize, width: usize, endianness: Endianness) -> Self {
+        assert!((1..=8).contains(&width), "Field width must be between 1 and 8 bytes");
+        
+        Self {
+            offset,
+            width,
+            endianness,
+        }
+    }
+    
+    /// A big endian (network byte order) field
+    pub fn be(offset: usize, width: usize) -> Self {
+        Self::new(offset, width, Endianness::Big)
+    }
+    
+    /// A little endian field
+    pub fn le(offset: usize, width: usize) -> Self {
+        Self::new(offset, width, Endianness::Little)
+    }
+    
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.width
+    }
+    
+    pub fn read(&self, content: &[u8]) -> Option<u64> {
+        let bytes = content.get(self.range())?;
+        let mut value = 0;
+        
+        for i in 0..self.width {
+            let byte = match self.endianness {
+                Endianness::Big => bytes[i],
+                Endianness::Little => bytes[self.width - 1 - i],
+            };
+            value = (value << 8) | byte as u64;
+        }
+        
+        Some(value)
+    }
+    
+    /// Writes the lower `width` bytes of `value`. Returns false if the field is not inside the content.
+    pub fn write(&self, content: &mut [u8], value: u64) -> bool {
+        let Some(bytes) = content.get_mut(self.range()) else {
+            return false;
+        };
+        
+        for i in 0..self.width {
+            let byte = (value >> (8 * i)) as u8;
+            
+            match self.endianness {
+                Endianness::Big => bytes[self.width - 1 - i] = byte,
+                Endianness::Little => bytes[i] = byte,
+            }
+        }
+        
+        true
+    }
+}
+
+fn clamp(range: Range<usize>, len: usize) -> Range<usize> {
+    let end = std::cmp::min(range.end, len);
+    std::cmp::min(range.start, end)..end
+}
+
+/// CRC-32 as used by Ethernet, zlib and PNG
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    
+    for byte in data {
+        crc ^= *byte as u32;
+        
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    
+    !crc
+}
+
+/// The ones' complement checksum of IP, TCP, UDP and ICMP (RFC 1071)
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+    
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    
+    !(sum as u16)
+}
+
+/// Sets `field` to the number of bytes from `start` to the end of the content plus `adjust`,
+/// e.g. `adjust` = 2 for a length that counts its own 2 bytes when `start` is right after the field
+pub fn fixup_length(content: &mut [u8], field: Field, start: usize, adjust: i64) -> bool {
+    let len = content.len().saturating_sub(start) as i64 + adjust;
+    field.write(content, std::cmp::max(len, 0) as u64)
+}
+
+/// Sets `field` to the [`crc32`] of the bytes in `range`
+pub fn fixup_crc32(content: &mut [u8], field: Field, range: Range<usize>) -> bool {
+    let crc = crc32(&content[clamp(range, content.len())]);
+    field.write(content, crc as u64)
+}
+
+/// Sets `field` to the [`internet_checksum`] of the bytes in `range`.
+/// The field may lie inside the range, in which case it counts as zero.
+pub fn fixup_internet_checksum(content: &mut [u8], field: Field, range: Range<usize>) -> bool {
+    if !field.write(content, 0) {
+        return false;
+    }
+    
+    let checksum = internet_checksum(&content[clamp(range, content.len())]);
+    field.write(content, checksum as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn fields() {
+        let mut content = [0u8; 6];
+        assert!(Field::be(1, 2).write(&mut content, 0x1234));
+        assert!(Field::le(3, 3).write(&mut content, 0xabcdef));
+        assert_eq!(content, [0x00, 0x12, 0x34, 0xef, 0xcd, 0xab]);
+        assert_eq!(Field::be(1, 2).read(&content), Some(0x1234));
+        assert_eq!(Field::le(3, 3).read(&content), Some(0xabcdef));
+        assert!(!Field::be(5, 2).write(&mut content, 1));
+        assert_eq!(Field::be(5, 2).read(&content), None);
+    }
+    
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(internet_checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]), !0xddf2);
+        
+        /* A header with a length field, a checksum over everything and a payload */
+        let mut content = b"\x00\x00\xff\xffhello".to_vec();
+        assert!(fixup_length(&mut content, Field::be(0, 2), 4, 0));
+        assert!(fixup_internet_checksum(&mut content, Field::be(2, 2), 0..usize::MAX));
+        assert_eq!(&content[..2], &[0, 5]);
+        assert_eq!(internet_checksum(&content), 0);
+        
+        let mut content = b"data\x00\x00\x00\x00".to_vec();
+        assert!(fixup_crc32(&mut content, Field::le(4, 4), 0..4));
+        assert_eq!(Field::le(4, 4).read(&content), Some(crc32(b"data") as u64));
+    }
+}
@@ -5,9 +5,17 @@ use serde::{
     Deserialize,
     Serialize,
 };
-use std::hash::{
-    Hash,
-    Hasher,
+use std::{
+    hash::{
+        Hash,
+        Hasher,
+    },
+    ops::Range,
+};
+use crate::components::template::{
+    serialize_template,
+    Capture,
+    Reference,
 };
 
 pub trait Packet {
@@ -20,6 +28,26 @@ pub trait Packet {
     fn terminates_group(&self) -> bool {
         true
     }
+    
+    /// Recomputes fields of the serialized content that depend on other parts of it,
+    /// like length prefixes or checksums, right before the content goes into the packet channel.
+    /// `content` is what [`Packet::serialize_content`] wrote and may be cut off if the channel was full.
+    /// See the [`fixup`](crate::components::fixup) module for helpers.
+    fn fixup_content(&self, _content: &mut [u8]) {}
+    
+    /// Values that libdragonfly extracts from the responses of the target on [`Packet::connection`],
+    /// like the port of a PASV reply or a session token, to fill into the [references](Packet::references)
+    /// of later packets. The captures of all packets of an input are active from the start of the input.
+    fn captures(&self) -> Vec<Capture> {
+        Vec::new()
+    }
+    
+    /// Placeholders in the serialized content that get replaced with captured values when the target reads the packet.
+    /// Their offsets refer to the content as [`Packet::serialize_content`] writes it for the current state of the packet.
+    /// Substitution happens after [`Packet::fixup_content`], so fixups that cover a placeholder see the placeholder.
+    fn references(&self) -> Vec<Reference> {
+        Vec::new()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -29,6 +57,9 @@ where
     P: Packet,
 {
     packets: Vec<P>,
+    /// Whether the packets are sent without their [fixups](Packet::fixup_content)
+    #[serde(default)]
+    skip_fixups: bool,
 }
 
 impl<P> DragonflyInput<P>
@@ -38,6 +69,7 @@ where
     pub fn new(packets: Vec<P>) -> Self {
         Self {
             packets,
+            skip_fixups: false,
         }
     }
     
@@ -48,6 +80,54 @@ where
     pub fn packets_mut(&mut self) -> &mut Vec<P> {
         &mut self.packets
     }
+    
+    pub fn skip_fixups(&self) -> bool {
+        self.skip_fixups
+    }
+    
+    /// Sends the packets without their [fixups](Packet::fixup_content) such that the target sees wrong lengths and checksums.
+    /// The choice is part of the input, so it is kept in the corpus and by everything that replays the input.
+    /// [`FixupSkipMutator`](crate::components::FixupSkipMutator) sets it anew for every mutant.
+    pub fn set_skip_fixups(&mut self, skip_fixups: bool) {
+        self.skip_fixups = skip_fixups;
+    }
+    
+    /// Returns the indices of all packets that end up as data packets in the packet channel, in order.
+    pub fn data_packets(&self) -> Vec<usize> {
+        let mut ret = Vec::new();
+        
+        for (i, packet) in self.packets.iter().enumerate() {
+            if packet.serialize_content(&mut []).is_some() {
+                ret.push(i);
+            }
+        }
+        
+        ret
+    }
+    
+    /// Returns the ranges of all groups, where a group ends with a packet that [terminates](Packet::terminates_group) it.
+    /// Packets after the last terminating packet form a group of their own.
+    pub fn groups(&self) -> Vec<Range<usize>> {
+        packet_groups(&self.packets)
+    }
+}
+
+pub(crate) fn packet_groups<P: Packet>(packets: &[P]) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    
+    for (i, packet) in packets.iter().enumerate() {
+        if packet.terminates_group() {
+            groups.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    
+    if start < packets.len() {
+        groups.push(start..packets.len());
+    }
+    
+    groups
 }
 
 impl<P> Input for DragonflyInput<P>
@@ -62,6 +142,10 @@ where
             packet.hash(&mut hasher);
         }
 
+        if self.skip_fixups {
+            hasher.write_u8(1);
+        }
+
         let digest = hasher.finish();
         format!("dragonfly-{:016x}", digest)
     }
@@ -83,6 +167,10 @@ enum PacketType {
     Data = 1,
     Sep = 2,
     Eof = 3,
+    Snapshot = 4,
+    Input = 5,
+    Capture = 6,
+    Template = 7,
 }
 
 #[repr(C, align(8))]
@@ -113,6 +201,42 @@ impl PacketHeader {
         }
     }
     
+    #[inline]
+    fn template(conn: u32, size: u64) -> Self {
+        Self {
+            typ: PacketType::Template,
+            conn,
+            size,
+        }
+    }
+    
+    #[inline]
+    fn capture(conn: u32, size: u64) -> Self {
+        Self {
+            typ: PacketType::Capture,
+            conn,
+            size,
+        }
+    }
+    
+    #[inline]
+    fn snapshot() -> Self {
+        Self {
+            typ: PacketType::Snapshot,
+            conn: 0,
+            size: 0,
+        }
+    }
+    
+    #[inline]
+    fn input(generation: u64) -> Self {
+        Self {
+            typ: PacketType::Input,
+            conn: 0,
+            size: generation,
+        }
+    }
+    
     #[inline]
     fn eof() -> Self {
         Self {
@@ -123,7 +247,7 @@ impl PacketHeader {
     }
 }
 
-fn align8(x: usize) -> usize {
+pub(crate) fn align8(x: usize) -> usize {
     let rem = x % 8;
 
     if rem == 0 {
@@ -133,40 +257,167 @@ fn align8(x: usize) -> usize {
     }
 }
 
+/// Describes what ended up in the packet channel after serializing an input
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SerializationResult {
+    len: usize,
+    packets: usize,
+    truncated: bool,
+    snapshot: Option<usize>,
+}
+
+impl SerializationResult {
+    /// Number of bytes written into the packet channel
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    
+    /// Number of packets of the input that made it into the packet channel completely
+    pub fn packets(&self) -> usize {
+        self.packets
+    }
+    
+    /// Whether the packet channel was too small such that packets were cut off or dropped
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+    
+    /// Length of the prefix before the snapshot marker, if one was placed
+    pub fn snapshot(&self) -> Option<usize> {
+        self.snapshot
+    }
+}
+
 impl<P> DragonflyInput<P>
 where
     P: Packet,
 {
-    pub fn serialize_dragonfly_format(&self, buffer: &mut [u8]) -> usize {
+    pub fn serialize_dragonfly_format(&self, buffer: &mut [u8]) -> SerializationResult {
+        self.serialize(buffer, None, None)
+    }
+    
+    /// Like [`DragonflyInput::serialize_dragonfly_format`] but places a snapshot marker after the first `snapshot_group` packet groups.
+    pub fn serialize_dragonfly_format_with_snapshot(&self, buffer: &mut [u8], snapshot_group: usize) -> SerializationResult {
+        self.serialize(buffer, Some(snapshot_group), None)
+    }
+    
+    /// Like [`DragonflyInput::serialize_dragonfly_format`] but prepends an input header that tells
+    /// a persistent target that this is the input with the given (non-zero) generation.
+    pub fn serialize_dragonfly_format_persistent(&self, buffer: &mut [u8], generation: u64) -> SerializationResult {
+        debug_assert!(generation != 0);
+        self.serialize(buffer, None, Some(generation))
+    }
+    
+    fn serialize(&self, buffer: &mut [u8], snapshot_group: Option<usize>, generation: Option<u64>) -> SerializationResult {
         let end = buffer.len().saturating_sub(PacketHeader::SIZE);
         debug_assert!(end >= PacketHeader::SIZE);
         let mut cursor = 0;
         let mut last_was_sep = true;
+        let mut groups = 0;
+        let mut snapshot = None;
+        let mut packets = 0;
+        let mut truncated = false;
+        
+        /* In persistent mode, start with the input header */
+        if let Some(generation) = generation {
+            unsafe {
+                *std::mem::transmute::<*mut u8, *mut PacketHeader>(buffer.as_mut_ptr()) = PacketHeader::input(generation);
+            }
+            cursor += PacketHeader::SIZE;
+        }
         
         /* First, put a separator */
         unsafe {
-            *std::mem::transmute::<*mut u8, *mut PacketHeader>(buffer.as_mut_ptr()) = PacketHeader::separator();
+            *std::mem::transmute::<*mut u8, *mut PacketHeader>(buffer[cursor..].as_mut_ptr()) = PacketHeader::separator();
         }
         cursor += PacketHeader::SIZE;
         
+        /* Capture rules go right after the first separator, drop duplicates and the ones that don't fit */
+        let mut captures = Vec::new();
+        
+        for packet in self.packets() {
+            for capture in packet.captures() {
+                let capture = (packet.connection(), capture);
+                
+                if !captures.contains(&capture) {
+                    captures.push(capture);
+                }
+            }
+        }
+        
+        for (connection, capture) in captures {
+            let Some(size) = capture.serialize_into(&mut buffer[cursor + PacketHeader::SIZE..end]) else {
+                truncated = true;
+                continue;
+            };
+            let next = cursor + PacketHeader::SIZE + align8(size);
+            
+            if next >= end {
+                truncated = true;
+                continue;
+            }
+            
+            let header = PacketHeader::capture(connection as u32, size as u64);
+            unsafe {
+                *std::mem::transmute::<*mut u8, *mut PacketHeader>(buffer[cursor..].as_mut_ptr()) = header;
+            }
+            cursor = next;
+        }
+        
         /* Then, serialize all packets */
         for packet in self.packets() {
             debug_assert!(cursor % 8 == 0);
             
             /* If packet contains data, write data */
             if cursor + PacketHeader::SIZE >= end {
+                truncated = true;
                 break;
             }
             
-            if let Some(packet_size) = packet.serialize_content(&mut buffer[cursor + PacketHeader::SIZE..end]) {
-                let header = PacketHeader::data(packet.connection() as u32, packet_size as u64);
+            if let Some(mut packet_size) = packet.serialize_content(&mut buffer[cursor + PacketHeader::SIZE..end]) {
+                let content_end = std::cmp::min(cursor + PacketHeader::SIZE + packet_size, end);
+                
+                if !self.skip_fixups {
+                    packet.fixup_content(&mut buffer[cursor + PacketHeader::SIZE..content_end]);
+                }
+                
+                /* Turn the content into a template if it contains placeholders. If that doesn't fit, send it as is. */
+                let mut header = PacketHeader::data(packet.connection() as u32, packet_size as u64);
+                let references = packet.references();
+                
+                if !references.is_empty() {
+                    let content = buffer[cursor + PacketHeader::SIZE..content_end].to_vec();
+                    
+                    if let Some(size) = serialize_template(&content, &references, &mut buffer[cursor + PacketHeader::SIZE..end]) {
+                        packet_size = size;
+                        header = PacketHeader::template(packet.connection() as u32, packet_size as u64);
+                    }
+                }
+                
                 unsafe {
                     *std::mem::transmute::<*mut u8, *mut PacketHeader>(buffer[cursor..].as_mut_ptr()) = header;
                 }
-                cursor = std::cmp::min(cursor + PacketHeader::SIZE + align8(packet_size), end);
+                
+                /* A packet that fills up the rest of the buffer may have been cut off */
+                let next = cursor + PacketHeader::SIZE + align8(packet_size);
+                
+                if next >= end {
+                    truncated = true;
+                    cursor = end;
+                } else {
+                    cursor = next;
+                }
+                
                 last_was_sep = false;
             }
             
+            if truncated {
+                break;
+            }
+            
+            packets += 1;
+            
             /* If packet terminates a group, place a group separator */
             if packet.terminates_group() && cursor + PacketHeader::SIZE < end && !last_was_sep {
                 unsafe {
@@ -174,6 +425,16 @@ where
                 }
                 cursor += PacketHeader::SIZE;
                 last_was_sep = true;
+                groups += 1;
+                
+                /* If the group is the end of the prefix, place a snapshot marker */
+                if Some(groups) == snapshot_group && cursor + PacketHeader::SIZE < end {
+                    unsafe {
+                        *std::mem::transmute::<*mut u8, *mut PacketHeader>(buffer[cursor..].as_mut_ptr()) = PacketHeader::snapshot();
+                    }
+                    snapshot = Some(cursor);
+                    cursor += PacketHeader::SIZE;
+                }
             }
         }
         
@@ -183,6 +444,135 @@ where
             *std::mem::transmute::<*mut u8, *mut PacketHeader>(buffer[cursor..].as_mut_ptr()) = PacketHeader::eof();
         }
         
-        cursor + PacketHeader::SIZE
+        SerializationResult {
+            len: cursor + PacketHeader::SIZE,
+            packets,
+            truncated,
+            snapshot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::RawPacket;
+    
+    struct TestPacket(usize);
+    
+    impl Packet for TestPacket {
+        fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
+            Some(std::cmp::min(self.0, buffer.len()))
+        }
+    }
+    
+    #[test]
+    fn groups() {
+        let packets = [
+            RawPacket::new(0, b"x".to_vec(), false),
+            RawPacket::new(0, b"y".to_vec(), true),
+            RawPacket::new(0, b"z".to_vec(), true),
+            RawPacket::new(0, b"w".to_vec(), false),
+        ];
+        assert_eq!(packet_groups(&packets), vec![0..2, 2..3, 3..4]);
+        assert_eq!(DragonflyInput::new(packets[..2].to_vec()).groups(), vec![0..2]);
+    }
+    
+    #[test]
+    fn truncation() {
+        let mut buffer = vec![0; 256];
+        
+        let input = DragonflyInput::new(vec![TestPacket(16), TestPacket(32)]);
+        let result = input.serialize_dragonfly_format(&mut buffer);
+        assert!(!result.is_truncated());
+        assert_eq!(result.packets(), 2);
+        assert_eq!(result.len(), 6 * PacketHeader::SIZE + 48);
+        
+        let input = DragonflyInput::new(vec![TestPacket(16), TestPacket(1024), TestPacket(16)]);
+        let result = input.serialize_dragonfly_format(&mut buffer);
+        assert!(result.is_truncated());
+        assert_eq!(result.packets(), 1);
+        assert!(result.len() <= buffer.len());
+    }
+    
+    /// A packet whose first byte is the length of the rest
+    #[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+    struct LengthPacket(Vec<u8>);
+    
+    impl Packet for LengthPacket {
+        fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
+            let len = std::cmp::min(self.0.len() + 1, buffer.len());
+            buffer[1..len].copy_from_slice(&self.0[..len - 1]);
+            Some(len)
+        }
+        
+        fn fixup_content(&self, content: &mut [u8]) {
+            crate::components::fixup::fixup_length(content, crate::components::fixup::Field::be(0, 1), 1, 0);
+        }
+    }
+    
+    #[test]
+    fn fixups() {
+        let mut buffer = vec![0xff; 256];
+        let mut input = DragonflyInput::new(vec![LengthPacket(b"hello".to_vec())]);
+        
+        input.serialize_dragonfly_format(&mut buffer);
+        assert_eq!(&buffer[2 * PacketHeader::SIZE..2 * PacketHeader::SIZE + 6], b"\x05hello");
+        
+        buffer.fill(0xff);
+        input.set_skip_fixups(true);
+        input.serialize_dragonfly_format(&mut buffer);
+        assert_eq!(&buffer[2 * PacketHeader::SIZE..2 * PacketHeader::SIZE + 6], b"\xffhello");
+        assert_ne!(input.generate_name(0), DragonflyInput::new(vec![LengthPacket(b"hello".to_vec())]).generate_name(0));
+    }
+    
+    /// A packet that captures a port from the responses and refers to it
+    struct PortPacket(&'static [u8]);
+    
+    impl Packet for PortPacket {
+        fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
+            let len = std::cmp::min(self.0.len(), buffer.len());
+            buffer[..len].copy_from_slice(&self.0[..len]);
+            Some(len)
+        }
+        
+        fn captures(&self) -> Vec<Capture> {
+            vec![Capture::regex(0, "port ([0-9]+)", 1).unwrap()]
+        }
+        
+        fn references(&self) -> Vec<Reference> {
+            self.0.windows(2).position(|x| x == b"21").map(|x| Reference::new(0, x, 2)).into_iter().collect()
+        }
+    }
+    
+    fn packet_type(buffer: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes(buffer[offset..offset + 4].try_into().unwrap())
+    }
+    
+    #[test]
+    fn templates() {
+        let mut buffer = vec![0; 256];
+        let input = DragonflyInput::new(vec![PortPacket(b"PORT 21\r\n")]);
+        let result = input.serialize_dragonfly_format(&mut buffer);
+        assert!(!result.is_truncated());
+        assert_eq!(result.packets(), 1);
+        
+        /* Separator, capture, template, separator, eof */
+        let capture_size = align8(32 + "port ([0-9]+)".len() + 1);
+        let template = PacketHeader::SIZE * 2 + capture_size;
+        assert_eq!(packet_type(&buffer, 0), PacketType::Sep as u32);
+        assert_eq!(packet_type(&buffer, PacketHeader::SIZE), PacketType::Capture as u32);
+        assert_eq!(packet_type(&buffer, template), PacketType::Template as u32);
+        assert_eq!(result.len(), template + PacketHeader::SIZE + align8(3 * 16 + 9) + 2 * PacketHeader::SIZE);
+        
+        /* Every packet declares the capture but it is sent once */
+        let input = DragonflyInput::new(vec![PortPacket(b"PORT 21\r\n"), PortPacket(b"PORT 21\r\n")]);
+        input.serialize_dragonfly_format(&mut buffer);
+        assert_eq!(packet_type(&buffer, template), PacketType::Template as u32);
+        
+        /* Without a placeholder in the content the packet stays data */
+        let input = DragonflyInput::new(vec![PortPacket(b"NOOP\r\n")]);
+        input.serialize_dragonfly_format(&mut buffer);
+        assert_eq!(packet_type(&buffer, template), PacketType::Data as u32);
     }
 }
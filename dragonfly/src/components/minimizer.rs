@@ -0,0 +1,196 @@
+use libafl::prelude::Error;
+use crate::{
+    components::{
+        DragonflyInput,
+        Packet,
+    },
+    tokens::HasTokenStream,
+};
+
+/// Shrinks an input while a predicate, usually "the crash still reproduces", holds.
+/// Removes whole packets first, then tokens inside of token streams and finally shortens the data of tokens.
+/// Group separators are ordinary packets so removing them collapses groups.
+#[derive(Debug)]
+pub struct DragonflyMinimizer<P, F>
+where
+    P: Packet + HasTokenStream + Clone,
+    F: FnMut(&DragonflyInput<P>) -> Result<bool, Error>,
+{
+    current: DragonflyInput<P>,
+    reproduces: F,
+    executions: usize,
+}
+
+impl<P, F> DragonflyMinimizer<P, F>
+where
+    P: Packet + HasTokenStream + Clone,
+    F: FnMut(&DragonflyInput<P>) -> Result<bool, Error>,
+{
+    pub fn new(input: DragonflyInput<P>, reproduces: F) -> Self {
+        Self {
+            current: input,
+            reproduces,
+            executions: 0,
+        }
+    }
+    
+    /// How often the predicate has been evaluated
+    pub fn executions(&self) -> usize {
+        self.executions
+    }
+    
+    fn check(&mut self, candidate: DragonflyInput<P>) -> Result<bool, Error> {
+        self.executions += 1;
+        
+        if (self.reproduces)(&candidate)? {
+            self.current = candidate;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+    
+    /// Runs all passes until none of them makes progress.
+    /// Fails if the original input does not satisfy the predicate.
+    pub fn minimize(mut self) -> Result<DragonflyInput<P>, Error> {
+        let original = self.current.clone();
+        
+        if !self.check(original)? {
+            return Err(Error::illegal_argument("DragonflyMinimizer: input does not reproduce"));
+        }
+        
+        loop {
+            let mut progress = self.remove_packets()?;
+            progress |= self.remove_tokens()?;
+            progress |= self.shorten_tokens()?;
+            
+            if !progress {
+                break;
+            }
+        }
+        
+        Ok(self.current)
+    }
+    
+    /// Deletes chunks of packets, starting with large chunks
+    fn remove_packets(&mut self) -> Result<bool, Error> {
+        let mut progress = false;
+        let mut chunk = self.current.packets().len() / 2;
+        
+        while chunk > 0 {
+            let mut i = 0;
+            
+            while i < self.current.packets().len() {
+                let mut candidate = self.current.clone();
+                let end = std::cmp::min(i + chunk, candidate.packets().len());
+                candidate.packets_mut().drain(i..end);
+                
+                if self.check(candidate)? {
+                    progress = true;
+                } else {
+                    i += chunk;
+                }
+            }
+            
+            chunk /= 2;
+        }
+        
+        Ok(progress)
+    }
+    
+    /// Deletes chunks of tokens in every token stream
+    fn remove_tokens(&mut self) -> Result<bool, Error> {
+        let mut progress = false;
+        
+        for packet in 0..self.current.packets().len() {
+            if !self.current.packets()[packet].has_token_stream() {
+                continue;
+            }
+            
+            let mut chunk = self.current.packets()[packet].token_stream().len() / 2;
+            
+            while chunk > 0 {
+                let mut i = 0;
+                
+                while i < self.current.packets()[packet].token_stream().len() {
+                    let mut candidate = self.current.clone();
+                    let tokens = candidate.packets_mut()[packet].token_stream_mut().tokens_mut();
+                    let end = std::cmp::min(i + chunk, tokens.len());
+                    tokens.drain(i..end);
+                    
+                    if self.check(candidate)? {
+                        progress = true;
+                    } else {
+                        i += chunk;
+                    }
+                }
+                
+                chunk /= 2;
+            }
+        }
+        
+        Ok(progress)
+    }
+    
+    /// Cuts off the end of the data of non-constant tokens
+    fn shorten_tokens(&mut self) -> Result<bool, Error> {
+        let mut progress = false;
+        
+        for packet in 0..self.current.packets().len() {
+            if !self.current.packets()[packet].has_token_stream() {
+                continue;
+            }
+            
+            for token in 0..self.current.packets()[packet].token_stream().len() {
+                let mut cut = self.current.packets()[packet].token_stream().tokens()[token].len() / 2;
+                
+                while cut > 0 {
+                    let mut candidate = self.current.clone();
+                    let token = &mut candidate.packets_mut()[packet].token_stream_mut().tokens_mut()[token];
+                    
+                    if token.is_constant() || token.len() <= cut {
+                        break;
+                    }
+                    
+                    let len = token.len() - cut;
+                    token.data_mut().truncate(len);
+                    
+                    if self.check(candidate)? {
+                        progress = true;
+                        cut = len / 2;
+                    } else {
+                        cut /= 2;
+                    }
+                }
+            }
+        }
+        
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::TokenStream;
+    use std::str::FromStr;
+    
+    #[test]
+    fn minimize_tokens() {
+        let input = DragonflyInput::new(vec![
+            TokenStream::from_str("USER anonymous\r\n").unwrap(),
+            TokenStream::from_str("SITE CHMOD 7777777 AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\r\n").unwrap(),
+            TokenStream::from_str("QUIT\r\n").unwrap(),
+        ]);
+        
+        /* "Crashes" whenever there is a token that starts with AAAA */
+        let minimizer = DragonflyMinimizer::new(input, |input: &DragonflyInput<TokenStream>| {
+            Ok(input.packets().iter().any(|x| x.tokens().iter().any(|t| t.data().starts_with(b"AAAA"))))
+        });
+        let minimized = minimizer.minimize().unwrap();
+        
+        assert_eq!(minimized.packets().len(), 1);
+        assert_eq!(minimized.packets()[0].tokens().len(), 1);
+        assert_eq!(minimized.packets()[0].tokens()[0].data(), b"AAAA");
+    }
+}
@@ -1,7 +1,33 @@
 mod input;
 mod mutators;
 mod executor;
+mod persistent;
+mod response;
+mod state;
+mod scheduler;
+mod snapshot;
+mod sandbox;
+mod text;
+mod minimizer;
+mod sanitizer;
+mod replay;
+mod trace;
+mod stats;
+mod template;
+pub mod fixup;
 
 pub use input::*;
 pub use mutators::*;
 pub use executor::*;
+pub use persistent::*;
+pub use response::*;
+pub use state::*;
+pub use scheduler::*;
+pub use sandbox::*;
+pub use text::*;
+pub use minimizer::*;
+pub use sanitizer::*;
+pub use replay::*;
+pub use trace::*;
+pub use stats::*;
+pub use template::*;
@@ -0,0 +1,136 @@
+use libafl_bolts::prelude::{Rand, StdRand};
+use libafl::prelude::{MutationResult, Error, HasRand, HasCorpus, HasMetadata, Corpus, UsesInput};
+use std::hash::Hash;
+use crate::{
+    components::{PacketMutator, Packet, DragonflyInput, MutationRecord, log_mutation, replayed_mutation, random_donor},
+    binary::{HasBinaryStream, mutators::*},
+};
+use serde::{Serialize, Deserialize};
+
+const STACKS: [usize; 4] = [
+    1,
+    2,
+    4,
+    16,
+];
+
+/// Names of the mutations in the order of their numbers, crossovers are twice as likely
+const MUTATIONS: [&str; 13] = [
+    "int_arith",
+    "int_interesting",
+    "int_random",
+    "int_flip",
+    "bytes_flip",
+    "bytes_random_insert",
+    "bytes_delete",
+    "bytes_repeat",
+    "token_delete",
+    "token_copy",
+    "token_swap",
+    "crossover",
+    "crossover",
+];
+
+pub struct BinaryStreamMutator {
+    max_tokens: usize,
+    rand: StdRand,
+}
+
+impl BinaryStreamMutator {
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            rand: StdRand::with_seed(0),
+        }
+    }
+}
+
+impl<P, S> PacketMutator<P, S> for BinaryStreamMutator
+where
+    P: Packet + HasBinaryStream + std::fmt::Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+    S: HasRand + HasCorpus + HasMetadata,
+    S: UsesInput<Input = DragonflyInput<P>>,
+{
+    fn mutate_packet(&mut self, state: &mut S, packet: &mut P) -> Result<MutationResult, Error> {
+        if !packet.has_binary_stream() {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let stream = packet.binary_stream_mut();
+        let mut stack = state.rand_mut().choose(STACKS);
+        let mut chosen = Vec::with_capacity(stack);
+        let mut applied = 0;
+        
+        self.rand.set_seed(state.rand_mut().next());
+        
+        /* When re-deriving an input, take the recorded choices */
+        let replayed = replayed_mutation(state, "BinaryStreamMutator");
+        
+        if let Some(record) = &replayed {
+            stack = record.get("stack").unwrap_or(stack);
+        }
+        
+        for i in 0..stack {
+            let mut mutation = self.rand.below(MUTATIONS.len() as u64) as usize;
+            
+            if let Some(step) = replayed.as_ref().and_then(|x| x.steps().get(i)).and_then(|x| MUTATIONS.iter().position(|y| y == x)) {
+                mutation = step;
+            }
+            
+            chosen.push(MUTATIONS[mutation]);
+            
+            let success = match mutation {
+                0 => mutate_int_arith(&mut self.rand, stream),
+                1 => mutate_int_interesting(&mut self.rand, stream),
+                2 => mutate_int_random(&mut self.rand, stream),
+                3 => mutate_int_flip(&mut self.rand, stream),
+                4 => mutate_bytes_flip(&mut self.rand, stream),
+                5 => mutate_bytes_random_insert(&mut self.rand, stream),
+                6 => mutate_bytes_delete(&mut self.rand, stream),
+                7 => mutate_bytes_repeat::<_, 1024>(&mut self.rand, stream),
+                8 => mutate_token_delete(&mut self.rand, stream),
+                9 => mutate_token_copy(&mut self.rand, stream, self.max_tokens),
+                10 => mutate_token_swap(&mut self.rand, stream),
+                11..=12 => {
+                    let Some(idx) = random_donor(state, self.rand.next())? else {
+                        continue;
+                    };
+                    
+                    let mut other_testcase = state.corpus().get(idx)?.borrow_mut();
+                    let other_testcase = other_testcase.load_input(state.corpus())?;
+                    
+                    if other_testcase.packets().is_empty() {
+                        continue;
+                    }
+                    
+                    let idx = self.rand.below(other_testcase.packets().len() as u64) as usize;
+                    let other_packet = &other_testcase.packets()[idx];
+                    
+                    if !other_packet.has_binary_stream() {
+                        continue;
+                    }
+                    
+                    if self.rand.below(2) == 0 {
+                        mutate_token_crossover_insert(&mut self.rand, stream, other_packet.binary_stream(), self.max_tokens)
+                    } else {
+                        mutate_token_crossover_replace(&mut self.rand, stream, other_packet.binary_stream(), self.max_tokens)
+                    }
+                },
+                _ => unreachable!(),
+            };
+            
+            if success {
+                applied += 1;
+            }
+        }
+        
+        let record = chosen.iter().fold(MutationRecord::new("BinaryStreamMutator"), |record, x| record.step(x));
+        log_mutation(state, record.param("stack", stack).param("applied", applied));
+        
+        if applied > 0 {
+            Ok(MutationResult::Mutated)
+        } else {
+            Ok(MutationResult::Skipped)
+        }
+    }
+}
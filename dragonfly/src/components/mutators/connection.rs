@@ -0,0 +1,298 @@
+use libafl_bolts::prelude::{Named, Rand};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use std::collections::VecDeque;
+use crate::components::{DragonflyInput, Packet, HasGroupBoundary, protected_prefix, MutationRecord, log_mutation};
+
+/// Packets whose connection can be changed by the connection mutators
+pub trait HasConnection: Packet {
+    /// Moves the packet to another connection.
+    /// Returns false if the packet cannot be sent over other connections.
+    fn set_connection(&mut self, connection: usize) -> bool;
+}
+
+/// Indices of the data packets after the protected prefix
+fn mutable_data_packets<P: Packet, S: HasMetadata>(state: &S, input: &DragonflyInput<P>) -> Vec<usize> {
+    let prefix = protected_prefix(state, input.packets().len());
+    input.data_packets().into_iter().filter(|x| *x >= prefix).collect()
+}
+
+/// The connection after the highest one that data packets use. Since libdragonfly assigns
+/// connection numbers in the order of `accept()`s, this is the next one to be opened.
+fn next_connection<P: Packet>(input: &DragonflyInput<P>) -> usize {
+    input.data_packets().into_iter().map(|x| input.packets()[x].connection() + 1).max().unwrap_or(0)
+}
+
+/// Moves a data packet to a different connection that already exists in the input
+pub struct ConnectionReassignMutator;
+
+impl ConnectionReassignMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Named for ConnectionReassignMutator {
+    fn name(&self) -> &str {
+        "ConnectionReassignMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for ConnectionReassignMutator
+where
+    P: HasConnection,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let candidates = mutable_data_packets(state, input);
+        let num_conns = next_connection(input);
+        
+        if candidates.is_empty() || num_conns < 2 {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let idx = candidates[state.rand_mut().below(candidates.len() as u64) as usize];
+        let old = input.packets()[idx].connection();
+        let new = state.rand_mut().below(num_conns as u64 - 1) as usize;
+        let new = if new >= old { new + 1 } else { new };
+        
+        if input.packets_mut()[idx].set_connection(new) {
+            log_mutation(state, MutationRecord::new("ConnectionReassignMutator").param("packet", idx).param("connection", new));
+            Ok(MutationResult::Mutated)
+        } else {
+            Ok(MutationResult::Skipped)
+        }
+    }
+}
+
+/// Shuffles the data packets of two connections within a group such that the target
+/// receives them in a different order while each connection still sees its packets in order.
+/// The group boundaries stay where they are.
+pub struct ConnectionInterleaveMutator;
+
+impl ConnectionInterleaveMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Named for ConnectionInterleaveMutator {
+    fn name(&self) -> &str {
+        "ConnectionInterleaveMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for ConnectionInterleaveMutator
+where
+    P: HasGroupBoundary + Clone,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let prefix = protected_prefix(state, input.packets().len());
+        let data_packets = input.data_packets();
+        
+        /* Groups that contain data packets of at least two connections */
+        let groups: Vec<_> = input.groups().into_iter().filter_map(|group| {
+            let packets: Vec<usize> = data_packets.iter().copied().filter(|x| group.contains(x) && *x >= prefix).collect();
+            let mut conns: Vec<usize> = packets.iter().map(|x| input.packets()[*x].connection()).collect();
+            conns.sort_unstable();
+            conns.dedup();
+            
+            if conns.len() >= 2 {
+                Some((packets, conns))
+            } else {
+                None
+            }
+        }).collect();
+        
+        if groups.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let (packets, conns) = &groups[state.rand_mut().below(groups.len() as u64) as usize];
+        let first = conns[state.rand_mut().below(conns.len() as u64) as usize];
+        let second = conns[state.rand_mut().below(conns.len() as u64) as usize];
+        
+        if first == second {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        /* Positions of the two connections and their packets in order */
+        let slots: Vec<usize> = packets.iter().copied().filter(|x| [first, second].contains(&input.packets()[*x].connection())).collect();
+        let mut queues = [first, second].map(|conn| {
+            slots.iter().filter(|x| input.packets()[**x].connection() == conn).map(|x| input.packets()[*x].clone()).collect::<VecDeque<P>>()
+        });
+        let mut order = Vec::with_capacity(slots.len());
+        
+        for _ in 0..slots.len() {
+            let queue = if queues[0].is_empty() {
+                1
+            } else if queues[1].is_empty() {
+                0
+            } else {
+                state.rand_mut().below(2) as usize
+            };
+            order.push((queue, queues[queue].pop_front().unwrap()));
+        }
+        
+        let old_order: Vec<usize> = slots.iter().map(|x| if input.packets()[*x].connection() == first { 0 } else { 1 }).collect();
+        
+        if old_order.iter().eq(order.iter().map(|(x, _)| x)) {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        /* Every slot keeps its boundary, only the content and connection move */
+        for (slot, (_, packet)) in slots.iter().zip(order.iter_mut()) {
+            let terminates_group = input.packets()[*slot].terminates_group();
+            
+            if packet.terminates_group() != terminates_group && !packet.set_terminates_group(terminates_group) {
+                return Ok(MutationResult::Skipped);
+            }
+        }
+        
+        for (slot, (_, packet)) in slots.into_iter().zip(order) {
+            input.packets_mut()[slot] = packet;
+        }
+        
+        log_mutation(state, MutationRecord::new("ConnectionInterleaveMutator").param("first", first).param("second", second));
+        
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Opens an additional connection by copying a run of data packets of an existing
+/// connection to a new one, up to the number of connections libdragonfly was built with
+/// (its `max_conns` option).
+/// The copies are spread over existing groups, the number of groups stays the same.
+pub struct ConnectionOpenMutator {
+    max_conns: usize,
+    max_length: usize,
+}
+
+impl ConnectionOpenMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new(max_conns: usize, max_length: usize) -> Self {
+        Self {
+            max_conns,
+            max_length,
+        }
+    }
+}
+
+impl Named for ConnectionOpenMutator {
+    fn name(&self) -> &str {
+        "ConnectionOpenMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for ConnectionOpenMutator
+where
+    P: HasConnection + HasGroupBoundary + Clone,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let len = input.packets().len();
+        let new = next_connection(input);
+        let data_packets = input.data_packets();
+        
+        if data_packets.is_empty() || new >= self.max_conns || len >= self.max_length {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        /* Copy up to the remaining space of packets of one connection */
+        let conn = input.packets()[data_packets[state.rand_mut().below(data_packets.len() as u64) as usize]].connection();
+        let source: Vec<usize> = data_packets.into_iter().filter(|x| input.packets()[*x].connection() == conn).collect();
+        let start = state.rand_mut().below(source.len() as u64) as usize;
+        let count = 1 + state.rand_mut().below(std::cmp::min(source.len() - start, self.max_length - len) as u64) as usize;
+        let mut packets = Vec::with_capacity(count);
+        
+        for idx in &source[start..start + count] {
+            let mut packet = input.packets()[*idx].clone();
+            
+            if !packet.set_connection(new) || (packet.terminates_group() && !packet.set_terminates_group(false)) {
+                return Ok(MutationResult::Skipped);
+            }
+            
+            packets.push(packet);
+        }
+        
+        /* Place the new connection somewhere after the protected prefix. Copies are only inserted
+           in front of packets of an existing group such that they do not form a group of their own. */
+        let prefix = protected_prefix(state, len);
+        let mut end = if input.packets()[len - 1].terminates_group() { len - 1 } else { len };
+        
+        if prefix > end {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let mut to = prefix + state.rand_mut().below((end - prefix) as u64 + 1) as usize;
+        log_mutation(state, MutationRecord::new("ConnectionOpenMutator").param("from", conn).param("connection", new).param("count", count).param("to", to));
+        
+        for packet in packets {
+            input.packets_mut().insert(to, packet);
+            end += 1;
+            to += 1 + state.rand_mut().below((end - to) as u64) as usize;
+        }
+        
+        Ok(MutationResult::Mutated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl::prelude::{ConstFeedback, InMemoryCorpus, StdState};
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    use crate::pcap::RawPacket;
+    
+    fn packets_of(input: &DragonflyInput<RawPacket>, conn: usize) -> Vec<u8> {
+        input.packets().iter().filter(|x| x.connection() == conn).map(|x| x.data()[0]).collect()
+    }
+    
+    #[test]
+    fn connection_mutators() {
+        let mut state = StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::<DragonflyInput<RawPacket>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        let original = DragonflyInput::new(vec![
+            RawPacket::new(0, vec![b'a'], false),
+            RawPacket::new(1, vec![b'b'], false),
+            RawPacket::new(0, vec![b'c'], false),
+            RawPacket::new(1, vec![b'd'], true),
+        ]);
+        let mut interleaved = false;
+        
+        for _ in 0..100 {
+            let mut input = original.clone();
+            
+            if ConnectionInterleaveMutator::new().mutate(&mut state, &mut input).unwrap() == MutationResult::Mutated {
+                assert_eq!(packets_of(&input, 0), b"ac");
+                assert_eq!(packets_of(&input, 1), b"bd");
+                assert_eq!(input.groups(), original.groups());
+                assert!(input.packets()[3].terminates_group());
+                interleaved = true;
+            }
+            
+            let mut input = original.clone();
+            assert_eq!(ConnectionReassignMutator::new().mutate(&mut state, &mut input).unwrap(), MutationResult::Mutated);
+            assert_eq!(packets_of(&input, 0).len() + packets_of(&input, 1).len(), 4);
+            assert_ne!(packets_of(&input, 0).len(), 2);
+            
+            let mut input = original.clone();
+            assert_eq!(ConnectionOpenMutator::new(2, 16).mutate(&mut state, &mut input).unwrap(), MutationResult::Skipped);
+            assert_eq!(ConnectionOpenMutator::new(3, 16).mutate(&mut state, &mut input).unwrap(), MutationResult::Mutated);
+            let new = packets_of(&input, 2);
+            assert!(!new.is_empty());
+            assert_eq!(input.groups().len(), original.groups().len());
+            assert!(input.packets().iter().filter(|x| x.connection() == 2).all(|x| !x.terminates_group()));
+            assert!(b"ac".windows(new.len()).chain(b"bd".windows(new.len())).any(|x| x == new.as_slice()));
+        }
+        
+        assert!(interleaved);
+    }
+}
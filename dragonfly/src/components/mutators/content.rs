@@ -1,6 +1,6 @@
 use libafl_bolts::prelude::{Named, Rand};
-use libafl::prelude::{Mutator, MutationResult, Error, HasRand};
-use crate::components::{DragonflyInput, Packet};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use crate::components::{DragonflyInput, Packet, protected_prefix, MutationRecord, logged_mutations, log_mutation_at};
 use std::marker::PhantomData;
 
 pub trait PacketMutator<P, S>
@@ -47,17 +47,25 @@ impl<P, S, M> Mutator<DragonflyInput<P>, S> for PacketContentMutator<P, S, M>
 where
     M: PacketMutator<P, S>,
     P: Packet,
-    S: HasRand,
+    S: HasRand + HasMetadata,
 {
     fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
         let len = input.packets().len();
+        let prefix = protected_prefix(state, len);
         
-        if len == 0 {
+        if len <= prefix {
             return Ok(MutationResult::Skipped);
         }
         
-        let idx = state.rand_mut().below(len as u64) as usize;
+        let idx = prefix + state.rand_mut().below((len - prefix) as u64) as usize;
         let packet = &mut input.packets_mut()[idx];
-        self.mutator.mutate_packet(state, packet)
+        let mark = logged_mutations(state);
+        let result = self.mutator.mutate_packet(state, packet)?;
+        
+        if result == MutationResult::Mutated {
+            log_mutation_at(state, mark, MutationRecord::new("PacketContentMutator").param("packet", idx));
+        }
+        
+        Ok(result)
     }
 }
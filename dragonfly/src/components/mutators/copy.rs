@@ -1,6 +1,6 @@
 use libafl_bolts::prelude::{Named, Rand};
-use libafl::prelude::{Mutator, MutationResult, Error, HasRand};
-use crate::components::{DragonflyInput, Packet};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use crate::components::{DragonflyInput, Packet, protected_prefix, MutationRecord, log_mutation};
 
 pub struct PacketCopyMutator {
     max_length: usize,
@@ -24,7 +24,7 @@ impl Named for PacketCopyMutator {
 impl<P, S> Mutator<DragonflyInput<P>, S> for PacketCopyMutator
 where
     P: Packet + Clone,
-    S: HasRand,
+    S: HasRand + HasMetadata,
 {
     fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
         let len = input.packets().len();
@@ -33,11 +33,13 @@ where
             return Ok(MutationResult::Skipped);
         }
         
-        let to = state.rand_mut().below(len as u64 + 1) as usize;
+        let prefix = protected_prefix(state, len);
+        let to = prefix + state.rand_mut().below((len - prefix) as u64 + 1) as usize;
         let from = state.rand_mut().below(len as u64) as usize;
         
         let packet = input.packets()[from].clone();
         input.packets_mut().insert(to, packet);
+        log_mutation(state, MutationRecord::new("PacketCopyMutator").param("from", from).param("to", to));
         
         Ok(MutationResult::Mutated)
     }
@@ -1,6 +1,6 @@
 use libafl_bolts::prelude::{Named, Rand};
-use libafl::prelude::{Mutator, MutationResult, Error, HasRand};
-use crate::components::{DragonflyInput, Packet};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use crate::components::{DragonflyInput, Packet, protected_prefix, MutationRecord, log_mutation};
 use std::marker::PhantomData;
 
 pub trait PacketCreator<S>
@@ -41,16 +41,18 @@ where
 impl<P, S> Mutator<DragonflyInput<P>, S> for PacketInsertionMutator<P, S>
 where
     P: Packet + PacketCreator<S>,
-    S: HasRand,
+    S: HasRand + HasMetadata,
 {
     fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
         let len = input.packets().len();
-        let idx = state.rand_mut().below(len as u64) as usize;
+        let prefix = protected_prefix(state, len);
+        let idx = prefix + state.rand_mut().below((len - prefix) as u64 + 1) as usize;
         let new_packets = P::create_packets(state);
         
         if new_packets.is_empty() {
             Ok(MutationResult::Skipped)
         } else {
+            log_mutation(state, MutationRecord::new("PacketInsertionMutator").param("to", idx).param("count", new_packets.len()));
             input.packets_mut().splice(idx..idx, new_packets);
             Ok(MutationResult::Mutated)
         }
@@ -0,0 +1,237 @@
+use libafl_bolts::prelude::{Named, Rand};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata, HasCorpus, Corpus, CorpusId, UsesInput};
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    ops::Range,
+};
+use serde::{Serialize, Deserialize};
+use crate::components::{DragonflyInput, Packet, protected_prefix, input::packet_groups, MutationRecord, log_mutation, random_donor};
+
+/// Picks either a contiguous run of packets or a whole group that starts at or after `start`
+/// and is not longer than `max_size`
+fn random_run<P: Packet, R: Rand>(rand: &mut R, packets: &[P], start: usize, whole_groups: bool, max_size: usize) -> Option<Range<usize>> {
+    if whole_groups {
+        let candidates: Vec<_> = packet_groups(packets).into_iter().filter(|x| x.start >= start && x.len() <= max_size).collect();
+        
+        if candidates.is_empty() {
+            None
+        } else {
+            Some(candidates[rand.below(candidates.len() as u64) as usize].clone())
+        }
+    } else {
+        if packets.len() <= start || max_size == 0 {
+            return None;
+        }
+        
+        let idx = start + rand.below((packets.len() - start) as u64) as usize;
+        let len = 1 + rand.below(std::cmp::min(packets.len() - idx, max_size) as u64) as usize;
+        Some(idx..idx + len)
+    }
+}
+
+/// Clones the packets of a random corpus entry other than the current one
+fn random_other_packets<P, S>(state: &mut S) -> Result<Option<(CorpusId, Vec<P>)>, Error>
+where
+    P: Packet + Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+    S: HasRand + HasMetadata + HasCorpus,
+    S: UsesInput<Input = DragonflyInput<P>>,
+{
+    let draw = state.rand_mut().next();
+    
+    let Some(idx) = random_donor(state, draw)? else {
+        return Ok(None);
+    };
+    
+    let mut other_testcase = state.corpus().get(idx)?.borrow_mut();
+    let other_testcase = other_testcase.load_input(state.corpus())?;
+    
+    if other_testcase.packets().is_empty() {
+        return Ok(None);
+    }
+    
+    Ok(Some((idx, other_testcase.packets().to_vec())))
+}
+
+/// Inserts a run of packets or a whole group from another corpus entry into the input
+pub struct PacketCrossoverInsertMutator {
+    max_length: usize,
+}
+
+impl PacketCrossoverInsertMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+        }
+    }
+}
+
+impl Named for PacketCrossoverInsertMutator {
+    fn name(&self) -> &str {
+        "PacketCrossoverInsertMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for PacketCrossoverInsertMutator
+where
+    P: Packet + Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+    S: HasRand + HasMetadata + HasCorpus,
+    S: UsesInput<Input = DragonflyInput<P>>,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let len = input.packets().len();
+        
+        if len >= self.max_length {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let Some((donor, other)) = random_other_packets(state)? else {
+            return Ok(MutationResult::Skipped);
+        };
+        let prefix = protected_prefix(state, len);
+        let whole_groups = state.rand_mut().below(2) == 0;
+        
+        let Some(src) = random_run(state.rand_mut(), &other, 0, whole_groups, self.max_length - len) else {
+            return Ok(MutationResult::Skipped);
+        };
+        
+        let to = if whole_groups {
+            /* Insert only at group boundaries */
+            let mut boundaries: Vec<usize> = input.groups().into_iter().map(|x| x.start).filter(|x| *x >= prefix).collect();
+            boundaries.push(len);
+            boundaries[state.rand_mut().below(boundaries.len() as u64) as usize]
+        } else {
+            prefix + state.rand_mut().below((len - prefix) as u64 + 1) as usize
+        };
+        
+        log_mutation(state, MutationRecord::new("PacketCrossoverInsertMutator").param("donor", donor.into()).param("src_start", src.start).param("src_end", src.end).param("to", to));
+        input.packets_mut().splice(to..to, other[src].iter().cloned());
+        
+        debug_assert!(input.packets().len() <= self.max_length);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Replaces a run of packets or a whole group of the input with one from another corpus entry
+pub struct PacketCrossoverReplaceMutator {
+    max_length: usize,
+}
+
+impl PacketCrossoverReplaceMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+        }
+    }
+}
+
+impl Named for PacketCrossoverReplaceMutator {
+    fn name(&self) -> &str {
+        "PacketCrossoverReplaceMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for PacketCrossoverReplaceMutator
+where
+    P: Packet + Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+    S: HasRand + HasMetadata + HasCorpus,
+    S: UsesInput<Input = DragonflyInput<P>>,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let len = input.packets().len();
+        let prefix = protected_prefix(state, len);
+        
+        if len <= prefix {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let Some((donor, other)) = random_other_packets(state)? else {
+            return Ok(MutationResult::Skipped);
+        };
+        let whole_groups = state.rand_mut().below(2) == 0;
+        
+        let Some(dst) = random_run(state.rand_mut(), input.packets(), prefix, whole_groups, len) else {
+            return Ok(MutationResult::Skipped);
+        };
+        
+        let remaining = len - dst.len();
+        
+        if remaining >= self.max_length {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let Some(src) = random_run(state.rand_mut(), &other, 0, whole_groups, self.max_length - remaining) else {
+            return Ok(MutationResult::Skipped);
+        };
+        
+        log_mutation(state, MutationRecord::new("PacketCrossoverReplaceMutator").param("donor", donor.into()).param("src_start", src.start).param("src_end", src.end).param("dst_start", dst.start).param("dst_end", dst.end));
+        input.packets_mut().splice(dst, other[src].iter().cloned());
+        
+        debug_assert!(input.packets().len() <= self.max_length);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl::prelude::{ConstFeedback, InMemoryCorpus, StdState, Testcase};
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    use crate::{
+        components::ProtectedPrefixMetadata,
+        pcap::RawPacket,
+    };
+    
+    fn packet(data: &[u8], terminates_group: bool) -> RawPacket {
+        RawPacket::new(0, data.to_vec(), terminates_group)
+    }
+    
+    #[test]
+    fn crossover() {
+        let mut state = StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::<DragonflyInput<RawPacket>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        let current = state.corpus_mut().add(Testcase::new(DragonflyInput::new(vec![
+            packet(b"a", true),
+        ]))).unwrap();
+        state.corpus_mut().add(Testcase::new(DragonflyInput::new(vec![
+            packet(b"x", false),
+            packet(b"y", true),
+            packet(b"z", true),
+        ]))).unwrap();
+        *state.corpus_mut().current_mut() = Some(current);
+        state.add_metadata(ProtectedPrefixMetadata::new(1));
+        
+        let mut insert = PacketCrossoverInsertMutator::new(4);
+        let mut replace = PacketCrossoverReplaceMutator::new(4);
+        let mut mutated = 0;
+        
+        for i in 0..1000 {
+            let mut input = DragonflyInput::new(vec![
+                packet(b"a", true),
+                packet(b"b", true),
+            ]);
+            
+            let result = if i % 2 == 0 {
+                insert.mutate(&mut state, &mut input).unwrap()
+            } else {
+                replace.mutate(&mut state, &mut input).unwrap()
+            };
+            
+            if result == MutationResult::Mutated {
+                mutated += 1;
+                let data: Vec<u8> = input.packets().iter().flat_map(|x| x.data().to_vec()).collect();
+                assert!(input.packets().len() <= 4);
+                assert_eq!(data[0], b'a');
+            }
+        }
+        
+        assert!(mutated > 0);
+    }
+}
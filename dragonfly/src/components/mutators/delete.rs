@@ -1,6 +1,6 @@
 use libafl_bolts::prelude::{Named, Rand};
-use libafl::prelude::{Mutator, MutationResult, Error, HasRand};
-use crate::components::{DragonflyInput, Packet};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use crate::components::{DragonflyInput, Packet, protected_prefix, MutationRecord, log_mutation};
 
 pub struct PacketDeleteMutator {
     min_length: usize,
@@ -24,17 +24,19 @@ impl Named for PacketDeleteMutator {
 impl<P, S> Mutator<DragonflyInput<P>, S> for PacketDeleteMutator
 where
     P: Packet,
-    S: HasRand,
+    S: HasRand + HasMetadata,
 {
     fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
         let len = input.packets().len();
+        let prefix = protected_prefix(state, len);
         
-        if len <= self.min_length {
+        if len <= self.min_length || len <= prefix {
             return Ok(MutationResult::Skipped);
         }
         
-        let idx = state.rand_mut().below(len as u64) as usize;
+        let idx = prefix + state.rand_mut().below((len - prefix) as u64) as usize;
         input.packets_mut().remove(idx);
+        log_mutation(state, MutationRecord::new("PacketDeleteMutator").param("packet", idx));
         
         Ok(MutationResult::Mutated)
     }
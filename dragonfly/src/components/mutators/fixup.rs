@@ -0,0 +1,110 @@
+use libafl_bolts::prelude::{Named, Rand};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata, CorpusId};
+use crate::components::{DragonflyInput, Packet, MutationRecord, log_mutation};
+
+/// Wraps a mutator and decides for every input it mutates whether the input is sent without its
+/// [fixups](Packet::fixup_content), such that the target still sees wrong lengths and checksums.
+/// The fixups are skipped with probability `fraction`, independent of the parent,
+/// so a skipping corpus entry does not make all of its descendants skip.
+/// The decision is stored in the input, see [`DragonflyInput::set_skip_fixups`].
+pub struct FixupSkipMutator<M> {
+    mutator: M,
+    fraction: f64,
+}
+
+impl<M> FixupSkipMutator<M> {
+    pub fn new(mutator: M, fraction: f64) -> Self {
+        assert!((0.0..=1.0).contains(&fraction), "FixupSkipMutator: fraction must be between 0 and 1");
+        
+        Self {
+            mutator,
+            fraction,
+        }
+    }
+    
+    pub fn inner(&self) -> &M {
+        &self.mutator
+    }
+}
+
+impl<M> Named for FixupSkipMutator<M> {
+    fn name(&self) -> &str {
+        "FixupSkipMutator"
+    }
+}
+
+impl<M, P, S> Mutator<DragonflyInput<P>, S> for FixupSkipMutator<M>
+where
+    M: Mutator<DragonflyInput<P>, S>,
+    P: Packet,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        if self.mutator.mutate(state, input)? == MutationResult::Skipped {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let skip_fixups = ((state.rand_mut().next() >> 11) as f64 / (1u64 << 53) as f64) < self.fraction;
+        input.set_skip_fixups(skip_fixups);
+        log_mutation(state, MutationRecord::new("FixupSkipMutator").param("skip", skip_fixups as usize));
+        
+        Ok(MutationResult::Mutated)
+    }
+    
+    fn post_exec(&mut self, state: &mut S, new_corpus_idx: Option<CorpusId>) -> Result<(), Error> {
+        self.mutator.post_exec(state, new_corpus_idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::BinaryStream;
+    use libafl::prelude::{StdState, InMemoryCorpus, ConstFeedback};
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    
+    /// Changes nothing but always reports a mutation
+    struct NopMutator;
+    
+    impl Named for NopMutator {
+        fn name(&self) -> &str {
+            "NopMutator"
+        }
+    }
+    
+    impl<I, S> Mutator<I, S> for NopMutator {
+        fn mutate(&mut self, _state: &mut S, _input: &mut I) -> Result<MutationResult, Error> {
+            Ok(MutationResult::Mutated)
+        }
+    }
+    
+    #[test]
+    fn skip_fraction() {
+        let mut state = StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::<DragonflyInput<BinaryStream>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        let mut mutator = FixupSkipMutator::new(NopMutator, 0.25);
+        
+        /* The parent skips its fixups, the children should do so only with the configured probability */
+        let mut parent = DragonflyInput::new(vec![BinaryStream::default()]);
+        parent.set_skip_fixups(true);
+        let mut skipped = 0;
+        
+        for _ in 0..10000 {
+            let mut child = parent.clone();
+            assert_eq!(mutator.mutate(&mut state, &mut child).unwrap(), MutationResult::Mutated);
+            skipped += child.skip_fixups() as usize;
+        }
+        
+        assert!((2200..2800).contains(&skipped), "{}", skipped);
+        
+        let mut mutator = FixupSkipMutator::new(NopMutator, 0.0);
+        let mut child = parent.clone();
+        mutator.mutate(&mut state, &mut child).unwrap();
+        assert!(!child.skip_fixups());
+    }
+}
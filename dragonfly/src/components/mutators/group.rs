@@ -0,0 +1,443 @@
+use libafl_bolts::prelude::{Named, Rand};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use std::ops::Range;
+use crate::components::{DragonflyInput, Packet, protected_prefix, MutationRecord, log_mutation};
+
+/// Packets whose group boundaries can be moved by the [`GroupBoundaryMutator`]
+pub trait HasGroupBoundary: Packet + Sized {
+    /// Changes whether this packet terminates its group.
+    /// Returns false if the packet does not support that, e.g. because it is a dedicated separator.
+    fn set_terminates_group(&mut self, terminates_group: bool) -> bool;
+    
+    /// A packet without content that only terminates a group, if the packet type has one
+    fn group_separator() -> Option<Self> {
+        None
+    }
+}
+
+/// Groups that mutators may modify, i.e. that start after the protected prefix
+fn mutable_groups<P: Packet, S: HasMetadata>(state: &S, input: &DragonflyInput<P>) -> Vec<Range<usize>> {
+    let prefix = protected_prefix(state, input.packets().len());
+    input.groups().into_iter().filter(|x| x.start >= prefix).collect()
+}
+
+/// Whether a group ends with a terminating packet. Moving an unterminated group
+/// in front of another group would merge the two.
+fn is_terminated<P: Packet>(input: &DragonflyInput<P>, group: &Range<usize>) -> bool {
+    input.packets()[group.end - 1].terminates_group()
+}
+
+/// Positions after the protected prefix where a group can be inserted
+fn group_boundaries<P: Packet, S: HasMetadata>(state: &S, input: &DragonflyInput<P>) -> Vec<usize> {
+    let mut boundaries: Vec<usize> = mutable_groups(state, input).into_iter().map(|x| x.start).collect();
+    
+    if input.packets().last().map(|x| x.terminates_group()).unwrap_or(true) {
+        boundaries.push(input.packets().len());
+    }
+    
+    boundaries
+}
+
+/// Deletes a whole group
+pub struct GroupDeleteMutator {
+    min_length: usize,
+}
+
+impl GroupDeleteMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new(min_length: usize) -> Self {
+        Self {
+            min_length,
+        }
+    }
+}
+
+impl Named for GroupDeleteMutator {
+    fn name(&self) -> &str {
+        "GroupDeleteMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for GroupDeleteMutator
+where
+    P: Packet,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let len = input.packets().len();
+        let groups = mutable_groups(state, input);
+        
+        if groups.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let group = groups[state.rand_mut().below(groups.len() as u64) as usize].clone();
+        
+        if len - group.len() < self.min_length {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        log_mutation(state, MutationRecord::new("GroupDeleteMutator").param("start", group.start).param("end", group.end));
+        input.packets_mut().drain(group);
+        
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Copies a whole group to a group boundary
+pub struct GroupCopyMutator {
+    max_length: usize,
+}
+
+impl GroupCopyMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+        }
+    }
+}
+
+impl Named for GroupCopyMutator {
+    fn name(&self) -> &str {
+        "GroupCopyMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for GroupCopyMutator
+where
+    P: Packet + Clone,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let len = input.packets().len();
+        let groups: Vec<_> = input.groups().into_iter().filter(|x| is_terminated(input, x)).collect();
+        let boundaries = group_boundaries(state, input);
+        
+        if groups.is_empty() || boundaries.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let group = groups[state.rand_mut().below(groups.len() as u64) as usize].clone();
+        let to = boundaries[state.rand_mut().below(boundaries.len() as u64) as usize];
+        
+        if len + group.len() > self.max_length {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        log_mutation(state, MutationRecord::new("GroupCopyMutator").param("start", group.start).param("end", group.end).param("to", to));
+        let packets = input.packets()[group].to_vec();
+        input.packets_mut().splice(to..to, packets);
+        
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Repeats a whole group a random number of times
+pub struct GroupRepeatMutator {
+    max_length: usize,
+}
+
+impl GroupRepeatMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+        }
+    }
+}
+
+impl Named for GroupRepeatMutator {
+    fn name(&self) -> &str {
+        "GroupRepeatMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for GroupRepeatMutator
+where
+    P: Packet + Clone,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let len = input.packets().len();
+        let groups: Vec<_> = mutable_groups(state, input).into_iter().filter(|x| is_terminated(input, x)).collect();
+        
+        if groups.is_empty() || len >= self.max_length {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let group = groups[state.rand_mut().below(groups.len() as u64) as usize].clone();
+        let max_repeats = (self.max_length - len) / group.len();
+        
+        if max_repeats == 0 {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let n = 1 + state.rand_mut().below(max_repeats as u64) as usize;
+        let packets: Vec<P> = input.packets()[group.clone()].iter().cycle().take(n * group.len()).cloned().collect();
+        input.packets_mut().splice(group.start..group.start, packets);
+        log_mutation(state, MutationRecord::new("GroupRepeatMutator").param("start", group.start).param("end", group.end).param("count", n));
+        
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Swaps two whole groups
+pub struct GroupSwapMutator;
+
+impl GroupSwapMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Named for GroupSwapMutator {
+    fn name(&self) -> &str {
+        "GroupSwapMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for GroupSwapMutator
+where
+    P: Packet,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let groups: Vec<_> = mutable_groups(state, input).into_iter().filter(|x| is_terminated(input, x)).collect();
+        
+        if groups.len() < 2 {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let mut first = state.rand_mut().below(groups.len() as u64) as usize;
+        let mut second = state.rand_mut().below(groups.len() as u64) as usize;
+        
+        if first == second {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        if first > second {
+            std::mem::swap(&mut first, &mut second);
+        }
+        
+        let first = groups[first].clone();
+        let second = groups[second].clone();
+        log_mutation(state, MutationRecord::new("GroupSwapMutator").param("first", first.start).param("second", second.start));
+        
+        /* Rotate [first, middle, second] into [second, middle, first] */
+        let packets = &mut input.packets_mut()[first.start..second.end];
+        let middle_len = second.start - first.end;
+        packets.rotate_left(first.len());
+        packets[middle_len..].rotate_right(first.len());
+        packets.rotate_right(second.len());
+        
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Merges two adjacent groups or splits a group in two in order to change
+/// the segmentation of the data that the target observes.
+/// Splitting inserts a [separator](HasGroupBoundary::group_separator) if the packet type has one,
+/// which only happens while the input has less than `max_length` packets.
+pub struct GroupBoundaryMutator {
+    max_length: usize,
+}
+
+impl GroupBoundaryMutator {
+    #[allow(clippy::new_without_default)]
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+        }
+    }
+}
+
+impl Named for GroupBoundaryMutator {
+    fn name(&self) -> &str {
+        "GroupBoundaryMutator"
+    }
+}
+
+impl<P, S> Mutator<DragonflyInput<P>, S> for GroupBoundaryMutator
+where
+    P: HasGroupBoundary,
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        let len = input.packets().len();
+        let prefix = protected_prefix(state, len);
+        
+        if len <= prefix {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let idx = prefix + state.rand_mut().below((len - prefix) as u64) as usize;
+        let packet = &mut input.packets_mut()[idx];
+        
+        if packet.terminates_group() {
+            /* Merge, unless there is no group to merge with */
+            if idx + 1 == len {
+                return Ok(MutationResult::Skipped);
+            }
+            
+            if packet.set_terminates_group(false) {
+                log_mutation(state, MutationRecord::new("GroupBoundaryMutator").param("packet", idx).step("merge"));
+                return Ok(MutationResult::Mutated);
+            }
+            
+            if packet.serialize_content(&mut []).is_none() {
+                input.packets_mut().remove(idx);
+                log_mutation(state, MutationRecord::new("GroupBoundaryMutator").param("packet", idx).step("merge"));
+                return Ok(MutationResult::Mutated);
+            }
+        } else {
+            /* Split */
+            if packet.set_terminates_group(true) {
+                log_mutation(state, MutationRecord::new("GroupBoundaryMutator").param("packet", idx).step("split"));
+                return Ok(MutationResult::Mutated);
+            }
+            
+            if len >= self.max_length {
+                return Ok(MutationResult::Skipped);
+            }
+            
+            if let Some(separator) = P::group_separator() {
+                input.packets_mut().insert(idx + 1, separator);
+                log_mutation(state, MutationRecord::new("GroupBoundaryMutator").param("packet", idx).step("split"));
+                return Ok(MutationResult::Mutated);
+            }
+        }
+        
+        Ok(MutationResult::Skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl::prelude::{ConstFeedback, InMemoryCorpus, StdState};
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    use crate::{
+        components::ProtectedPrefixMetadata,
+        pcap::RawPacket,
+    };
+    
+    fn packet(data: u8, terminates_group: bool) -> RawPacket {
+        RawPacket::new(0, vec![data], terminates_group)
+    }
+    
+    fn groups_of(input: &DragonflyInput<RawPacket>) -> Vec<Vec<u8>> {
+        input.groups().into_iter().map(|x| input.packets()[x].iter().map(|x| x.data()[0]).collect()).collect()
+    }
+    
+    #[test]
+    fn groups() {
+        let input = DragonflyInput::new(vec![
+            packet(b'x', false),
+            packet(b'y', true),
+            packet(b'z', true),
+            packet(b'w', false),
+        ]);
+        assert_eq!(input.groups(), vec![0..2, 2..3, 3..4]);
+    }
+    
+    #[test]
+    fn group_mutators() {
+        let mut state = StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::<DragonflyInput<RawPacket>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        state.add_metadata(ProtectedPrefixMetadata::new(1));
+        let original = DragonflyInput::new(vec![
+            packet(b'a', true),
+            packet(b'b', false),
+            packet(b'c', true),
+            packet(b'd', true),
+            packet(b'e', false),
+            packet(b'f', true),
+        ]);
+        let valid = [vec![b'a'], vec![b'b', b'c'], vec![b'd'], vec![b'e', b'f']];
+        
+        for _ in 0..100 {
+            let mut input = original.clone();
+            
+            GroupDeleteMutator::new(0).mutate(&mut state, &mut input).unwrap();
+            GroupCopyMutator::new(16).mutate(&mut state, &mut input).unwrap();
+            GroupRepeatMutator::new(16).mutate(&mut state, &mut input).unwrap();
+            GroupSwapMutator::new().mutate(&mut state, &mut input).unwrap();
+            
+            let groups = groups_of(&input);
+            assert!(input.packets().len() <= 16);
+            assert_eq!(groups[0], vec![b'a']);
+            assert!(groups.iter().all(|x| valid.contains(x)), "{:?}", groups);
+        }
+        
+        for _ in 0..100 {
+            let mut input = original.clone();
+            
+            if GroupBoundaryMutator::new(16).mutate(&mut state, &mut input).unwrap() == MutationResult::Mutated {
+                assert_eq!(input.packets().len(), original.packets().len());
+                assert_ne!(groups_of(&input), groups_of(&original));
+                assert_eq!(groups_of(&input)[0], vec![b'a']);
+            }
+        }
+    }
+    
+    /// A packet type with dedicated separators like the FTP packets of the fuzzer
+    #[derive(Clone, Debug, PartialEq)]
+    enum SeparatedPacket {
+        Data(u8),
+        Separator,
+    }
+    
+    impl Packet for SeparatedPacket {
+        fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
+            match self {
+                SeparatedPacket::Data(data) => {
+                    buffer[0] = *data;
+                    Some(1)
+                },
+                SeparatedPacket::Separator => None,
+            }
+        }
+        
+        fn terminates_group(&self) -> bool {
+            *self == SeparatedPacket::Separator
+        }
+    }
+    
+    impl HasGroupBoundary for SeparatedPacket {
+        fn set_terminates_group(&mut self, _terminates_group: bool) -> bool {
+            false
+        }
+        
+        fn group_separator() -> Option<Self> {
+            Some(SeparatedPacket::Separator)
+        }
+    }
+    
+    #[test]
+    fn boundary_max_length() {
+        let mut state = StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::<DragonflyInput<RawPacket>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        let original = DragonflyInput::new(vec![SeparatedPacket::Data(b'a'); 4]);
+        
+        for _ in 0..100 {
+            let mut input = original.clone();
+            assert_eq!(GroupBoundaryMutator::new(4).mutate(&mut state, &mut input).unwrap(), MutationResult::Skipped);
+            
+            let mut input = original.clone();
+            assert_eq!(GroupBoundaryMutator::new(5).mutate(&mut state, &mut input).unwrap(), MutationResult::Mutated);
+            assert_eq!(input.packets().len(), 5);
+        }
+    }
+}
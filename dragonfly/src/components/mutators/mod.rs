@@ -4,7 +4,14 @@ mod swap;
 mod repeat;
 mod content;
 mod token;
+mod binary;
 mod create;
+mod tree;
+mod crossover;
+mod group;
+mod connection;
+mod provenance;
+mod fixup;
 
 pub use delete::*;
 pub use copy::*;
@@ -12,4 +19,11 @@ pub use swap::*;
 pub use repeat::*;
 pub use content::*;
 pub use token::*;
+pub use binary::*;
 pub use create::*;
+pub use tree::*;
+pub use crossover::*;
+pub use group::*;
+pub use connection::*;
+pub use provenance::*;
+pub use fixup::*;
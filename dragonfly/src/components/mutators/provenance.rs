@@ -0,0 +1,560 @@
+use libafl_bolts::prelude::{Named, Rand, impl_serdeany};
+use libafl::prelude::{
+    Mutator, MutationResult, Error, HasRand, HasMetadata, HasCorpus, HasSolutions,
+    Corpus, CorpusId, UsesInput, Testcase, Input,
+};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::{self, Debug, Display},
+    hash::Hash,
+    path::{Path, PathBuf},
+};
+use serde::{Serialize, Deserialize};
+use crate::components::{DragonflyInput, Packet, ProtectedPrefixMetadata};
+
+/// A single mutation that was applied to an input together with the decisions it made
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MutationRecord {
+    mutator: String,
+    params: Vec<(String, usize)>,
+    steps: Vec<String>,
+}
+
+impl MutationRecord {
+    pub fn new(mutator: &str) -> Self {
+        Self {
+            mutator: mutator.to_string(),
+            params: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+    
+    pub fn param(mut self, name: &str, value: usize) -> Self {
+        self.params.push((name.to_string(), value));
+        self
+    }
+    
+    pub fn step(mut self, step: &str) -> Self {
+        self.steps.push(step.to_string());
+        self
+    }
+    
+    pub fn mutator(&self) -> &str {
+        &self.mutator
+    }
+    
+    pub fn params(&self) -> &[(String, usize)] {
+        &self.params
+    }
+    
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.params.iter().find(|(x, _)| x == name).map(|(_, x)| *x)
+    }
+    
+    /// The ordered sub-mutations of mutators that stack several of them, e.g. the token mutations
+    pub fn steps(&self) -> &[String] {
+        &self.steps
+    }
+}
+
+impl Display for MutationRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mutator)?;
+        
+        for (name, value) in &self.params {
+            write!(f, " {}={}", name, value)?;
+        }
+        
+        if !self.steps.is_empty() {
+            write!(f, " [{}]", self.steps.join(", "))?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// A corpus entry that a crossover took data from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Donor {
+    id: CorpusId,
+    path: Option<PathBuf>,
+}
+
+impl Donor {
+    pub fn id(&self) -> CorpusId {
+        self.id
+    }
+    
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+/// How a corpus entry or crash was derived from its parent.
+/// Attached to testcases by the [`MutationRecorder`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MutationProvenance {
+    parent_id: Option<CorpusId>,
+    parent: Option<PathBuf>,
+    child: Option<PathBuf>,
+    seed: u64,
+    prefix: usize,
+    /// Every corpus entry a crossover drew, `None` if it drew the parent and was skipped
+    donors: Vec<Option<Donor>>,
+    mutations: Vec<MutationRecord>,
+}
+
+impl_serdeany!(MutationProvenance);
+
+impl MutationProvenance {
+    pub fn parent_id(&self) -> Option<CorpusId> {
+        self.parent_id
+    }
+    
+    pub fn parent(&self) -> Option<&Path> {
+        self.parent.as_deref()
+    }
+    
+    pub fn child(&self) -> Option<&Path> {
+        self.child.as_deref()
+    }
+    
+    /// The seed of the random number generator when the mutation started
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    
+    /// The length of the protected prefix of the parent
+    pub fn prefix(&self) -> usize {
+        self.prefix
+    }
+    
+    pub fn donors(&self) -> &[Option<Donor>] {
+        &self.donors
+    }
+    
+    pub fn mutations(&self) -> &[MutationRecord] {
+        &self.mutations
+    }
+    
+    pub fn to_file<Q: AsRef<Path>>(&self, path: Q) -> Result<(), Error> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())?;
+        Ok(())
+    }
+    
+    pub fn from_file<Q: AsRef<Path>>(path: Q) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| Error::illegal_argument(format!("Invalid mutation provenance: {}", e)))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Replay {
+    records: VecDeque<MutationRecord>,
+    donors: VecDeque<Option<CorpusId>>,
+}
+
+/// Collects the records of the mutators while the [`MutationRecorder`] mutates an input
+/// and holds the recorded decisions while it re-derives one
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MutationLog {
+    records: Vec<MutationRecord>,
+    donors: Vec<Option<Donor>>,
+    replay: Option<Replay>,
+}
+
+impl_serdeany!(MutationLog);
+
+/// Appends a record to the log of the current mutation, if one is being recorded
+pub(crate) fn log_mutation<S: HasMetadata>(state: &mut S, record: MutationRecord) {
+    if let Some(log) = state.metadata_map_mut().get_mut::<MutationLog>() {
+        log.records.push(record);
+    }
+}
+
+/// The number of records in the log. Mutators that wrap other mutators use this
+/// to insert their own record in front of the records of the inner mutator.
+pub(crate) fn logged_mutations<S: HasMetadata>(state: &S) -> usize {
+    state.metadata_map().get::<MutationLog>().map(|x| x.records.len()).unwrap_or(0)
+}
+
+pub(crate) fn log_mutation_at<S: HasMetadata>(state: &mut S, idx: usize, record: MutationRecord) {
+    if let Some(log) = state.metadata_map_mut().get_mut::<MutationLog>() {
+        log.records.insert(idx, record);
+    }
+}
+
+/// While re-deriving, returns the next recorded decisions of `mutator`.
+/// Only mutators whose choices depend on more than the random number generator need this.
+pub(crate) fn replayed_mutation<S: HasMetadata>(state: &mut S, mutator: &str) -> Option<MutationRecord> {
+    let replay = state.metadata_map_mut().get_mut::<MutationLog>()?.replay.as_mut()?;
+    let idx = replay.records.iter().position(|x| x.mutator == mutator)?;
+    replay.records.remove(idx)
+}
+
+/// Draws a corpus entry for a crossover from a random number `draw`.
+/// Returns `None` if the current entry was drawn. When re-deriving, the recorded donor is returned instead.
+pub(crate) fn random_donor<S>(state: &mut S, draw: u64) -> Result<Option<CorpusId>, Error>
+where
+    S: HasCorpus + HasMetadata,
+{
+    if let Some(replay) = state.metadata_map_mut().get_mut::<MutationLog>().and_then(|x| x.replay.as_mut()) {
+        return Ok(replay.donors.pop_front().flatten());
+    }
+    
+    let count = state.corpus().count();
+    
+    if count == 0 {
+        return Ok(None);
+    }
+    
+    let idx = state.corpus().nth((draw % count as u64) as usize);
+    
+    let donor = if state.corpus().current().as_ref() == Some(&idx) {
+        None
+    } else {
+        Some(Donor {
+            id: idx,
+            path: state.corpus().get(idx)?.borrow().file_path().clone(),
+        })
+    };
+    
+    if let Some(log) = state.metadata_map_mut().get_mut::<MutationLog>() {
+        log.donors.push(donor.clone());
+    }
+    
+    Ok(donor.map(|x| x.id))
+}
+
+/// Wraps the mutator of a mutational stage and attaches a [`MutationProvenance`] to every
+/// new corpus entry and crash. The provenance can be used to re-derive the entry from its
+/// parent with [`MutationRecorder::rederive`].
+pub struct MutationRecorder<M> {
+    mutator: M,
+    output_dir: Option<PathBuf>,
+    pending: Option<MutationProvenance>,
+    solutions: usize,
+}
+
+impl<M> MutationRecorder<M> {
+    pub fn new(mutator: M) -> Self {
+        Self {
+            mutator,
+            output_dir: None,
+            pending: None,
+            solutions: 0,
+        }
+    }
+    
+    /// Also write the provenance of every new entry to `<dir>/<filename of the entry>.json`
+    pub fn output_dir<Q: AsRef<Path>>(mut self, dir: Q) -> Result<Self, Error> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        self.output_dir = Some(dir.as_ref().to_path_buf());
+        Ok(self)
+    }
+    
+    pub fn inner(&self) -> &M {
+        &self.mutator
+    }
+    
+    fn attach<I: Input>(&self, testcase: &RefCell<Testcase<I>>, mut provenance: MutationProvenance) -> Result<(), Error> {
+        let mut testcase = testcase.borrow_mut();
+        provenance.child = testcase.file_path().clone();
+        
+        if let (Some(dir), Some(filename)) = (&self.output_dir, testcase.filename()) {
+            provenance.to_file(dir.join(format!("{}.json", filename)))?;
+        }
+        
+        testcase.add_metadata(provenance);
+        Ok(())
+    }
+    
+    /// Re-derives a child from its parent by replaying the decisions in `provenance`.
+    /// The parent and the donors are loaded from the paths in the provenance and
+    /// added to the corpus of `state` for the duration of the mutation.
+    /// `state` should have the same metadata (dictionary, grammar, ...) as the fuzzer had.
+    pub fn rederive<P, S>(&mut self, state: &mut S, provenance: &MutationProvenance) -> Result<DragonflyInput<P>, Error>
+    where
+        M: Mutator<DragonflyInput<P>, S>,
+        P: Packet + Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+        S: HasRand + HasMetadata + HasCorpus,
+        S: UsesInput<Input = DragonflyInput<P>>,
+    {
+        let Some(parent) = provenance.parent() else {
+            return Err(Error::illegal_argument("The provenance does not name a parent file"));
+        };
+        let parent = DragonflyInput::from_file(parent)?;
+        let mut donors = Vec::with_capacity(provenance.donors().len());
+        
+        for donor in provenance.donors() {
+            donors.push(match donor {
+                Some(donor) => match donor.path() {
+                    Some(path) => Some(DragonflyInput::from_file(path)?),
+                    None => return Err(Error::illegal_argument(format!("Donor {} has no file", donor.id()))),
+                },
+                None => None,
+            });
+        }
+        
+        self.rederive_from(state, parent, donors, provenance)
+    }
+    
+    /// Like [`MutationRecorder::rederive`] but with the parent and the inputs of the donors,
+    /// in the order of [`MutationProvenance::donors`], already loaded
+    pub fn rederive_from<P, S>(&mut self, state: &mut S, parent: DragonflyInput<P>, donors: Vec<Option<DragonflyInput<P>>>, provenance: &MutationProvenance) -> Result<DragonflyInput<P>, Error>
+    where
+        M: Mutator<DragonflyInput<P>, S>,
+        P: Packet + Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+        S: HasRand + HasMetadata + HasCorpus,
+        S: UsesInput<Input = DragonflyInput<P>>,
+    {
+        let mut replay = Replay {
+            records: provenance.mutations().iter().cloned().collect(),
+            donors: VecDeque::with_capacity(donors.len()),
+        };
+        let mut added = Vec::with_capacity(donors.len() + 1);
+        
+        for donor in donors {
+            replay.donors.push_back(match donor {
+                Some(donor) => {
+                    let id = state.corpus_mut().add(Testcase::new(donor))?;
+                    added.push(id);
+                    Some(id)
+                },
+                None => None,
+            });
+        }
+        
+        let mut child = parent.clone();
+        let parent = state.corpus_mut().add(Testcase::new(parent))?;
+        added.push(parent);
+        let current = state.corpus_mut().current_mut().replace(parent);
+        
+        state.add_metadata(ProtectedPrefixMetadata::new(provenance.prefix()));
+        state.add_metadata(MutationLog {
+            replay: Some(replay),
+            ..MutationLog::default()
+        });
+        state.rand_mut().set_seed(provenance.seed());
+        
+        let result = self.mutator.mutate(state, &mut child);
+        let _ = state.metadata_map_mut().remove::<MutationLog>();
+        
+        /* Leave the corpus of the caller as it was */
+        *state.corpus_mut().current_mut() = current;
+        
+        for id in added {
+            state.corpus_mut().remove(id)?;
+        }
+        
+        result?;
+        
+        Ok(child)
+    }
+}
+
+impl<M> Named for MutationRecorder<M> {
+    fn name(&self) -> &str {
+        "MutationRecorder"
+    }
+}
+
+impl<M, P, S> Mutator<DragonflyInput<P>, S> for MutationRecorder<M>
+where
+    M: Mutator<DragonflyInput<P>, S>,
+    P: Packet + Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+    S: HasRand + HasMetadata + HasCorpus + HasSolutions,
+    S: UsesInput<Input = DragonflyInput<P>>,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
+        /* Reseed so that all decisions of this mutation follow from a single number */
+        let seed = state.rand_mut().next();
+        state.rand_mut().set_seed(seed);
+        
+        let prefix = state.metadata::<ProtectedPrefixMetadata>().map(|x| x.len()).unwrap_or(0);
+        let parent_id = *state.corpus().current();
+        let parent = match parent_id {
+            Some(idx) => state.corpus().get(idx)?.borrow().file_path().clone(),
+            None => None,
+        };
+        
+        state.add_metadata(MutationLog::default());
+        let result = self.mutator.mutate(state, input);
+        let log = state.metadata_map_mut().remove::<MutationLog>().unwrap_or_default();
+        
+        self.solutions = state.solutions().count();
+        self.pending = Some(MutationProvenance {
+            parent_id,
+            parent,
+            child: None,
+            seed,
+            prefix,
+            donors: log.donors,
+            mutations: log.records,
+        });
+        
+        result
+    }
+    
+    fn post_exec(&mut self, state: &mut S, new_corpus_idx: Option<CorpusId>) -> Result<(), Error> {
+        self.mutator.post_exec(state, new_corpus_idx)?;
+        
+        let Some(provenance) = self.pending.take() else {
+            return Ok(());
+        };
+        
+        if let Some(idx) = new_corpus_idx {
+            self.attach(state.corpus().get(idx)?, provenance.clone())?;
+        }
+        
+        if state.solutions().count() > self.solutions {
+            if let Some(idx) = state.solutions().last() {
+                self.attach(state.solutions().get(idx)?, provenance)?;
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl::prelude::{ConstFeedback, InMemoryCorpus, StdState, StdScheduledMutator};
+    use libafl_bolts::prelude::{StdRand, current_nanos, tuple_list};
+    use crate::{
+        components::{
+            PacketCopyMutator, PacketDeleteMutator, PacketSwapMutator, PacketContentMutator,
+            TokenStreamMutator, PacketCrossoverInsertMutator, PacketCrossoverReplaceMutator,
+            BinaryStreamMutator,
+        },
+        tokens::TokenStream,
+        binary::BinaryStream,
+    };
+    
+    type State = StdState<DragonflyInput<TokenStream>, InMemoryCorpus<DragonflyInput<TokenStream>>, StdRand, InMemoryCorpus<DragonflyInput<TokenStream>>>;
+    
+    fn new_state() -> State {
+        StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap()
+    }
+    
+    fn input(lines: &[&str]) -> DragonflyInput<TokenStream> {
+        DragonflyInput::new(lines.iter().map(|x| x.parse().unwrap()).collect())
+    }
+    
+    fn new_recorder() -> MutationRecorder<impl Mutator<DragonflyInput<TokenStream>, State>> {
+        MutationRecorder::new(StdScheduledMutator::new(tuple_list!(
+            PacketCopyMutator::new(8),
+            PacketDeleteMutator::new(1),
+            PacketSwapMutator::new(),
+            PacketContentMutator::new(TokenStreamMutator::new(64)),
+            PacketCrossoverInsertMutator::new(8),
+            PacketCrossoverReplaceMutator::new(8)
+        )))
+    }
+    
+    #[test]
+    fn rederive() {
+        let mut state = new_state();
+        let parent = input(&["USER ftp\r\n", "PASS x\r\n", "CWD /tmp\r\n"]);
+        let current = state.corpus_mut().add(Testcase::new(parent.clone())).unwrap();
+        state.corpus_mut().add(Testcase::new(input(&["PORT 127,0,0,1,80,80\r\n", "LIST\r\n"]))).unwrap();
+        *state.corpus_mut().current_mut() = Some(current);
+        state.add_metadata(ProtectedPrefixMetadata::new(1));
+        
+        let mut recorder = new_recorder();
+        let mut replayer = new_recorder();
+        let mut replay_state = new_state();
+        
+        for _ in 0..100 {
+            let mut child = parent.clone();
+            
+            if recorder.mutate(&mut state, &mut child).unwrap() == MutationResult::Skipped {
+                continue;
+            }
+            
+            let idx = state.corpus_mut().add(Testcase::new(child.clone())).unwrap();
+            recorder.post_exec(&mut state, Some(idx)).unwrap();
+            
+            let testcase = state.corpus().get(idx).unwrap().borrow();
+            let provenance = testcase.metadata::<MutationProvenance>().unwrap();
+            assert_eq!(provenance.parent_id(), Some(current));
+            assert_eq!(provenance.prefix(), 1);
+            assert!(!provenance.mutations().is_empty());
+            
+            let donors = provenance.donors().iter().map(|x| x.as_ref().map(|x| {
+                state.corpus().get(x.id()).unwrap().borrow().input().clone().unwrap()
+            })).collect();
+            let rederived = replayer.rederive_from(&mut replay_state, parent.clone(), donors, provenance).unwrap();
+            
+            assert_eq!(rederived.packets().len(), child.packets().len());
+            
+            for (a, b) in rederived.packets().iter().zip(child.packets()) {
+                assert_eq!(a.tokens(), b.tokens(), "{:?}", provenance.mutations());
+            }
+            
+            assert_eq!(replay_state.corpus().count(), 0);
+            assert!(replay_state.corpus().current().is_none());
+        }
+    }
+    
+    type BinaryState = StdState<DragonflyInput<BinaryStream>, InMemoryCorpus<DragonflyInput<BinaryStream>>, StdRand, InMemoryCorpus<DragonflyInput<BinaryStream>>>;
+    
+    fn new_binary_state() -> BinaryState {
+        StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap()
+    }
+    
+    #[test]
+    fn rederive_binary() {
+        let mut state = new_binary_state();
+        let parent = DragonflyInput::new(vec!["u16be:0x1234 blob8:\"hello\" u8:1".parse::<BinaryStream>().unwrap()]);
+        let current = state.corpus_mut().add(Testcase::new(parent.clone())).unwrap();
+        
+        /* Several donors such that replaying a different one would be noticed */
+        for i in 0..8 {
+            let donor = format!("u32le:{} crc32be{{ \"donor{}\" }}", i, i).parse::<BinaryStream>().unwrap();
+            state.corpus_mut().add(Testcase::new(DragonflyInput::new(vec![donor]))).unwrap();
+        }
+        
+        *state.corpus_mut().current_mut() = Some(current);
+        
+        let mut recorder = MutationRecorder::new(PacketContentMutator::new(BinaryStreamMutator::new(64)));
+        let mut replayer = MutationRecorder::new(PacketContentMutator::new(BinaryStreamMutator::new(64)));
+        let mut replay_state = new_binary_state();
+        
+        for _ in 0..100 {
+            let mut child = parent.clone();
+            
+            if recorder.mutate(&mut state, &mut child).unwrap() == MutationResult::Skipped {
+                continue;
+            }
+            
+            let idx = state.corpus_mut().add(Testcase::new(child.clone())).unwrap();
+            recorder.post_exec(&mut state, Some(idx)).unwrap();
+            
+            let testcase = state.corpus().get(idx).unwrap().borrow();
+            let provenance = testcase.metadata::<MutationProvenance>().unwrap();
+            assert!(provenance.mutations().iter().any(|x| x.mutator() == "BinaryStreamMutator"));
+            
+            let donors = provenance.donors().iter().map(|x| x.as_ref().map(|x| {
+                state.corpus().get(x.id()).unwrap().borrow().input().clone().unwrap()
+            })).collect();
+            let rederived = replayer.rederive_from(&mut replay_state, parent.clone(), donors, provenance).unwrap();
+            assert_eq!(rederived.packets(), child.packets(), "{:?}", provenance.mutations());
+            assert_eq!(replay_state.corpus().count(), 0);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use libafl_bolts::prelude::{Named, Rand};
-use libafl::prelude::{Mutator, MutationResult, Error, HasRand};
-use crate::components::{DragonflyInput, Packet};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use crate::components::{DragonflyInput, Packet, protected_prefix, MutationRecord, log_mutation};
 
 pub struct PacketRepeatMutator {
     max_length: usize,
@@ -24,19 +24,21 @@ impl Named for PacketRepeatMutator {
 impl<P, S> Mutator<DragonflyInput<P>, S> for PacketRepeatMutator
 where
     P: Packet + Clone,
-    S: HasRand,
+    S: HasRand + HasMetadata,
 {
     fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
         let len = input.packets().len();
+        let prefix = protected_prefix(state, len);
         
-        if len == 0 || len >= self.max_length {
+        if len <= prefix || len >= self.max_length {
             return Ok(MutationResult::Skipped);
         }
         
-        let idx = state.rand_mut().below(len as u64) as usize;
+        let idx = prefix + state.rand_mut().below((len - prefix) as u64) as usize;
         let n = 1 + state.rand_mut().below((self.max_length - len) as u64) as usize;
         let packet = input.packets()[idx].clone();
         input.packets_mut().splice(idx..idx, vec![packet; n]);
+        log_mutation(state, MutationRecord::new("PacketRepeatMutator").param("packet", idx).param("count", n));
         
         Ok(MutationResult::Mutated)
     }
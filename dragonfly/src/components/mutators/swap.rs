@@ -1,6 +1,6 @@
 use libafl_bolts::prelude::{Named, Rand};
-use libafl::prelude::{Mutator, MutationResult, Error, HasRand};
-use crate::components::{DragonflyInput, Packet};
+use libafl::prelude::{Mutator, MutationResult, Error, HasRand, HasMetadata};
+use crate::components::{DragonflyInput, Packet, protected_prefix, MutationRecord, log_mutation};
 
 pub struct PacketSwapMutator;
 
@@ -20,23 +20,25 @@ impl Named for PacketSwapMutator {
 impl<P, S> Mutator<DragonflyInput<P>, S> for PacketSwapMutator
 where
     P: Packet,
-    S: HasRand,
+    S: HasRand + HasMetadata,
 {
     fn mutate(&mut self, state: &mut S, input: &mut DragonflyInput<P>) -> Result<MutationResult, Error> {
         let len = input.packets().len();
+        let prefix = protected_prefix(state, len);
         
-        if len <= 1 {
+        if len <= prefix + 1 {
             return Ok(MutationResult::Skipped);
         }
         
-        let to = state.rand_mut().below(len as u64) as usize;
-        let from = state.rand_mut().below(len as u64) as usize;
+        let to = prefix + state.rand_mut().below((len - prefix) as u64) as usize;
+        let from = prefix + state.rand_mut().below((len - prefix) as u64) as usize;
         
         if to == from {
             return Ok(MutationResult::Skipped);
         }
         
         input.packets_mut().swap(to, from);
+        log_mutation(state, MutationRecord::new("PacketSwapMutator").param("from", from).param("to", to));
         
         Ok(MutationResult::Mutated)
     }
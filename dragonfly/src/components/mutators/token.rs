@@ -1,37 +1,397 @@
-use libafl_bolts::prelude::{Rand, StdRand};
-use libafl::prelude::{MutationResult, Error, HasRand, HasMetadata, Tokens, HasCorpus, random_corpus_id, Corpus, UsesInput};
-use std::hash::Hash;
+use libafl_bolts::prelude::{Named, Rand, StdRand, impl_serdeany};
+use libafl::prelude::{MutationResult, Error, HasRand, HasMetadata, Tokens, HasCorpus, Corpus, UsesInput, HasExecutions, Mutator, CorpusId};
+use std::{
+    collections::BTreeMap,
+    hash::Hash,
+    path::Path,
+};
 use crate::{
-    components::{PacketMutator, Packet, DragonflyInput},
+    components::{PacketMutator, Packet, DragonflyInput, MutationRecord, log_mutation, replayed_mutation, random_donor},
     tokens::{HasTokenStream, mutators::*},
 };
 use serde::{Serialize, Deserialize};
 
-const STACKS: [usize; 4] = [
-    2,
-    4,
-    16,
-    128,
-];
+/// The mutations of the [`TokenStreamMutator`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenMutation {
+    Copy,
+    CrossoverInsert,
+    CrossoverReplace,
+    Delete,
+    Flip,
+    Interesting,
+    RandomInsert,
+    RandomReplace,
+    RepeatChar,
+    RepeatToken,
+    SpecialInsert,
+    SpecialReplace,
+    Split,
+    SwapTokens,
+    SwapWords,
+    Truncate,
+    CrossoverLines,
+    CrossoverArguments,
+    DictInsert,
+    DictReplace,
+    SwapConstants,
+}
 
-pub struct TokenStreamMutator {
+impl TokenMutation {
+    pub const ALL: [TokenMutation; 21] = [
+        TokenMutation::Copy,
+        TokenMutation::CrossoverInsert,
+        TokenMutation::CrossoverReplace,
+        TokenMutation::Delete,
+        TokenMutation::Flip,
+        TokenMutation::Interesting,
+        TokenMutation::RandomInsert,
+        TokenMutation::RandomReplace,
+        TokenMutation::RepeatChar,
+        TokenMutation::RepeatToken,
+        TokenMutation::SpecialInsert,
+        TokenMutation::SpecialReplace,
+        TokenMutation::Split,
+        TokenMutation::SwapTokens,
+        TokenMutation::SwapWords,
+        TokenMutation::Truncate,
+        TokenMutation::CrossoverLines,
+        TokenMutation::CrossoverArguments,
+        TokenMutation::DictInsert,
+        TokenMutation::DictReplace,
+        TokenMutation::SwapConstants,
+    ];
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenMutation::Copy => "copy",
+            TokenMutation::CrossoverInsert => "crossover_insert",
+            TokenMutation::CrossoverReplace => "crossover_replace",
+            TokenMutation::Delete => "delete",
+            TokenMutation::Flip => "flip",
+            TokenMutation::Interesting => "interesting",
+            TokenMutation::RandomInsert => "random_insert",
+            TokenMutation::RandomReplace => "random_replace",
+            TokenMutation::RepeatChar => "repeat_char",
+            TokenMutation::RepeatToken => "repeat_token",
+            TokenMutation::SpecialInsert => "special_insert",
+            TokenMutation::SpecialReplace => "special_replace",
+            TokenMutation::Split => "split",
+            TokenMutation::SwapTokens => "swap_tokens",
+            TokenMutation::SwapWords => "swap_words",
+            TokenMutation::Truncate => "truncate",
+            TokenMutation::CrossoverLines => "crossover_lines",
+            TokenMutation::CrossoverArguments => "crossover_arguments",
+            TokenMutation::DictInsert => "dict_insert",
+            TokenMutation::DictReplace => "dict_replace",
+            TokenMutation::SwapConstants => "swap_constants",
+        }
+    }
+    
+    /// Whether the mutation needs a [`Tokens`] dictionary in the state
+    pub fn needs_dict(&self) -> bool {
+        matches!(self, TokenMutation::DictInsert | TokenMutation::DictReplace | TokenMutation::SwapConstants)
+    }
+    
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.name() == name)
+    }
+    
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// How often an operator was applied and how often that resulted in a new corpus entry
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct OperatorStats {
+    attempts: u64,
+    successes: u64,
+}
+
+impl OperatorStats {
+    pub fn attempts(&self) -> u64 {
+        self.attempts
+    }
+    
+    pub fn successes(&self) -> u64 {
+        self.successes
+    }
+    
+    /// Estimated probability that an application finds something new.
+    /// Never drops to zero so that every operator keeps getting a chance.
+    pub fn efficiency(&self) -> f64 {
+        (self.successes as f64 + 1.0) / (self.attempts as f64 + 2.0)
+    }
+    
+    fn record(&mut self, success: bool) {
+        self.attempts += 1;
+        self.successes += success as u64;
+    }
+}
+
+/// Success statistics of the [`TokenStreamMutator`] that bias its choice of mutations and stack sizes.
+///
+/// Mutations that were applied for an execution get credited when the mutational stage reports the outcome
+/// of that execution to the [`TokenMutationStatsRecorder`] wrapping the mutator:
+/// if the execution added a corpus entry, all of them count as a success.
+/// Mutations whose execution never gets reported are dropped when the next execution is mutated.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenMutationStats {
+    mutations: Vec<OperatorStats>,
+    stacks: BTreeMap<usize, OperatorStats>,
+    pending_mutations: Vec<TokenMutation>,
+    pending_stacks: Vec<usize>,
+    executions: u64,
+}
+
+impl_serdeany!(TokenMutationStats);
+
+impl Default for TokenMutationStats {
+    fn default() -> Self {
+        Self {
+            mutations: vec![OperatorStats::default(); TokenMutation::ALL.len()],
+            stacks: BTreeMap::new(),
+            pending_mutations: Vec::new(),
+            pending_stacks: Vec::new(),
+            executions: 0,
+        }
+    }
+}
+
+impl TokenMutationStats {
+    pub fn mutation(&self, mutation: TokenMutation) -> &OperatorStats {
+        &self.mutations[mutation.index()]
+    }
+    
+    /// Statistics of each stack size, i.e. the number of mutations applied in a row
+    pub fn stacks(&self) -> impl Iterator<Item = (usize, &OperatorStats)> {
+        self.stacks.iter().map(|(size, stats)| (*size, stats))
+    }
+    
+    fn stack_efficiency(&self, stack: usize) -> f64 {
+        self.stacks.get(&stack).copied().unwrap_or_default().efficiency()
+    }
+    
+    /// Drops pending mutations of earlier executions whose outcome was never reported.
+    /// Multiple calls before the same execution accumulate.
+    fn start(&mut self, executions: u64) {
+        if executions != self.executions {
+            self.pending_mutations.clear();
+            self.pending_stacks.clear();
+            self.executions = executions;
+        }
+    }
+    
+    /// Credits the pending mutations with the outcome of the execution they were applied for
+    fn credit(&mut self, success: bool) {
+        for mutation in self.pending_mutations.drain(..) {
+            self.mutations[mutation.index()].record(success);
+        }
+        
+        for stack in self.pending_stacks.drain(..) {
+            self.stacks.entry(stack).or_default().record(success);
+        }
+    }
+}
+
+/// Picks an index with a probability proportional to its weight
+fn choose_weighted<R: Rand>(rand: &mut R, weights: &[f64]) -> usize {
+    let total: f64 = weights.iter().sum();
+    let mut point = (rand.next() >> 11) as f64 / (1u64 << 53) as f64 * total;
+    
+    for (i, weight) in weights.iter().enumerate() {
+        if point < *weight {
+            return i;
+        }
+        
+        point -= weight;
+    }
+    
+    weights.iter().rposition(|x| *x > 0.0).unwrap_or(0)
+}
+
+/// Everything that can be configured about a [`TokenStreamMutator`].
+/// Harnesses can write it to their output directory to record the mutation settings of a run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenMutationConfig {
     max_tokens: usize,
+    weights: BTreeMap<TokenMutation, f64>,
+    max_repeat_char: usize,
+    max_repeat_token: usize,
+    stacks: Vec<usize>,
+    adaptive: bool,
+}
+
+impl TokenMutationConfig {
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+    
+    /// Relative weight of a mutation, 0 if it is disabled.
+    /// Mutations that are missing from the config keep the default weight of 1.
+    pub fn weight(&self, mutation: TokenMutation) -> f64 {
+        self.weights.get(&mutation).copied().unwrap_or(1.0)
+    }
+    
+    pub fn max_repeat_char(&self) -> usize {
+        self.max_repeat_char
+    }
+    
+    pub fn max_repeat_token(&self) -> usize {
+        self.max_repeat_token
+    }
+    
+    pub fn stacks(&self) -> &[usize] {
+        &self.stacks
+    }
+    
+    /// Whether the weights get scaled by the [`TokenMutationStats`]
+    pub fn adaptive(&self) -> bool {
+        self.adaptive
+    }
+    
+    pub fn to_file<Q: AsRef<Path>>(&self, path: Q) -> Result<(), Error> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())?;
+        Ok(())
+    }
+    
+    pub fn from_file<Q: AsRef<Path>>(path: Q) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| Error::illegal_argument(format!("Invalid token mutation config: {}", e)))
+    }
+    
+    fn validate(&self) -> Result<(), Error> {
+        if let Some((mutation, weight)) = self.weights.iter().find(|(_, x)| !x.is_finite() || **x < 0.0) {
+            return Err(Error::illegal_argument(format!("Invalid weight {} for {}", weight, mutation.name())));
+        }
+        
+        if !TokenMutation::ALL.iter().any(|x| self.weight(*x) > 0.0) {
+            return Err(Error::illegal_argument("All token mutations are disabled"));
+        }
+        
+        if self.stacks.is_empty() || self.stacks.contains(&0) {
+            return Err(Error::illegal_argument("Stack sizes must be non-empty and positive"));
+        }
+        
+        if self.max_repeat_char == 0 || self.max_repeat_token == 0 {
+            return Err(Error::illegal_argument("Repeat maximums must be positive"));
+        }
+        
+        Ok(())
+    }
+}
+
+impl Default for TokenMutationConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 128,
+            weights: TokenMutation::ALL.into_iter().map(|x| (x, 1.0)).collect(),
+            max_repeat_char: 4096,
+            max_repeat_token: 4096,
+            stacks: vec![2, 4, 16, 128],
+            adaptive: true,
+        }
+    }
+}
+
+/// Builds a [`TokenStreamMutator`]. All mutations start with a weight of 1.
+pub struct TokenStreamMutatorBuilder {
+    config: TokenMutationConfig,
+}
+
+impl TokenStreamMutatorBuilder {
+    /// Start from an existing configuration, e.g. one loaded with [`TokenMutationConfig::from_file`]
+    pub fn config(mut self, config: TokenMutationConfig) -> Self {
+        self.config = config;
+        self
+    }
+    
+    /// Maximum number of tokens in a stream
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self {
+        self.config.max_tokens = max_tokens;
+        self
+    }
+    
+    /// Sets the relative weight of a mutation. A weight of 0 disables it.
+    pub fn weight(mut self, mutation: TokenMutation, weight: f64) -> Self {
+        self.config.weights.insert(mutation, weight);
+        self
+    }
+    
+    pub fn disable(self, mutation: TokenMutation) -> Self {
+        self.weight(mutation, 0.0)
+    }
+    
+    /// Number of characters that a single character gets repeated up to
+    pub fn max_repeat_char(mut self, max: usize) -> Self {
+        self.config.max_repeat_char = max;
+        self
+    }
+    
+    /// Number of bytes that repetitions of a single token add up to
+    pub fn max_repeat_token(mut self, max: usize) -> Self {
+        self.config.max_repeat_token = max;
+        self
+    }
+    
+    /// The numbers of mutations that can be applied in a row
+    pub fn stacks(mut self, stacks: &[usize]) -> Self {
+        self.config.stacks = stacks.to_vec();
+        self
+    }
+    
+    /// Whether to bias the choice of mutations and stack sizes by their success.
+    /// Defaults to true.
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.config.adaptive = adaptive;
+        self
+    }
+    
+    pub fn build(self) -> Result<TokenStreamMutator, Error> {
+        self.config.validate()?;
+        
+        Ok(TokenStreamMutator {
+            config: self.config,
+            rand: StdRand::with_seed(0),
+        })
+    }
+}
+
+pub struct TokenStreamMutator {
+    config: TokenMutationConfig,
     rand: StdRand,
 }
 
 impl TokenStreamMutator {
+    /// Create a mutator with the default configuration.
+    /// A `max_tokens` of 0 disables all mutations that grow a stream.
     pub fn new(max_tokens: usize) -> Self {
         Self {
-            max_tokens,
+            config: TokenMutationConfig {
+                max_tokens,
+                ..TokenMutationConfig::default()
+            },
             rand: StdRand::with_seed(0),
         }
     }
+    
+    pub fn builder() -> TokenStreamMutatorBuilder {
+        TokenStreamMutatorBuilder {
+            config: TokenMutationConfig::default(),
+        }
+    }
+    
+    pub fn config(&self) -> &TokenMutationConfig {
+        &self.config
+    }
 }
 
 impl<P, S> PacketMutator<P, S> for TokenStreamMutator
 where
     P: Packet + HasTokenStream + std::fmt::Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
-    S: HasRand + HasMetadata + HasCorpus,
+    S: HasRand + HasMetadata + HasCorpus + HasExecutions,
     S: UsesInput<Input = DragonflyInput<P>>,
 {
     fn mutate_packet(&mut self, state: &mut S, packet: &mut P) -> Result<MutationResult, Error> {
@@ -39,28 +399,64 @@ where
             return Ok(MutationResult::Skipped);
         }
         
-        let stream = packet.token_stream_mut();
-        let stack = state.rand_mut().choose(STACKS);
-        let mut mutated = false;
-        let mut num_mutations = 19;
+        let max_tokens = self.config.max_tokens;
+        let executions = *state.executions();
+        let has_dict = state.metadata_map().get::<Tokens>().is_some();
+        let stats = state.metadata_or_insert_with(TokenMutationStats::default);
+        stats.start(executions);
+        
+        let mutation_weights: Vec<f64> = TokenMutation::ALL.iter().map(|x| {
+            if x.needs_dict() && !has_dict {
+                0.0
+            } else if self.config.adaptive {
+                self.config.weight(*x) * stats.mutation(*x).efficiency()
+            } else {
+                self.config.weight(*x)
+            }
+        }).collect();
+        let stack_weights: Vec<f64> = self.config.stacks.iter().map(|x| {
+            if self.config.adaptive {
+                stats.stack_efficiency(*x)
+            } else {
+                1.0
+            }
+        }).collect();
+        
+        if !mutation_weights.iter().any(|x| *x > 0.0) {
+            return Ok(MutationResult::Skipped);
+        }
         
         self.rand.set_seed(state.rand_mut().next());
         
-        let dict = state.metadata_map().get::<Tokens>();
+        /* When re-deriving an input, the weights may differ from the original run, so take the recorded choices */
+        let replayed = replayed_mutation(state, "TokenStreamMutator");
+        let stream = packet.token_stream_mut();
+        let mut stack = self.config.stacks[choose_weighted(&mut self.rand, &stack_weights)];
+        let mut chosen = Vec::new();
+        let mut applied = Vec::new();
         
-        if dict.is_none() {
-            num_mutations -= 3;
+        if let Some(record) = &replayed {
+            stack = record.get("stack").unwrap_or(stack);
         }
         
-        for _ in 0..stack {
-            mutated |= match self.rand.below(num_mutations) {
-                0 => mutate_copy(&mut self.rand, stream, self.max_tokens),
-                1 => {
-                    let idx = random_corpus_id!(state.corpus(), &mut self.rand);
-                    
-                    if state.corpus().current().as_ref() == Some(&idx) {
+        for i in 0..stack {
+            let mut mutation = TokenMutation::ALL[choose_weighted(&mut self.rand, &mutation_weights)];
+            
+            if let Some(step) = replayed.as_ref().and_then(|x| x.steps().get(i)).and_then(|x| TokenMutation::from_name(x)) {
+                mutation = step;
+            }
+            
+            chosen.push(mutation);
+            
+            let success = match mutation {
+                TokenMutation::Copy => mutate_copy(&mut self.rand, stream, max_tokens),
+                TokenMutation::CrossoverInsert |
+                TokenMutation::CrossoverReplace |
+                TokenMutation::CrossoverLines |
+                TokenMutation::CrossoverArguments => {
+                    let Some(idx) = random_donor(state, self.rand.next())? else {
                         continue;
-                    }
+                    };
                     
                     let mut other_testcase = state.corpus().get(idx)?.borrow_mut();
                     let other_testcase = other_testcase.load_input(state.corpus())?;
@@ -76,67 +472,237 @@ where
                         continue;
                     }
                     
-                    mutate_crossover_insert(&mut self.rand, stream, other_packet.token_stream(), self.max_tokens)
-                },
-                2 => {
-                    let idx = random_corpus_id!(state.corpus(), &mut self.rand);
-                    
-                    if state.corpus().current().as_ref() == Some(&idx) {
-                        continue;
-                    }
-                    
-                    let mut other_testcase = state.corpus().get(idx)?.borrow_mut();
-                    let other_testcase = other_testcase.load_input(state.corpus())?;
-                    
-                    if other_testcase.packets().is_empty() {
-                        continue;
-                    }
-                    
-                    let idx = self.rand.below(other_testcase.packets().len() as u64) as usize;
-                    let other_packet = &other_testcase.packets()[idx];
+                    let other = other_packet.token_stream();
                     
-                    if !other_packet.has_token_stream() {
-                        continue;
+                    match mutation {
+                        TokenMutation::CrossoverInsert => mutate_crossover_insert(&mut self.rand, stream, other, max_tokens),
+                        TokenMutation::CrossoverReplace => mutate_crossover_replace(&mut self.rand, stream, other, max_tokens),
+                        TokenMutation::CrossoverLines => mutate_crossover_lines(&mut self.rand, stream, other, max_tokens),
+                        _ => mutate_crossover_arguments(&mut self.rand, stream, other, max_tokens),
                     }
-                    
-                    mutate_crossover_replace(&mut self.rand, stream, other_packet.token_stream(), self.max_tokens)
                 },
-                3 => mutate_delete(&mut self.rand, stream),
-                4 => mutate_flip(&mut self.rand, stream),
-                5 => mutate_interesting(&mut self.rand, stream),
-                6 => mutate_random_insert(&mut self.rand, stream, self.max_tokens),
-                7 => mutate_random_replace(&mut self.rand, stream),
-                8 => mutate_repeat_char::<_, 4096>(&mut self.rand, stream),
-                9 => mutate_repeat_token::<_, 4096>(&mut self.rand, stream, self.max_tokens),
-                10 => mutate_special_insert(&mut self.rand, stream),
-                11 => mutate_special_replace(&mut self.rand, stream),
-                12 => mutate_split(&mut self.rand, stream, self.max_tokens),
-                13 => mutate_swap_tokens(&mut self.rand, stream),
-                14 => mutate_swap_words(&mut self.rand, stream),
-                15 => mutate_truncate(&mut self.rand, stream),
-                16 => {
-                    debug_assert!(dict.is_some());
-                    let dict = unsafe { dict.unwrap_unchecked() };
-                    mutate_dict_insert(&mut self.rand, stream, dict, self.max_tokens)
+                TokenMutation::Delete => mutate_delete(&mut self.rand, stream),
+                TokenMutation::Flip => mutate_flip(&mut self.rand, stream),
+                TokenMutation::Interesting => mutate_interesting(&mut self.rand, stream),
+                TokenMutation::RandomInsert => mutate_random_insert(&mut self.rand, stream, max_tokens),
+                TokenMutation::RandomReplace => mutate_random_replace(&mut self.rand, stream),
+                TokenMutation::RepeatChar => mutate_repeat_char(&mut self.rand, stream, self.config.max_repeat_char),
+                TokenMutation::RepeatToken => mutate_repeat_token(&mut self.rand, stream, max_tokens, self.config.max_repeat_token),
+                TokenMutation::SpecialInsert => mutate_special_insert(&mut self.rand, stream),
+                TokenMutation::SpecialReplace => mutate_special_replace(&mut self.rand, stream),
+                TokenMutation::Split => mutate_split(&mut self.rand, stream, max_tokens),
+                TokenMutation::SwapTokens => mutate_swap_tokens(&mut self.rand, stream),
+                TokenMutation::SwapWords => mutate_swap_words(&mut self.rand, stream),
+                TokenMutation::Truncate => mutate_truncate(&mut self.rand, stream),
+                TokenMutation::DictInsert => {
+                    let Some(dict) = state.metadata_map().get::<Tokens>() else {
+                        continue;
+                    };
+                    mutate_dict_insert(&mut self.rand, stream, dict, max_tokens)
                 },
-                17 => {
-                    debug_assert!(dict.is_some());
-                    let dict = unsafe { dict.unwrap_unchecked() };
+                TokenMutation::DictReplace => {
+                    let Some(dict) = state.metadata_map().get::<Tokens>() else {
+                        continue;
+                    };
                     mutate_dict_replace(&mut self.rand, stream, dict)
                 },
-                18 => {
-                    debug_assert!(dict.is_some());
-                    let dict = unsafe { dict.unwrap_unchecked() };
+                TokenMutation::SwapConstants => {
+                    let Some(dict) = state.metadata_map().get::<Tokens>() else {
+                        continue;
+                    };
                     mutate_swap_constants(&mut self.rand, stream, dict)
                 },
-                _ => unreachable!(),
             };
+            
+            if success {
+                applied.push(mutation);
+            }
+        }
+        
+        let record = chosen.iter().fold(MutationRecord::new("TokenStreamMutator"), |record, x| record.step(x.name()));
+        log_mutation(state, record.param("stack", stack).param("applied", applied.len()));
+        
+        if applied.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let stats = state.metadata_mut::<TokenMutationStats>()?;
+        stats.pending_mutations.extend(applied);
+        stats.pending_stacks.push(stack);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+/// Wraps the mutator of a mutational stage and reports the outcome of every execution to the
+/// [`TokenMutationStats`]. Without it the statistics never learn anything, because scheduled
+/// mutators don't pass the outcome on to the [`TokenStreamMutator`].
+pub struct TokenMutationStatsRecorder<M> {
+    mutator: M,
+}
+
+impl<M> TokenMutationStatsRecorder<M> {
+    pub fn new(mutator: M) -> Self {
+        Self {
+            mutator,
+        }
+    }
+    
+    pub fn inner(&self) -> &M {
+        &self.mutator
+    }
+}
+
+impl<M> Named for TokenMutationStatsRecorder<M> {
+    fn name(&self) -> &str {
+        "TokenMutationStatsRecorder"
+    }
+}
+
+impl<M, I, S> Mutator<I, S> for TokenMutationStatsRecorder<M>
+where
+    M: Mutator<I, S>,
+    S: HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut I) -> Result<MutationResult, Error> {
+        self.mutator.mutate(state, input)
+    }
+    
+    fn post_exec(&mut self, state: &mut S, new_corpus_idx: Option<CorpusId>) -> Result<(), Error> {
+        self.mutator.post_exec(state, new_corpus_idx)?;
+        
+        if let Ok(stats) = state.metadata_mut::<TokenMutationStats>() {
+            stats.credit(new_corpus_idx.is_some());
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl::prelude::{ConstFeedback, InMemoryCorpus, StdState, Testcase};
+    use libafl_bolts::prelude::current_nanos;
+    use crate::{components::PacketContentMutator, tokens::TokenStream};
+    
+    #[test]
+    fn credit_mutations() {
+        let mut stats = TokenMutationStats::default();
+        stats.start(1);
+        stats.pending_mutations.push(TokenMutation::Delete);
+        stats.pending_stacks.push(2);
+        stats.start(1);
+        stats.pending_mutations.push(TokenMutation::Flip);
+        
+        /* The execution added a corpus entry */
+        stats.credit(true);
+        assert_eq!(stats.mutation(TokenMutation::Delete).successes(), 1);
+        assert_eq!(stats.mutation(TokenMutation::Flip).successes(), 1);
+        assert_eq!(stats.stacks().collect::<Vec<_>>()[0].1.attempts(), 1);
+        assert!(stats.pending_mutations.is_empty());
+        
+        stats.start(2);
+        stats.pending_mutations.push(TokenMutation::Delete);
+        stats.credit(false);
+        assert_eq!(stats.mutation(TokenMutation::Delete).attempts(), 2);
+        assert_eq!(stats.mutation(TokenMutation::Delete).successes(), 1);
+        
+        /* Outcomes that were never reported are unknown */
+        stats.start(3);
+        stats.pending_mutations.push(TokenMutation::Delete);
+        stats.start(7);
+        stats.credit(true);
+        assert_eq!(stats.mutation(TokenMutation::Delete).attempts(), 2);
+    }
+    
+    #[test]
+    fn record_outcomes() {
+        let input = DragonflyInput::new(vec!["USER anonymous\r\n".parse::<TokenStream>().unwrap()]);
+        let mut state = StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::<DragonflyInput<TokenStream>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        let idx = state.corpus_mut().add(Testcase::new(input.clone())).unwrap();
+        let mut mutator = TokenMutationStatsRecorder::new(PacketContentMutator::new(TokenStreamMutator::new(16)));
+        
+        for executions in 1..=10 {
+            *state.executions_mut() = executions;
+            let mut child = input.clone();
+            
+            if mutator.mutate(&mut state, &mut child).unwrap() == MutationResult::Mutated {
+                mutator.post_exec(&mut state, Some(idx)).unwrap();
+            }
+        }
+        
+        let stats = state.metadata::<TokenMutationStats>().unwrap();
+        let attempts: u64 = TokenMutation::ALL.iter().map(|x| stats.mutation(*x).attempts()).sum();
+        assert!(attempts > 0);
+        assert!(TokenMutation::ALL.iter().all(|x| stats.mutation(*x).successes() == stats.mutation(*x).attempts()));
+        assert!(stats.pending_mutations.is_empty());
+    }
+    
+    #[test]
+    fn weighted_choice() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let mut counts = [0; 3];
+        
+        for _ in 0..10000 {
+            counts[choose_weighted(&mut rand, &[1.0, 0.0, 3.0])] += 1;
         }
         
-        if mutated {
-            Ok(MutationResult::Mutated)
-        } else {
-            Ok(MutationResult::Skipped)
+        assert_eq!(counts[1], 0);
+        assert!(counts[2] > 2 * counts[0]);
+    }
+    
+    #[test]
+    fn config() {
+        let mutator = TokenStreamMutator::builder()
+            .max_tokens(64)
+            .disable(TokenMutation::RepeatChar)
+            .weight(TokenMutation::Split, 4.0)
+            .max_repeat_token(16)
+            .stacks(&[1, 2])
+            .build()
+            .unwrap();
+        let config = mutator.config();
+        assert_eq!(config.weight(TokenMutation::RepeatChar), 0.0);
+        assert_eq!(config.weight(TokenMutation::Split), 4.0);
+        assert_eq!(config.weight(TokenMutation::Flip), 1.0);
+        
+        let json = serde_json::to_string(config).unwrap();
+        assert!(json.contains("\"repeat_char\":0.0"));
+        assert_eq!(&serde_json::from_str::<TokenMutationConfig>(&json).unwrap(), config);
+        
+        assert!(TokenStreamMutator::builder().stacks(&[]).build().is_err());
+        assert!(TokenStreamMutator::builder().weight(TokenMutation::Flip, -1.0).build().is_err());
+        
+        let mut builder = TokenStreamMutator::builder();
+        
+        for mutation in TokenMutation::ALL {
+            builder = builder.disable(mutation);
         }
+        
+        assert!(builder.build().is_err());
+    }
+    
+    #[test]
+    fn partial_config() {
+        let config: TokenMutationConfig = serde_json::from_str(r#"{
+            "max_tokens": 0,
+            "weights": {"repeat_char": 0.0},
+            "max_repeat_char": 16,
+            "max_repeat_token": 16,
+            "stacks": [1],
+            "adaptive": false
+        }"#).unwrap();
+        assert_eq!(config.weight(TokenMutation::RepeatChar), 0.0);
+        assert_eq!(config.weight(TokenMutation::Flip), 1.0);
+        
+        let mutator = TokenStreamMutator::builder().config(config).build().unwrap();
+        assert_eq!(mutator.config().max_tokens(), 0);
+        assert_eq!(TokenStreamMutator::new(0).config().max_tokens(), 0);
     }
 }
@@ -0,0 +1,150 @@
+use libafl_bolts::prelude::{Rand, StdRand};
+use libafl::prelude::{MutationResult, Error, HasRand, HasMetadata, HasCorpus, Corpus, UsesInput};
+use std::hash::Hash;
+use crate::{
+    components::{PacketMutator, Packet, DragonflyInput, MutationRecord, log_mutation, random_donor},
+    tokens::{HasDerivationTree, HasTokenStream, Grammar},
+};
+use serde::{Serialize, Deserialize};
+
+/// Mutates packets on the level of their [`DerivationTree`](crate::tokens::DerivationTree)
+/// with the [`Grammar`] in the metadata of the state:
+/// - Replaces a subtree with a new derivation of the same rule
+/// - Replaces a subtree with a subtree of the same rule from another corpus entry
+/// - Repeats recursive rules
+pub struct GrammarTreeMutator {
+    max_tokens: usize,
+    rand: StdRand,
+}
+
+impl GrammarTreeMutator {
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            rand: StdRand::with_seed(0),
+        }
+    }
+}
+
+impl<P, S> PacketMutator<P, S> for GrammarTreeMutator
+where
+    P: Packet + HasDerivationTree + HasTokenStream + std::fmt::Debug + Clone + Hash + Serialize + for<'a> Deserialize<'a>,
+    S: HasRand + HasMetadata + HasCorpus,
+    S: UsesInput<Input = DragonflyInput<P>>,
+{
+    fn mutate_packet(&mut self, state: &mut S, packet: &mut P) -> Result<MutationResult, Error> {
+        let Some(tree) = packet.derivation_tree_mut() else {
+            return Ok(MutationResult::Skipped);
+        };
+        
+        if !tree.is_derived() {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        self.rand.set_seed(state.rand_mut().next());
+        
+        if !state.has_metadata::<Grammar>() {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let original = tree.clone();
+        let operation = self.rand.below(3) as usize;
+        
+        let mutated = match operation {
+            0 => tree.replace_subtree(&mut self.rand, state.metadata::<Grammar>()?),
+            1 => {
+                let Some(idx) = random_donor(state, self.rand.next())? else {
+                    return Ok(MutationResult::Skipped);
+                };
+                
+                let mut other_testcase = state.corpus().get(idx)?.borrow_mut();
+                let other_testcase = other_testcase.load_input(state.corpus())?;
+                let others: Vec<_> = other_testcase.packets().iter().filter_map(|x| x.derivation_tree()).filter(|x| x.is_derived()).collect();
+                
+                if others.is_empty() {
+                    return Ok(MutationResult::Skipped);
+                }
+                
+                let other = others[self.rand.below(others.len() as u64) as usize];
+                tree.splice(&mut self.rand, other)
+            },
+            2 => tree.expand_recursion(&mut self.rand),
+            _ => unreachable!(),
+        };
+        
+        if !mutated {
+            return Ok(MutationResult::Skipped);
+        }
+        
+        if tree.token_stream().len() > self.max_tokens {
+            *tree = original;
+            return Ok(MutationResult::Skipped);
+        }
+        
+        let operation = ["replace_subtree", "splice", "expand_recursion"][operation];
+        log_mutation(state, MutationRecord::new("GrammarTreeMutator").step(operation));
+        
+        Ok(MutationResult::Mutated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl::prelude::{
+        ConstFeedback,
+        InMemoryCorpus,
+        StdState,
+        Testcase,
+    };
+    use crate::tokens::DerivationTree;
+    
+    const GRAMMAR: &str = r#"
+        <command> ::= "LIST " <list> "\r\n" | "CWD " <path> "\r\n"
+        <list> ::= <item> | <item> "," <list>
+        <item> ::= <number> | <text>
+    "#;
+    
+    #[test]
+    fn mutate_trees() {
+        let grammar = GRAMMAR.parse::<Grammar>().unwrap();
+        let mut rand = StdRand::with_seed(0);
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<DragonflyInput<DerivationTree>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        
+        for _ in 0..4 {
+            let input = DragonflyInput::new(vec![grammar.derive(&mut rand)]);
+            state.corpus_mut().add(Testcase::new(input)).unwrap();
+        }
+        
+        state.add_metadata(grammar.clone());
+        
+        let mut mutator = GrammarTreeMutator::new(64);
+        let mut mutations = 0;
+        
+        for _ in 0..1000 {
+            let mut packet = grammar.derive(&mut rand);
+            
+            if mutator.mutate_packet(&mut state, &mut packet).unwrap() == MutationResult::Mutated {
+                assert!(packet.is_derived());
+                assert!(packet.token_stream().len() <= 64);
+                mutations += 1;
+            }
+        }
+        
+        assert!(mutations > 0);
+        
+        /* Trees whose token stream has been modified are left alone */
+        let mut packet = grammar.derive(&mut rand);
+        packet.token_stream_mut().tokens_mut().pop();
+        
+        for _ in 0..100 {
+            assert_eq!(mutator.mutate_packet(&mut state, &mut packet).unwrap(), MutationResult::Skipped);
+        }
+    }
+}
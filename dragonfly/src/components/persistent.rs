@@ -0,0 +1,233 @@
+use libafl_bolts::prelude::shmem::ShMemProvider;
+use libafl::prelude::{
+    Error,
+    EventFirer,
+    Executor,
+    ExitKind,
+    HasObservers,
+    ObserversTuple,
+    UsesInput,
+    UsesObservers,
+    UsesState,
+    State,
+    HasExecutions,
+};
+use nix::sys::signal::{
+    kill,
+    Signal,
+};
+use crate::components::{
+    executor::report_truncations,
+    DragonflyForkserverExecutor,
+    DragonflyInput,
+    Packet,
+};
+
+/// Computes a fingerprint of the coverage of an execution, e.g. the hash of the edge map
+pub type CoverageFingerprint<OT> = fn(&OT) -> u64;
+
+#[derive(Debug)]
+struct Canary<OT, P>
+where
+    P: Packet,
+{
+    input: DragonflyInput<P>,
+    interval: usize,
+    fingerprint: CoverageFingerprint<OT>,
+    reference: Option<u64>,
+}
+
+/// Executes up to N inputs in the same process.
+/// Whenever all connections of the target have been closed, libdragonfly stops the target
+/// until the next input has been placed into the packet channel.
+/// This only works for targets that handle more than one connection in the same process.
+#[derive(Debug)]
+pub struct DragonflyPersistentExecutor<OT, S, SP, P>
+where
+    OT: ObserversTuple<S>,
+    S:  State + UsesInput<Input = DragonflyInput<P>>,
+    SP: ShMemProvider,
+    P: Packet,
+{
+    inner: DragonflyForkserverExecutor<OT, S, SP, P>,
+    max_iterations: usize,
+    iterations: usize,
+    generation: u64,
+    canary: Option<Canary<OT, P>>,
+    leaks: usize,
+}
+
+impl<OT, S, SP, P> DragonflyPersistentExecutor<OT, S, SP, P>
+where
+    OT: ObserversTuple<S>,
+    S:  State + UsesInput<Input = DragonflyInput<P>>,
+    SP: ShMemProvider,
+    P: Packet,
+{
+    pub(crate) fn new(inner: DragonflyForkserverExecutor<OT, S, SP, P>, max_iterations: usize) -> Self {
+        Self {
+            inner,
+            max_iterations,
+            iterations: 0,
+            generation: 0,
+            canary: None,
+            leaks: 0,
+        }
+    }
+    
+    /// Re-run `input` every `interval` iterations and restart the target if its coverage
+    /// differs from the coverage in a fresh process.
+    pub fn with_canary(mut self, input: DragonflyInput<P>, interval: usize, fingerprint: CoverageFingerprint<OT>) -> Self {
+        assert!(interval > 0);
+        
+        self.canary = Some(Canary {
+            input,
+            interval,
+            fingerprint,
+            reference: None,
+        });
+        self
+    }
+    
+    /// How often the canary detected leaked state
+    pub fn leaks(&self) -> usize {
+        self.leaks
+    }
+    
+    fn restart(&mut self) {
+        if self.iterations > 0 {
+            let forkserver = self.inner.forkserver_mut();
+            let _ = kill(forkserver.child_pid(), Signal::SIGKILL);
+            forkserver.reset_child_pid();
+            
+            /* Tells the forkserver to reap the child and fork a new one */
+            forkserver.set_last_run_timed_out(true);
+        }
+        
+        self.iterations = 0;
+    }
+    
+    fn run_iteration(&mut self, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
+        self.generation += 1;
+        let result = input.serialize_dragonfly_format_persistent(self.inner.packet_channel_mut(), self.generation);
+        self.inner.record_serialization(&result);
+        
+        self.inner.clear_stderr();
+        let exit_kind = self.inner.run_forkserver()?;
+        self.inner.observe_stderr();
+        
+        /* The target might hold open files of the old sandbox */
+        let sandbox_reset = self.inner.update_sandbox(&exit_kind)?;
+        
+        if exit_kind == ExitKind::Ok && libc::WIFSTOPPED(self.inner.forkserver().status()) {
+            self.iterations += 1;
+            
+            if sandbox_reset || self.iterations >= self.max_iterations {
+                self.restart();
+            }
+        } else {
+            self.iterations = 0;
+        }
+        
+        Ok(exit_kind)
+    }
+    
+    fn canary_due(&self) -> bool {
+        match &self.canary {
+            Some(canary) => canary.reference.is_none() || (self.iterations > 0 && self.iterations.is_multiple_of(canary.interval)),
+            None => false,
+        }
+    }
+    
+    fn run_canary(&mut self, state: &mut S) -> Result<(), Error> {
+        let mut canary = self.canary.take().unwrap();
+        
+        /* The reference coverage must come from a fresh process */
+        if canary.reference.is_none() {
+            self.restart();
+        }
+        
+        self.inner.observers_mut().pre_exec_all(state, &canary.input)?;
+        let exit_kind = self.run_iteration(&canary.input)?;
+        self.inner.observers_mut().post_exec_all(state, &canary.input, &exit_kind)?;
+        
+        let fingerprint = (canary.fingerprint)(self.inner.observers());
+        
+        match canary.reference {
+            None => canary.reference = Some(fingerprint),
+            Some(reference) if reference != fingerprint => {
+                self.leaks += 1;
+                self.restart();
+            },
+            _ => {},
+        }
+        
+        self.canary = Some(canary);
+        Ok(())
+    }
+}
+
+impl<OT, S, SP, P> UsesState for DragonflyPersistentExecutor<OT, S, SP, P>
+where
+    OT: ObserversTuple<S>,
+    S:  State + UsesInput<Input = DragonflyInput<P>>,
+    SP: ShMemProvider,
+    P: Packet,
+{
+    type State = S;
+}
+
+impl<OT, S, SP, P> UsesObservers for DragonflyPersistentExecutor<OT, S, SP, P>
+where
+    OT: ObserversTuple<S>,
+    S:  State + UsesInput<Input = DragonflyInput<P>>,
+    SP: ShMemProvider,
+    P: Packet,
+{
+    type Observers = OT;
+}
+
+impl<OT, S, SP, P> HasObservers for DragonflyPersistentExecutor<OT, S, SP, P>
+where
+    OT: ObserversTuple<S>,
+    S:  State + UsesInput<Input = DragonflyInput<P>>,
+    SP: ShMemProvider,
+    P: Packet,
+{
+    fn observers(&self) -> &OT {
+        self.inner.observers()
+    }
+    
+    fn observers_mut(&mut self) -> &mut OT {
+        self.inner.observers_mut()
+    }
+}
+
+impl<OT, S, SP, P, EM, Z> Executor<EM, Z> for DragonflyPersistentExecutor<OT, S, SP, P>
+where
+    OT: ObserversTuple<S>,
+    S:  State + UsesInput<Input = DragonflyInput<P>> + HasExecutions,
+    SP: ShMemProvider,
+    P: Packet,
+    EM: EventFirer<State = S>,
+    Z: UsesState<State = S>,
+{
+    fn run_target(&mut self, _fuzzer: &mut Z, state: &mut S, mgr: &mut EM, input: &DragonflyInput<P>) -> Result<ExitKind, Error> {
+        *state.executions_mut() += 1;
+        let truncations = self.inner.truncations();
+        
+        /* Check whether previous inputs leaked state into the current process */
+        if self.canary_due() {
+            self.run_canary(state)?;
+            self.inner.observers_mut().pre_exec_all(state, input)?;
+        }
+        
+        let exit_kind = self.run_iteration(input)?;
+        
+        if self.inner.truncations() != truncations {
+            report_truncations(state, mgr, self.inner.truncations())?;
+        }
+        
+        Ok(exit_kind)
+    }
+}
@@ -0,0 +1,350 @@
+use libafl::prelude::{
+    Error,
+    ExitKind,
+    InMemoryCorpus,
+    Input,
+    ObserversTuple,
+    State,
+    StdState,
+    UsesInput,
+};
+use libafl_bolts::prelude::{
+    StdRand,
+    UnixShMemProvider,
+    ShMemProvider,
+    ShMem,
+    AsSlice,
+    AsMutSlice,
+    tuple_list,
+};
+use nix::sys::signal::Signal;
+use serde::{Serialize, Deserialize};
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use crate::components::{
+    DragonflyInput, DragonflyForkserverExecutor, DragonflyDebugExecutor,
+    DragonflyForkserverExecutorBuilder, FilesystemSandbox, ResetPolicy,
+    HasRawBytes, Packet, SANDBOX_ENV_VAR,
+    ResponseObserver, RESPONSE_CHANNEL_SIZE, RESPONSE_CHANNEL_ENV_VAR,
+    ReplayTrace, TRACE_CHANNEL_SIZE, TRACE_CHANNEL_ENV_VAR,
+    text::TEXT_HEADER,
+    trace::{monotonic_now, reset_trace_channel, parse_trace_channel},
+};
+
+/// The state of executors that run inputs outside of a fuzzing campaign
+pub type ReplayState<P> = StdState<DragonflyInput<P>, InMemoryCorpus<DragonflyInput<P>>, StdRand, InMemoryCorpus<DragonflyInput<P>>>;
+
+/// Everything a target harness needs to know in order to launch the target
+/// for replaying or debugging an input.
+#[derive(Clone, Debug)]
+pub struct TargetCommand {
+    program: String,
+    debug_program: Option<String>,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    sandbox: Option<PathBuf>,
+    reset_policies: Vec<ResetPolicy>,
+    is_deferred_forkserver: bool,
+    timeout: Duration,
+    gdb: String,
+}
+
+impl TargetCommand {
+    pub fn new<S: Into<String>>(program: S) -> Self {
+        Self {
+            program: program.into(),
+            debug_program: None,
+            args: Vec::new(),
+            envs: Vec::new(),
+            sandbox: None,
+            reset_policies: Vec::new(),
+            is_deferred_forkserver: false,
+            timeout: Duration::from_secs(999999),
+            gdb: "/usr/bin/gdb".to_string(),
+        }
+    }
+    
+    /// A different build of the target to run under gdb, e.g. one without instrumentation
+    pub fn debug_program<S: Into<String>>(mut self, program: S) -> Self {
+        self.debug_program = Some(program.into());
+        self
+    }
+    
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+    
+    pub fn args<IT, S>(mut self, args: IT) -> Self
+    where
+        IT: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for arg in args {
+            self.args.push(arg.into());
+        }
+        self
+    }
+    
+    /// Environment variables of the target, e.g. `LD_PRELOAD`. They are not applied to gdb itself.
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+    
+    /// Template directory of a [`FilesystemSandbox`].
+    /// Replays and gdb sessions operate on a copy such that the template stays pristine.
+    pub fn sandbox<Q: AsRef<Path>>(mut self, template: Q) -> Self {
+        self.sandbox = Some(template.as_ref().to_path_buf());
+        self
+    }
+    
+    /// Adds a reset policy to the sandbox of executors created by [`TargetCommand::executor`]
+    pub fn reset_policy(mut self, policy: ResetPolicy) -> Self {
+        self.reset_policies.push(policy);
+        self
+    }
+    
+    pub fn is_deferred_forkserver(mut self, is_deferred: bool) -> Self {
+        self.is_deferred_forkserver = is_deferred;
+        self
+    }
+    
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    
+    /// Path of the gdb binary, defaults to `/usr/bin/gdb`
+    pub fn gdb<S: Into<String>>(mut self, gdb: S) -> Self {
+        self.gdb = gdb.into();
+        self
+    }
+    
+    fn new_sandbox(&self) -> Result<Option<FilesystemSandbox>, Error> {
+        let Some(template) = &self.sandbox else {
+            return Ok(None);
+        };
+        
+        let mut sandbox = FilesystemSandbox::new(template)?;
+        
+        for policy in &self.reset_policies {
+            sandbox = sandbox.reset_policy(*policy);
+        }
+        
+        Ok(Some(sandbox))
+    }
+    
+    /// Returns a builder for a forkserver executor that launches the target.
+    /// Observers and further options can be set before building it.
+    pub fn executor<'a, OT, S, SP, P>(&self, shmem_provider: &'a mut SP) -> Result<DragonflyForkserverExecutorBuilder<'a, OT, S, SP, P>, Error>
+    where
+        OT: ObserversTuple<S>,
+        S: State + UsesInput<Input = DragonflyInput<P>>,
+        SP: ShMemProvider,
+        P: Packet,
+    {
+        let mut builder = DragonflyForkserverExecutor::builder()
+            .shmem_provider(shmem_provider)
+            .timeout(self.timeout)
+            .signal(Signal::SIGKILL)
+            .is_deferred_forkserver(self.is_deferred_forkserver)
+            .envs(self.envs.iter().map(|(k, v)| (k, v)))
+            .program(&self.program)
+            .args(&self.args);
+        
+        if let Some(sandbox) = self.new_sandbox()? {
+            builder = builder.sandbox(sandbox);
+        }
+        
+        Ok(builder)
+    }
+}
+
+/// Reads an input that is either in the binary format or in the text format of [`DragonflyInput::to_text`]
+pub fn load_input<P, Q>(path: Q) -> Result<DragonflyInput<P>, Error>
+where
+    P: HasRawBytes + Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+    Q: AsRef<Path>,
+{
+    let content = std::fs::read(path.as_ref())?;
+    
+    if content.starts_with(TEXT_HEADER.as_bytes()) {
+        DragonflyInput::from_text_file(path)
+    } else {
+        DragonflyInput::from_file(path)
+    }
+}
+
+fn build_executor<P>(target: &TargetCommand, shmem_provider: &mut UnixShMemProvider) -> Result<DragonflyForkserverExecutor<(), ReplayState<P>, UnixShMemProvider, P>, Error>
+where
+    P: HasRawBytes + Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+{
+    target.executor(shmem_provider)?
+        .observers(tuple_list!())
+        .debug_child(true)
+        .build()
+}
+
+/// Runs an input once in the target with its output visible on the terminal
+pub fn replay_input<P>(input: &DragonflyInput<P>, target: &TargetCommand) -> Result<ExitKind, Error>
+where
+    P: HasRawBytes + Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+{
+    let mut shmem_provider = UnixShMemProvider::new()?;
+    let mut executor = build_executor(target, &mut shmem_provider)?;
+    executor.run_input(input)
+}
+
+/// Like [`replay_input`] but records which packet was read when and from which fd
+/// together with the responses of the target
+pub fn trace_input<P>(input: &DragonflyInput<P>, target: &TargetCommand) -> Result<ReplayTrace, Error>
+where
+    P: HasRawBytes + Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+{
+    let mut shmem_provider = UnixShMemProvider::new()?;
+    let mut response_shmem = shmem_provider.new_shmem(RESPONSE_CHANNEL_SIZE)?;
+    response_shmem.write_to_env(RESPONSE_CHANNEL_ENV_VAR)?;
+    let mut trace_shmem = shmem_provider.new_shmem(TRACE_CHANNEL_SIZE)?;
+    trace_shmem.write_to_env(TRACE_CHANNEL_ENV_VAR)?;
+    
+    let mut executor = build_executor(target, &mut shmem_provider)?;
+    let mut responses = ResponseObserver::new("responses", response_shmem.as_mut_slice());
+    responses.reset_channel();
+    reset_trace_channel(trace_shmem.as_mut_slice());
+    
+    let start = monotonic_now();
+    let exit_kind = executor.run_input(input)?;
+    let end = monotonic_now();
+    
+    responses.parse_channel();
+    let events = parse_trace_channel(trace_shmem.as_slice());
+    Ok(ReplayTrace::new(input, exit_kind, &events, responses.responses(), start, end))
+}
+
+/// Replaces the current process with gdb that runs the target on the given input.
+/// Only returns if gdb could not be launched.
+/// The copy of the sandbox outlives the gdb session and is cleaned up by the next [`FilesystemSandbox`].
+pub fn debug_input<P>(input: &DragonflyInput<P>, target: &TargetCommand) -> Result<(), Error>
+where
+    P: HasRawBytes + Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+{
+    let mut shmem_provider = UnixShMemProvider::new()?;
+    let mut executor = DragonflyDebugExecutor::<ReplayState<P>, _, _>::new(&mut shmem_provider)?;
+    
+    executor.arg(target.gdb.as_str());
+    
+    for (key, value) in &target.envs {
+        executor.arg("-ex");
+        executor.arg(format!("set environment {}={}", key, value));
+    }
+    
+    let sandbox = target.new_sandbox()?;
+    
+    if let Some(sandbox) = &sandbox {
+        executor.env(SANDBOX_ENV_VAR, sandbox.path().to_string_lossy().as_bytes());
+    }
+    
+    executor.arg("--args");
+    executor.arg(target.debug_program.as_ref().unwrap_or(&target.program).as_str());
+    
+    for arg in &target.args {
+        executor.arg(arg.as_str());
+    }
+    
+    executor.run_input(input)?;
+    Ok(())
+}
+
+/// Subcommands that every target harness can embed into its own command line with `#[command(flatten)]`
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum ReplayCommand {
+    /// Print an input in the text format
+    Print {
+        file: String,
+    },
+    
+    /// Convert an input from the text format into the binary format
+    FromText {
+        file: String,
+        
+        output: String,
+    },
+    
+    /// Run an input in the target
+    Replay {
+        /// Run the target under gdb
+        #[arg(long, conflicts_with = "trace")]
+        gdb: bool,
+        
+        /// Write a transcript of requests, responses and timings to this file
+        #[arg(long)]
+        trace: Option<String>,
+        
+        file: String,
+    },
+}
+
+impl ReplayCommand {
+    pub fn execute<P>(&self, target: &TargetCommand) -> Result<(), Error>
+    where
+        P: HasRawBytes + Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+    {
+        match self {
+            ReplayCommand::Print { file } => {
+                let input = load_input::<P, _>(file)?;
+                print!("{}", input.to_text());
+            },
+            ReplayCommand::FromText { file, output } => {
+                let input = DragonflyInput::<P>::from_text_file(file)?;
+                input.to_file(output)?;
+            },
+            ReplayCommand::Replay { gdb: true, file, .. } => {
+                let input = load_input::<P, _>(file)?;
+                debug_input(&input, target)?;
+            },
+            ReplayCommand::Replay { trace: Some(output), file, .. } => {
+                let input = load_input::<P, _>(file)?;
+                let trace = trace_input(&input, target)?;
+                std::fs::write(output, trace.transcript())?;
+                println!("{:?}", trace.exit_kind());
+            },
+            ReplayCommand::Replay { gdb: false, trace: None, file } => {
+                let input = load_input::<P, _>(file)?;
+                let exit_kind = replay_input(&input, target)?;
+                println!("{:?}", exit_kind);
+            },
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::RawPacket;
+    
+    #[test]
+    fn load_formats() {
+        let input = DragonflyInput::new(vec![
+            RawPacket::new(0, b"USER ftp\r\n".to_vec(), true),
+        ]);
+        let dir = std::env::temp_dir().join(format!("dragonfly-replay-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        
+        input.to_file(dir.join("binary")).unwrap();
+        input.to_text_file(dir.join("text")).unwrap();
+        
+        for name in ["binary", "text"] {
+            let loaded = load_input::<RawPacket, _>(dir.join(name)).unwrap();
+            assert_eq!(loaded.to_text(), input.to_text());
+        }
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
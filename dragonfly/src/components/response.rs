@@ -0,0 +1,358 @@
+use libafl_bolts::{
+    impl_serdeany,
+    prelude::{
+        Named,
+        OwnedMutSlice,
+        AsSlice,
+        AsMutSlice,
+    },
+};
+use libafl::prelude::{
+    Error,
+    EventFirer,
+    ExitKind,
+    Feedback,
+    HasNamedMetadata,
+    Observer,
+    ObserversTuple,
+    State,
+    UsesInput,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::collections::HashSet;
+use crate::components::{
+    input::align8,
+    StateObserver,
+};
+
+pub const RESPONSE_CHANNEL_SIZE: usize = 1024 * 1024;
+pub const RESPONSE_CHANNEL_ENV_VAR: &str = "__LIBDRAGONFLY_RESPONSE_CHANNEL";
+
+const CHANNEL_HEADER_SIZE: usize = 16;
+const RESPONSE_HEADER_SIZE: usize = 16;
+const NO_PACKET: u32 = u32::MAX;
+
+/// Everything the target wrote to a connection while processing a single data packet.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Response {
+    conn: usize,
+    packet: Option<usize>,
+    data: Vec<u8>,
+}
+
+impl Response {
+    /// The connection the response was written to
+    pub fn connection(&self) -> usize {
+        self.conn
+    }
+    
+    /// Index of the data packet that was read last before this response was written.
+    /// `None` if the target wrote something before reading any data, e.g. a banner.
+    /// Use [`DragonflyInput::data_packets()`](crate::components::DragonflyInput::data_packets) to map this to a packet of the input.
+    pub fn packet(&self) -> Option<usize> {
+        self.packet
+    }
+    
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Collects the responses of the target from the response channel of libdragonfly.
+/// The shared memory must be at least [`RESPONSE_CHANNEL_SIZE`] bytes big and its id
+/// must be passed to the target via [`RESPONSE_CHANNEL_ENV_VAR`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseObserver<'a> {
+    name: String,
+    channel: OwnedMutSlice<'a, u8>,
+    responses: Vec<Response>,
+}
+
+impl<'a> ResponseObserver<'a> {
+    pub fn new<S: Into<String>>(name: S, channel: &'a mut [u8]) -> Self {
+        assert!(channel.len() > CHANNEL_HEADER_SIZE);
+        
+        Self {
+            name: name.into(),
+            channel: OwnedMutSlice::from(channel),
+            responses: Vec::new(),
+        }
+    }
+    
+    pub fn responses(&self) -> &[Response] {
+        &self.responses
+    }
+    
+    #[cfg(test)]
+    pub(crate) fn channel_mut(&mut self) -> &mut [u8] {
+        self.channel.as_mut_slice()
+    }
+    
+    pub(crate) fn reset_channel(&mut self) {
+        let channel = self.channel.as_mut_slice();
+        let capacity = channel.len() as u64;
+        channel[0..8].copy_from_slice(&capacity.to_ne_bytes());
+        channel[8..16].copy_from_slice(&0u64.to_ne_bytes());
+    }
+    
+    pub(crate) fn parse_channel(&mut self) {
+        let channel = self.channel.as_slice();
+        let used = u64::from_ne_bytes(channel[8..16].try_into().unwrap()) as usize;
+        let end = std::cmp::min(CHANNEL_HEADER_SIZE + used, channel.len());
+        let mut cursor = CHANNEL_HEADER_SIZE;
+        
+        self.responses.clear();
+        
+        while cursor + RESPONSE_HEADER_SIZE <= end {
+            let conn = u32::from_ne_bytes(channel[cursor..cursor + 4].try_into().unwrap()) as usize;
+            let packet = u32::from_ne_bytes(channel[cursor + 4..cursor + 8].try_into().unwrap());
+            let size = u64::from_ne_bytes(channel[cursor + 8..cursor + 16].try_into().unwrap()) as usize;
+            let packet = if packet == NO_PACKET {
+                None
+            } else {
+                Some(packet as usize)
+            };
+            
+            cursor += RESPONSE_HEADER_SIZE;
+            let data = &channel[cursor..std::cmp::min(cursor.saturating_add(size), end)];
+            cursor = cursor.saturating_add(align8(size));
+            
+            /* Merge writes that belong to the same packet */
+            match self.responses.last_mut() {
+                Some(last) if last.conn == conn && last.packet == packet => {
+                    last.data.extend_from_slice(data);
+                },
+                _ => {
+                    self.responses.push(Response {
+                        conn,
+                        packet,
+                        data: data.to_vec(),
+                    });
+                },
+            }
+        }
+    }
+}
+
+impl<'a> Named for ResponseObserver<'a> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<'a, S> Observer<S> for ResponseObserver<'a>
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.responses.clear();
+        self.reset_channel();
+        Ok(())
+    }
+    
+    fn post_exec(&mut self, _state: &mut S, _input: &S::Input, _exit_kind: &ExitKind) -> Result<(), Error> {
+        self.parse_channel();
+        Ok(())
+    }
+}
+
+/// Extracts the parts of a response that identify its kind, e.g. status codes, in order.
+/// A response holds everything that was written for a single packet, so it can contain multiple replies.
+pub type ResponseCodeExtractor = fn(&[u8]) -> Vec<&[u8]>;
+
+/// Extracts the three digit reply code at the start of a single reply of text protocols like FTP, SMTP or HTTP.
+pub fn reply_code(data: &[u8]) -> Option<&[u8]> {
+    /* HTTP puts its version in front of the status code */
+    let data = if data.starts_with(b"HTTP/") {
+        let start = data.iter().position(|x| *x == b' ')? + 1;
+        &data[start..]
+    } else {
+        data
+    };
+    
+    if data.len() >= 3 && data[0..3].iter().all(u8::is_ascii_digit) && (data.len() == 3 || !data[3].is_ascii_digit()) {
+        Some(&data[0..3])
+    } else {
+        None
+    }
+}
+
+/// Extracts the reply codes at the start of every line, e.g. both codes of "150 ...\r\n226 ...\r\n".
+/// The lines of a multiline reply like "530-...\r\n530 ...\r\n" yield the code only once.
+pub fn reply_codes(data: &[u8]) -> Vec<&[u8]> {
+    let mut codes: Vec<&[u8]> = Vec::new();
+    
+    for line in data.split(|x| *x == b'\n') {
+        if let Some(code) = reply_code(line) {
+            if codes.last() != Some(&code) {
+                codes.push(code);
+            }
+        }
+    }
+    
+    codes
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ResponseCodeMetadata {
+    codes: HashSet<Vec<u8>>,
+}
+
+impl ResponseCodeMetadata {
+    pub fn codes(&self) -> &HashSet<Vec<u8>> {
+        &self.codes
+    }
+}
+
+impl_serdeany!(ResponseCodeMetadata);
+
+/// Considers an input interesting if the target answered with a response code
+/// that has not been seen before.
+#[derive(Debug)]
+pub struct ResponseCodeFeedback {
+    observer_name: String,
+    extractor: ResponseCodeExtractor,
+}
+
+impl ResponseCodeFeedback {
+    pub fn new(observer: &ResponseObserver) -> Self {
+        Self::with_extractor(observer, reply_codes)
+    }
+    
+    pub fn with_extractor(observer: &ResponseObserver, extractor: ResponseCodeExtractor) -> Self {
+        Self {
+            observer_name: observer.name().to_string(),
+            extractor,
+        }
+    }
+    
+    /// Reads the responses from a [`StateObserver`] instead, such that this feedback
+    /// can be used together with a [`StateFeedback`](crate::components::StateFeedback).
+    pub fn with_state_observer(observer: &StateObserver) -> Self {
+        Self {
+            observer_name: observer.name().to_string(),
+            extractor: reply_codes,
+        }
+    }
+    
+    fn responses<'o, S, OT>(&self, observers: &'o OT) -> Result<&'o [Response], Error>
+    where
+        S: UsesInput,
+        OT: ObserversTuple<S>,
+    {
+        if let Some(observer) = observers.match_name::<ResponseObserver>(&self.observer_name) {
+            Ok(observer.responses())
+        } else if let Some(observer) = observers.match_name::<StateObserver>(&self.observer_name) {
+            Ok(observer.responses())
+        } else {
+            Err(Error::illegal_argument(format!("ResponseCodeFeedback: observer {} not found", self.observer_name)))
+        }
+    }
+}
+
+impl Named for ResponseCodeFeedback {
+    fn name(&self) -> &str {
+        "ResponseCodeFeedback"
+    }
+}
+
+impl<S> Feedback<S> for ResponseCodeFeedback
+where
+    S: State + HasNamedMetadata,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        state.add_named_metadata(self.name(), ResponseCodeMetadata::default());
+        Ok(())
+    }
+    
+    fn is_interesting<EM, OT>(&mut self, state: &mut S, _manager: &mut EM, _input: &S::Input, observers: &OT, _exit_kind: &ExitKind) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        let responses = self.responses::<S, OT>(observers)?;
+        let metadata = state.named_metadata_mut::<ResponseCodeMetadata>(self.name())?;
+        let mut interesting = false;
+        
+        for response in responses {
+            for code in (self.extractor)(response.data()) {
+                interesting |= metadata.codes.insert(code.to_vec());
+            }
+        }
+        
+        Ok(interesting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn write_response(channel: &mut [u8], conn: u32, packet: u32, data: &[u8]) {
+        let used = u64::from_ne_bytes(channel[8..16].try_into().unwrap()) as usize;
+        let cursor = CHANNEL_HEADER_SIZE + used;
+        channel[cursor..cursor + 4].copy_from_slice(&conn.to_ne_bytes());
+        channel[cursor + 4..cursor + 8].copy_from_slice(&packet.to_ne_bytes());
+        channel[cursor + 8..cursor + 16].copy_from_slice(&(data.len() as u64).to_ne_bytes());
+        channel[cursor + 16..cursor + 16 + data.len()].copy_from_slice(data);
+        let used = (used + RESPONSE_HEADER_SIZE + align8(data.len())) as u64;
+        channel[8..16].copy_from_slice(&used.to_ne_bytes());
+    }
+    
+    #[test]
+    fn parse_responses() {
+        let mut buffer = vec![0u8; 256];
+        let mut observer = ResponseObserver::new("responses", &mut buffer);
+        observer.reset_channel();
+        
+        let channel = observer.channel.as_mut_slice();
+        write_response(channel, 0, NO_PACKET, b"220 Welcome\r\n");
+        write_response(channel, 0, 0, b"331 Password");
+        write_response(channel, 0, 0, b" required\r\n");
+        write_response(channel, 1, 0, b"data");
+        write_response(channel, 0, 1, b"230 Logged in\r\n");
+        observer.parse_channel();
+        
+        let responses = observer.responses();
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0].packet(), None);
+        assert_eq!(responses[1].data(), b"331 Password required\r\n");
+        assert_eq!(responses[2].connection(), 1);
+        assert_eq!(responses[3].packet(), Some(1));
+    }
+    
+    #[test]
+    fn truncated_channel() {
+        let mut buffer = vec![0u8; 48];
+        let mut observer = ResponseObserver::new("responses", &mut buffer);
+        observer.reset_channel();
+        
+        let channel = observer.channel.as_mut_slice();
+        write_response(channel, 0, 0, b"0123456789abcdef");
+        observer.parse_channel();
+        
+        assert_eq!(observer.responses()[0].data(), b"0123456789abcdef");
+        
+        /* Pretend the target claims more than fits */
+        observer.channel.as_mut_slice()[8..16].copy_from_slice(&1000u64.to_ne_bytes());
+        observer.parse_channel();
+        
+        assert_eq!(observer.responses().len(), 1);
+    }
+    
+    #[test]
+    fn extract_reply_codes() {
+        assert_eq!(reply_code(b"220 ProFTPD Server ready\r\n"), Some(&b"220"[..]));
+        assert_eq!(reply_code(b"530-Login incorrect\r\n"), Some(&b"530"[..]));
+        assert_eq!(reply_code(b"HTTP/1.1 404 Not Found\r\n"), Some(&b"404"[..]));
+        assert_eq!(reply_code(b"2200 nope"), None);
+        assert_eq!(reply_code(b"hello"), None);
+        
+        assert_eq!(reply_codes(b"150 Opening data connection\r\n226 Transfer complete\r\n"), vec![&b"150"[..], &b"226"[..]]);
+        assert_eq!(reply_codes(b"530-Login incorrect\r\n Try again\r\n530 Login incorrect\r\n"), vec![&b"530"[..]]);
+        assert!(reply_codes(b"hello\r\n").is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use libafl::prelude::{
+    Error,
+    ExitKind,
+};
+use ahash::AHasher;
+use std::{
+    fs,
+    hash::Hasher,
+    io::Read,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Environment variable that holds the path of the private directory
+pub const SANDBOX_ENV_VAR: &str = "DRAGONFLY_SANDBOX";
+
+/// When to restore the private directory from the template
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetPolicy {
+    /// Every N executions
+    Every(usize),
+    /// After each execution that crashed or timed out
+    AfterCrash,
+    /// Whenever the contents of the private directory differ from the template.
+    /// This hashes the whole directory after every execution so it is only suitable for small templates.
+    OnDivergence,
+}
+
+/// Gives each fuzzer instance its own copy of a template directory such that
+/// the disk state of different instances does not interfere.
+#[derive(Debug)]
+pub struct FilesystemSandbox {
+    template: PathBuf,
+    dir: PathBuf,
+    policies: Vec<ResetPolicy>,
+    executions: usize,
+    reference: u64,
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::create_dir(to)?;
+    
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        
+        if file_type.is_dir() {
+            copy_recursively(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    
+    fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+    Ok(())
+}
+
+fn hash_recursively(dir: &Path, hasher: &mut AHasher) -> Result<(), Error> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|x| x.file_name());
+    
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        hasher.write(entry.file_name().as_encoded_bytes());
+        
+        if file_type.is_dir() {
+            hasher.write_u8(b'd');
+            hash_recursively(&entry.path(), hasher)?;
+        } else if file_type.is_symlink() {
+            hasher.write_u8(b'l');
+            hasher.write(fs::read_link(entry.path())?.as_os_str().as_encoded_bytes());
+        } else {
+            let mut content = Vec::new();
+            fs::File::open(entry.path())?.read_to_end(&mut content)?;
+            hasher.write_u8(b'f');
+            hasher.write_usize(content.len());
+            hasher.write(&content);
+        }
+    }
+    
+    Ok(())
+}
+
+fn hash_directory(dir: &Path) -> Result<u64, Error> {
+    let mut hasher = AHasher::default();
+    hash_recursively(dir, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+fn default_location() -> PathBuf {
+    /* Prefer a tmpfs */
+    let shm = PathBuf::from("/dev/shm");
+    
+    if shm.is_dir() {
+        shm
+    } else {
+        std::env::temp_dir()
+    }
+}
+
+const DIR_PREFIX: &str = "dragonfly-sandbox-";
+
+/// Removes the private directories of fuzzer processes that no longer exist.
+/// These are left behind if a process aborts, since then no destructor runs.
+fn remove_stale(location: &Path) {
+    let Ok(entries) = fs::read_dir(location) else {
+        return;
+    };
+    
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(|x| x.strip_prefix(DIR_PREFIX)).and_then(|x| x.split('-').next()) else {
+            continue;
+        };
+        let Ok(pid) = pid.parse::<i32>() else {
+            continue;
+        };
+        
+        if nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None) == Err(nix::errno::Errno::ESRCH) {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+impl FilesystemSandbox {
+    /// Copies `template` into a private directory on a tmpfs
+    pub fn new<P: AsRef<Path>>(template: P) -> Result<Self, Error> {
+        Self::with_location(template, default_location())
+    }
+    
+    /// Copies `template` into a private directory inside of `location`
+    pub fn with_location<P1: AsRef<Path>, P2: AsRef<Path>>(template: P1, location: P2) -> Result<Self, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        
+        let template = template.as_ref().to_owned();
+        
+        if !template.is_dir() {
+            return Err(Error::illegal_argument(format!("FilesystemSandbox: template {} is not a directory", template.display())));
+        }
+        
+        remove_stale(location.as_ref());
+        
+        let dir = location.as_ref().join(format!("{}{}-{}", DIR_PREFIX, std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        
+        let mut sandbox = Self {
+            template,
+            dir,
+            policies: Vec::new(),
+            executions: 0,
+            reference: 0,
+        };
+        sandbox.reset()?;
+        sandbox.reference = hash_directory(&sandbox.dir)?;
+        Ok(sandbox)
+    }
+    
+    /// Adds a reset policy. Multiple policies can be combined.
+    pub fn reset_policy(mut self, policy: ResetPolicy) -> Self {
+        assert!(policy != ResetPolicy::Every(0));
+        self.policies.push(policy);
+        self
+    }
+    
+    /// Path to the private copy of the template
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+    
+    /// Restores the private directory from the template
+    pub fn reset(&mut self) -> Result<(), Error> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        
+        copy_recursively(&self.template, &self.dir)
+    }
+    
+    /// Applies the reset policies after an execution. Returns whether the directory has been reset.
+    pub fn after_execution(&mut self, exit_kind: &ExitKind) -> Result<bool, Error> {
+        self.executions += 1;
+        let mut reset = false;
+        
+        for policy in &self.policies {
+            reset |= match policy {
+                ResetPolicy::Every(n) => self.executions.is_multiple_of(*n),
+                ResetPolicy::AfterCrash => matches!(exit_kind, ExitKind::Crash | ExitKind::Timeout),
+                ResetPolicy::OnDivergence => hash_directory(&self.dir)? != self.reference,
+            };
+            
+            if reset {
+                break;
+            }
+        }
+        
+        if reset {
+            self.reset()?;
+        }
+        
+        Ok(reset)
+    }
+}
+
+impl Drop for FilesystemSandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn template(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dragonfly-template-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("uploads")).unwrap();
+        fs::write(dir.join("welcome.msg"), b"hello").unwrap();
+        dir
+    }
+    
+    #[test]
+    fn reset_on_divergence() {
+        let template = template("divergence");
+        let mut sandbox = FilesystemSandbox::with_location(&template, std::env::temp_dir()).unwrap().reset_policy(ResetPolicy::OnDivergence);
+        let upload = sandbox.path().join("uploads").join("file");
+        
+        assert!(sandbox.path().join("welcome.msg").is_file());
+        assert!(!sandbox.after_execution(&ExitKind::Ok).unwrap());
+        
+        fs::write(&upload, b"data").unwrap();
+        assert!(sandbox.after_execution(&ExitKind::Ok).unwrap());
+        assert!(!upload.exists());
+        
+        let path = sandbox.path().to_owned();
+        drop(sandbox);
+        assert!(!path.exists());
+        fs::remove_dir_all(template).unwrap();
+    }
+    
+    #[test]
+    fn remove_stale_sandboxes() {
+        let location = std::env::temp_dir().join(format!("dragonfly-location-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&location);
+        
+        /* Leftovers of a process that does not exist anymore */
+        let stale = location.join(format!("{}{}-0", DIR_PREFIX, i32::MAX));
+        fs::create_dir_all(&stale).unwrap();
+        
+        let template = template("stale");
+        let sandbox = FilesystemSandbox::with_location(&template, &location).unwrap();
+        assert!(!stale.exists());
+        assert!(sandbox.path().is_dir());
+        
+        drop(sandbox);
+        fs::remove_dir_all(location).unwrap();
+        fs::remove_dir_all(template).unwrap();
+    }
+}
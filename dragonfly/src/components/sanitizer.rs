@@ -0,0 +1,465 @@
+use libafl_bolts::{
+    impl_serdeany,
+    prelude::Named,
+};
+use libafl::prelude::{
+    Error,
+    EventFirer,
+    ExitKind,
+    Feedback,
+    HasMetadata,
+    HasNamedMetadata,
+    Observer,
+    ObserversTuple,
+    State,
+    Testcase,
+    UsesInput,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    hash::Hasher,
+    path::{Path, PathBuf},
+};
+
+pub(crate) const STDERR_FD_ENV_VAR: &str = "__LIBDRAGONFLY_STDERR_FD";
+
+/// Only the start of the output is kept, sanitizer reports come first
+const MAX_STDERR_SIZE: usize = 1024 * 1024;
+
+/// Frames of the sanitizer runtime that say nothing about the bug
+const RUNTIME_FRAMES: [&str; 5] = ["__asan_", "__interceptor_", "__sanitizer", "__ubsan_", "__lsan_"];
+
+/// A file that the target uses as its stderr
+#[derive(Debug)]
+pub(crate) struct StderrChannel {
+    fd: i32,
+}
+
+impl StderrChannel {
+    pub(crate) fn new() -> Result<Self, Error> {
+        let fd = unsafe { libc::memfd_create(c"dragonfly-stderr".as_ptr(), 0) };
+        
+        if fd < 0 {
+            return Err(Error::unknown(format!("Could not create stderr file: {}", std::io::Error::last_os_error())));
+        }
+        
+        /* Such that all children write to the start of the file after it has been truncated */
+        if unsafe { libc::fcntl(fd, libc::F_SETFL, libc::O_APPEND) } != 0 {
+            return Err(Error::unknown(format!("Could not configure stderr file: {}", std::io::Error::last_os_error())));
+        }
+        
+        Ok(Self {
+            fd,
+        })
+    }
+    
+    /// Value of [`STDERR_FD_ENV_VAR`] for the target
+    pub(crate) fn env_value(&self) -> String {
+        format!("{}", self.fd)
+    }
+    
+    pub(crate) fn clear(&mut self) {
+        unsafe { libc::ftruncate(self.fd, 0) };
+    }
+    
+    pub(crate) fn read(&mut self) -> Vec<u8> {
+        let mut buffer = vec![0u8; MAX_STDERR_SIZE];
+        let mut len = 0;
+        
+        while len < buffer.len() {
+            let ret = unsafe { libc::pread(self.fd, buffer[len..].as_mut_ptr() as *mut libc::c_void, buffer.len() - len, len as libc::off_t) };
+            
+            if ret <= 0 {
+                break;
+            }
+            
+            len += ret as usize;
+        }
+        
+        buffer.truncate(len);
+        buffer
+    }
+}
+
+impl Drop for StderrChannel {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// 64-bit FNV-1a. Signatures are stored in the fuzzer state and compared across
+/// restarts and instances, so unlike ahash this must not be seeded randomly.
+struct SignatureHasher(u64);
+
+impl SignatureHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for SignatureHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Identifies a bug by the type of the sanitizer report and the topmost stack frames
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashSignature {
+    bug_type: String,
+    frames: Vec<String>,
+    hash: u64,
+}
+
+impl CrashSignature {
+    fn new(bug_type: String, frames: Vec<String>) -> Self {
+        let mut hasher = SignatureHasher::new();
+        hasher.write(bug_type.as_bytes());
+        
+        for frame in &frames {
+            hasher.write_u8(0);
+            hasher.write(frame.as_bytes());
+        }
+        
+        Self {
+            bug_type,
+            frames,
+            hash: hasher.finish(),
+        }
+    }
+    
+    /// E.g. `AddressSanitizer: heap-buffer-overflow`
+    pub fn bug_type(&self) -> &str {
+        &self.bug_type
+    }
+    
+    /// The functions of the topmost frames, or their locations if there were no symbols
+    pub fn frames(&self) -> &[String] {
+        &self.frames
+    }
+    
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl_serdeany!(CrashSignature);
+
+/// Extracts the function of a frame like `#0 0x4f5a3c in pr_str_strip /proftpd/src/str.c:123:5`
+/// or the module of a frame like `#1 0x7f0d3a (/lib/x86_64-linux-gnu/libc.so.6+0x29d90)`.
+fn parse_frame(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    
+    if !line.starts_with('#') {
+        return None;
+    }
+    
+    let mut words = line.split_whitespace().skip(2);
+    
+    match words.next()? {
+        "in" => words.next().map(|x| x.to_string()),
+        module => Some(module.trim_start_matches('(').split('+').next()?.to_string()),
+    }
+}
+
+/// Parses the first ASan, LSan or UBSan report in the output of the target
+pub fn parse_sanitizer_report(output: &[u8], depth: usize) -> Option<CrashSignature> {
+    let output = String::from_utf8_lossy(output);
+    let mut lines = output.lines();
+    
+    while let Some(line) = lines.next() {
+        /* ==1234==ERROR: AddressSanitizer: heap-buffer-overflow on address ... */
+        if let Some(report) = line.split_once("ERROR: ").map(|(_, x)| x).filter(|x| x.contains("Sanitizer: ")) {
+            let bug_type = report.split(" on ").next().unwrap().split(" (").next().unwrap().trim().to_string();
+            
+            /* Only the first stack trace belongs to the crash site */
+            let frames = lines
+                .by_ref()
+                .skip_while(|x| parse_frame(x).is_none())
+                .map_while(parse_frame)
+                .filter(|x| !RUNTIME_FRAMES.iter().any(|prefix| x.starts_with(prefix)))
+                .take(depth)
+                .collect();
+            return Some(CrashSignature::new(bug_type, frames));
+        }
+        
+        /* file.c:12:3: runtime error: signed integer overflow: ... */
+        if let Some((location, error)) = line.split_once(": runtime error: ") {
+            let bug_type = format!("UndefinedBehaviorSanitizer: {}", error.split(':').next().unwrap());
+            let mut frames: Vec<String> = lines.by_ref().map_while(parse_frame).take(depth).collect();
+            
+            if frames.is_empty() {
+                frames.push(location.to_string());
+            }
+            
+            return Some(CrashSignature::new(bug_type, frames));
+        }
+    }
+    
+    None
+}
+
+/// Parses the sanitizer report the target printed to stderr.
+/// Requires the executor to capture stderr.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SanitizerObserver {
+    name: String,
+    depth: usize,
+    signature: Option<CrashSignature>,
+}
+
+impl SanitizerObserver {
+    /// Signatures consist of the `depth` topmost frames of the stack trace
+    pub fn new<S: Into<String>>(name: S, depth: usize) -> Self {
+        Self {
+            name: name.into(),
+            depth,
+            signature: None,
+        }
+    }
+    
+    /// The signature of the report of the last execution, if there was one
+    pub fn signature(&self) -> Option<&CrashSignature> {
+        self.signature.as_ref()
+    }
+}
+
+impl Named for SanitizerObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Observer<S> for SanitizerObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.signature = None;
+        Ok(())
+    }
+    
+    fn observes_stderr(&self) -> bool {
+        true
+    }
+    
+    fn observe_stderr(&mut self, stderr: &[u8]) {
+        self.signature = parse_sanitizer_report(stderr, self.depth);
+    }
+}
+
+/// A unique crash and how often it was hit
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UniqueCrash {
+    signature: CrashSignature,
+    hits: usize,
+}
+
+impl UniqueCrash {
+    pub fn signature(&self) -> &CrashSignature {
+        &self.signature
+    }
+    
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
+
+/// All crash signatures seen so far
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UniqueCrashesMetadata {
+    crashes: Vec<UniqueCrash>,
+}
+
+impl UniqueCrashesMetadata {
+    pub fn crashes(&self) -> &[UniqueCrash] {
+        &self.crashes
+    }
+    
+    /// Records a hit of the signature. Returns whether it has not been seen before.
+    fn add(&mut self, signature: &CrashSignature) -> bool {
+        if let Some(crash) = self.crashes.iter_mut().find(|x| x.signature.hash == signature.hash) {
+            crash.hits += 1;
+            false
+        } else {
+            self.crashes.push(UniqueCrash {
+                signature: signature.clone(),
+                hits: 1,
+            });
+            true
+        }
+    }
+    
+    /// Writes the list of unique crashes as JSON to the given file
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        
+        std::fs::write(path, serde_json::to_string_pretty(&self.crashes).unwrap())?;
+        Ok(())
+    }
+}
+
+impl_serdeany!(UniqueCrashesMetadata);
+
+/// An objective that keeps only the first crashing input per [`CrashSignature`].
+/// Crashes without a sanitizer report are always kept.
+#[derive(Debug)]
+pub struct UniqueCrashFeedback {
+    observer_name: String,
+    summary: Option<PathBuf>,
+    signature: Option<CrashSignature>,
+}
+
+impl UniqueCrashFeedback {
+    pub fn new(observer: &SanitizerObserver) -> Self {
+        Self {
+            observer_name: observer.name().to_string(),
+            summary: None,
+            signature: None,
+        }
+    }
+    
+    /// Like [`UniqueCrashFeedback::new`] but also writes a summary of all unique crashes to the given file
+    pub fn with_summary<P: AsRef<Path>>(observer: &SanitizerObserver, summary: P) -> Self {
+        Self {
+            observer_name: observer.name().to_string(),
+            summary: Some(summary.as_ref().to_owned()),
+            signature: None,
+        }
+    }
+}
+
+impl Named for UniqueCrashFeedback {
+    fn name(&self) -> &str {
+        "UniqueCrashFeedback"
+    }
+}
+
+impl<S> Feedback<S> for UniqueCrashFeedback
+where
+    S: State + HasNamedMetadata,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        state.add_named_metadata(self.name(), UniqueCrashesMetadata::default());
+        Ok(())
+    }
+    
+    fn is_interesting<EM, OT>(&mut self, state: &mut S, _manager: &mut EM, _input: &S::Input, observers: &OT, exit_kind: &ExitKind) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        self.signature = None;
+        
+        if *exit_kind != ExitKind::Crash {
+            return Ok(false);
+        }
+        
+        let observer = observers.match_name::<SanitizerObserver>(&self.observer_name).ok_or_else(|| Error::illegal_argument(format!("UniqueCrashFeedback: observer {} not found", self.observer_name)))?;
+        
+        let Some(signature) = observer.signature() else {
+            return Ok(true);
+        };
+        
+        let crashes = state.named_metadata_mut::<UniqueCrashesMetadata>(self.name())?;
+        let novel = crashes.add(signature);
+        
+        if let Some(summary) = &self.summary {
+            crashes.dump(summary)?;
+        }
+        
+        if novel {
+            self.signature = Some(signature.clone());
+        }
+        
+        Ok(novel)
+    }
+    
+    fn append_metadata<EM, OT>(&mut self, _state: &mut S, _manager: &mut EM, _observers: &OT, testcase: &mut Testcase<S::Input>) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+        EM: EventFirer<State = S>,
+    {
+        if let Some(signature) = self.signature.take() {
+            testcase.add_metadata(signature);
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const ASAN_REPORT: &str = "\
+=================================================================
+==4711==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000011 at pc 0x4f5a3c bp 0x7ffd sp 0x7ffd
+READ of size 1 at 0x602000000011 thread T0
+    #0 0x4a1b2c in __interceptor_strlen (/proftpd/proftpd-fuzzing+0x4a1b2c)
+    #1 0x4f5a3c in pr_str_strip /proftpd/src/str.c:123:5
+    #2 0x4f1234 in pr_cmd_read /proftpd/src/main.c:456:3
+    #3 0x7f0d3a29d90 (/lib/x86_64-linux-gnu/libc.so.6+0x29d90)
+
+0x602000000011 is located 0 bytes after 1-byte region
+SUMMARY: AddressSanitizer: heap-buffer-overflow /proftpd/src/str.c:123:5 in pr_str_strip
+";
+    
+    #[test]
+    fn parse_asan() {
+        let signature = parse_sanitizer_report(ASAN_REPORT.as_bytes(), 3).unwrap();
+        assert_eq!(signature.bug_type(), "AddressSanitizer: heap-buffer-overflow");
+        assert_eq!(signature.frames(), &["pr_str_strip", "pr_cmd_read", "/lib/x86_64-linux-gnu/libc.so.6"]);
+        
+        /* Addresses and pids do not matter */
+        let other = ASAN_REPORT.replace("4711", "42").replace("0x4f1234", "0x5f1234");
+        assert_eq!(parse_sanitizer_report(other.as_bytes(), 3).unwrap().hash(), signature.hash());
+        assert_ne!(parse_sanitizer_report(ASAN_REPORT.as_bytes(), 1).unwrap().hash(), signature.hash());
+    }
+    
+    #[test]
+    fn stable_hash() {
+        let frames = vec!["pr_str_strip".to_string(), "pr_cmd_read".to_string()];
+        let a = CrashSignature::new("AddressSanitizer: heap-buffer-overflow".to_string(), frames.clone());
+        let b = CrashSignature::new("AddressSanitizer: heap-buffer-overflow".to_string(), frames);
+        assert_eq!(a.hash(), b.hash());
+        
+        /* Must not depend on the process either */
+        assert_eq!(a.hash(), 0xeab87099d062fe91);
+    }
+    
+    #[test]
+    fn capture_stderr() {
+        let mut channel = StderrChannel::new().unwrap();
+        
+        for msg in [&b"first run\n"[..], b"second\n"] {
+            channel.clear();
+            assert_eq!(unsafe { libc::write(channel.fd, msg.as_ptr() as *const libc::c_void, msg.len()) }, msg.len() as isize);
+            assert_eq!(channel.read(), msg);
+        }
+    }
+    
+    #[test]
+    fn parse_ubsan() {
+        let report = "some log output\n/proftpd/src/table.c:77:12: runtime error: signed integer overflow: 2147483647 + 1 cannot be represented in type 'int'\n";
+        let signature = parse_sanitizer_report(report.as_bytes(), 3).unwrap();
+        assert_eq!(signature.bug_type(), "UndefinedBehaviorSanitizer: signed integer overflow");
+        assert_eq!(signature.frames(), &["/proftpd/src/table.c:77:12"]);
+        
+        assert!(parse_sanitizer_report(b"nothing to see here\n", 3).is_none());
+    }
+}
@@ -0,0 +1,402 @@
+use libafl_bolts::{
+    impl_serdeany,
+    prelude::Rand,
+};
+use libafl::prelude::{
+    Corpus,
+    CorpusId,
+    Error,
+    HasCorpus,
+    HasMetadata,
+    HasRand,
+    ObserversTuple,
+    RemovableScheduler,
+    Scheduler,
+    State,
+    Testcase,
+    UsesInput,
+    UsesState,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    hash::Hash,
+    marker::PhantomData,
+};
+use crate::components::{
+    DragonflyInput,
+    Packet,
+    StateSequenceMetadata,
+    INITIAL_STATE,
+};
+
+/// The number of packets at the start of the current input that mutators must not touch.
+/// Set by the [`StateAwareScheduler`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProtectedPrefixMetadata {
+    len: usize,
+}
+
+impl ProtectedPrefixMetadata {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+        }
+    }
+    
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl_serdeany!(ProtectedPrefixMetadata);
+
+/// Returns the number of packets of an input of length `len` that mutators must leave untouched
+pub fn protected_prefix<S: HasMetadata>(state: &S, len: usize) -> usize {
+    match state.metadata::<ProtectedPrefixMetadata>() {
+        Ok(prefix) => std::cmp::min(prefix.len(), len),
+        Err(_) => 0,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateInfo {
+    name: String,
+    /// Corpus entries that reach this state together with the number of packets needed for that
+    entries: Vec<(CorpusId, usize)>,
+    selected: usize,
+    fuzzed: usize,
+    discoveries: usize,
+}
+
+impl StateInfo {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            entries: Vec::new(),
+            selected: 0,
+            fuzzed: 0,
+            discoveries: 0,
+        }
+    }
+    
+    /// Same formula as AFLNet: favor states that have been selected rarely
+    /// and that led to many new corpus entries.
+    fn score(&self) -> f64 {
+        let exercised = ((self.fuzzed + 1) as f64).log10() * self.selected as f64;
+        1000.0 * 2f64.powf(-(exercised + 1.0).log10()) * 2f64.powf(((self.discoveries + 1) as f64).ln())
+    }
+}
+
+/// Bookkeeping of the [`StateAwareScheduler`]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateSchedulerMetadata {
+    states: Vec<StateInfo>,
+    current: Option<usize>,
+}
+
+impl StateSchedulerMetadata {
+    /// Returns the currently targeted state
+    pub fn current_state(&self) -> Option<&str> {
+        self.current.map(|idx| self.states[idx].name.as_str())
+    }
+    
+    fn state_mut(&mut self, name: &str) -> &mut StateInfo {
+        let idx = if let Some(idx) = self.states.iter().position(|x| x.name == name) {
+            idx
+        } else {
+            self.states.push(StateInfo::new(name));
+            self.states.len() - 1
+        };
+        
+        &mut self.states[idx]
+    }
+    
+    /// Forgets a corpus entry in all states
+    fn remove_entry(&mut self, id: CorpusId) {
+        for info in &mut self.states {
+            info.entries.retain(|(x, _)| *x != id);
+        }
+    }
+}
+
+impl_serdeany!(StateSchedulerMetadata);
+
+/// An AFLNet-style scheduler that first picks a protocol state, favoring rarely exercised states,
+/// and then a corpus entry that reaches that state.
+/// It needs the [`StateSequenceMetadata`] of a [`StateFeedback`](crate::components::StateFeedback)
+/// and protects the packets that lead to the chosen state from being mutated
+/// via the [`ProtectedPrefixMetadata`].
+#[derive(Debug, Clone)]
+pub struct StateAwareScheduler<P, S> {
+    phantom: PhantomData<(P, S)>,
+}
+
+impl<P, S> StateAwareScheduler<P, S> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P, S> UsesState for StateAwareScheduler<P, S>
+where
+    P: Packet,
+    S: State + UsesInput<Input = DragonflyInput<P>>,
+{
+    type State = S;
+}
+
+impl<P, S> StateAwareScheduler<P, S>
+where
+    P: Packet + std::fmt::Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+    S: State + UsesInput<Input = DragonflyInput<P>> + HasCorpus + HasMetadata + HasRand,
+{
+    /// Returns the states reached by a corpus entry along with the length of the prefix that reaches them
+    fn reached_states(state: &S, id: CorpusId) -> Result<Vec<(String, usize)>, Error> {
+        let mut testcase = state.corpus().get(id)?.borrow_mut();
+        let mut ret = vec![(INITIAL_STATE.to_string(), 0)];
+        let (states, packets) = match testcase.metadata::<StateSequenceMetadata>() {
+            Ok(sequence) => (sequence.states().to_vec(), sequence.state_packets().to_vec()),
+            Err(_) => return Ok(ret),
+        };
+        let data_packets = testcase.load_input(state.corpus())?.data_packets();
+        
+        for (name, packet) in states.into_iter().zip(packets) {
+            if ret.iter().any(|(x, _)| *x == name) {
+                continue;
+            }
+            
+            let prefix = packet.and_then(|x| data_packets.get(x)).map(|x| *x + 1).unwrap_or(0);
+            ret.push((name, prefix));
+        }
+        
+        Ok(ret)
+    }
+    
+    fn add_entry(state: &mut S, id: CorpusId) -> Result<(), Error> {
+        let reached = Self::reached_states(state, id)?;
+        let metadata = state.metadata_or_insert_with(StateSchedulerMetadata::default);
+        
+        for (name, prefix) in reached {
+            metadata.state_mut(&name).entries.push((id, prefix));
+        }
+        
+        Ok(())
+    }
+    
+    fn next_in_queue(state: &S) -> Option<CorpusId> {
+        state.corpus().current().and_then(|id| state.corpus().next(id)).or_else(|| state.corpus().first())
+    }
+}
+
+impl<P, S> Scheduler for StateAwareScheduler<P, S>
+where
+    P: Packet + std::fmt::Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+    S: State + UsesInput<Input = DragonflyInput<P>> + HasCorpus + HasMetadata + HasRand,
+{
+    fn on_add(&mut self, state: &mut S, idx: CorpusId) -> Result<(), Error> {
+        let current_idx = *state.corpus().current();
+        state.corpus().get(idx)?.borrow_mut().set_parent_id_optional(current_idx);
+        
+        let metadata = state.metadata_or_insert_with(StateSchedulerMetadata::default);
+        
+        /* Credit the state that was targeted when this entry was found */
+        if let Some(current) = metadata.current {
+            metadata.states[current].discoveries += 1;
+        }
+        
+        Self::add_entry(state, idx)
+    }
+    
+    fn on_evaluation<OT>(&mut self, state: &mut S, _input: &DragonflyInput<P>, _observers: &OT) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        if let Ok(metadata) = state.metadata_mut::<StateSchedulerMetadata>() {
+            if let Some(current) = metadata.current {
+                metadata.states[current].fuzzed += 1;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn next(&mut self, state: &mut S) -> Result<CorpusId, Error> {
+        if state.corpus().count() == 0 {
+            return Err(Error::empty("No entries in corpus. This often implies the target is not properly instrumented."));
+        }
+        
+        let scores: Vec<f64> = match state.metadata::<StateSchedulerMetadata>() {
+            Ok(metadata) => metadata.states.iter().map(|x| if x.entries.is_empty() { 0.0 } else { x.score() }).collect(),
+            Err(_) => Vec::new(),
+        };
+        let total: f64 = scores.iter().sum();
+        
+        /* Without any known states behave like a queue */
+        if total <= 0.0 {
+            let id = Self::next_in_queue(state).unwrap();
+            state.add_metadata(ProtectedPrefixMetadata::new(0));
+            self.set_current_scheduled(state, Some(id))?;
+            return Ok(id);
+        }
+        
+        /* Pick a state proportional to its score */
+        let mut point = (state.rand_mut().next() as f64 / u64::MAX as f64) * total;
+        let mut target = 0;
+        
+        for (i, score) in scores.iter().enumerate() {
+            if *score > 0.0 {
+                target = i;
+                
+                if point < *score {
+                    break;
+                }
+                
+                point -= *score;
+            }
+        }
+        
+        /* Pick an entry that reaches the state */
+        let num_entries = state.metadata::<StateSchedulerMetadata>()?.states[target].entries.len();
+        let entry = state.rand_mut().below(num_entries as u64) as usize;
+        let metadata = state.metadata_mut::<StateSchedulerMetadata>()?;
+        let info = &mut metadata.states[target];
+        let (id, prefix) = info.entries[entry];
+        info.selected += 1;
+        metadata.current = Some(target);
+        
+        state.add_metadata(ProtectedPrefixMetadata::new(prefix));
+        self.set_current_scheduled(state, Some(id))?;
+        Ok(id)
+    }
+}
+
+impl<P, S> RemovableScheduler for StateAwareScheduler<P, S>
+where
+    P: Packet + std::fmt::Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+    S: State + UsesInput<Input = DragonflyInput<P>> + HasCorpus + HasMetadata + HasRand,
+{
+    fn on_remove(&mut self, state: &mut S, idx: CorpusId, _testcase: &Option<Testcase<DragonflyInput<P>>>) -> Result<(), Error> {
+        if let Ok(metadata) = state.metadata_mut::<StateSchedulerMetadata>() {
+            metadata.remove_entry(idx);
+        }
+        
+        Ok(())
+    }
+    
+    fn on_replace(&mut self, state: &mut S, idx: CorpusId, _prev: &Testcase<DragonflyInput<P>>) -> Result<(), Error> {
+        /* The new testcase may reach different states with different prefixes */
+        if let Ok(metadata) = state.metadata_mut::<StateSchedulerMetadata>() {
+            metadata.remove_entry(idx);
+        }
+        
+        Self::add_entry(state, idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl::prelude::{
+        ConstFeedback,
+        InMemoryCorpus,
+        StdState,
+    };
+    use libafl_bolts::prelude::StdRand;
+    use crate::components::StateSequenceMetadata;
+    
+    #[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+    struct TestPacket(bool);
+    
+    impl Packet for TestPacket {
+        fn serialize_content(&self, _buffer: &mut [u8]) -> Option<usize> {
+            if self.0 {
+                Some(0)
+            } else {
+                None
+            }
+        }
+    }
+    
+    #[test]
+    fn prefix_of_state() {
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<DragonflyInput<TestPacket>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        let mut scheduler = StateAwareScheduler::new();
+        
+        /* The second state is revealed by the second data packet which is the fourth packet */
+        let mut testcase = Testcase::new(DragonflyInput::new(vec![
+            TestPacket(true),
+            TestPacket(false),
+            TestPacket(false),
+            TestPacket(true),
+            TestPacket(true),
+        ]));
+        testcase.add_metadata(StateSequenceMetadata::new(
+            vec!["220".to_string(), "331".to_string()],
+            vec![None, Some(1)],
+        ));
+        let id = state.corpus_mut().add(testcase).unwrap();
+        scheduler.on_add(&mut state, id).unwrap();
+        
+        let mut prefixes = Vec::new();
+        
+        for _ in 0..64 {
+            assert_eq!(scheduler.next(&mut state).unwrap(), id);
+            prefixes.push(protected_prefix(&state, 5));
+        }
+        
+        assert!(prefixes.contains(&0));
+        assert!(prefixes.contains(&4));
+        assert!(prefixes.iter().all(|x| *x == 0 || *x == 4));
+    }
+    
+    #[test]
+    fn removed_entries() {
+        let mut state = StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<DragonflyInput<TestPacket>>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        ).unwrap();
+        let mut scheduler = StateAwareScheduler::new();
+        let mut ids = Vec::new();
+        
+        for _ in 0..2 {
+            let mut testcase = Testcase::new(DragonflyInput::new(vec![TestPacket(true)]));
+            testcase.add_metadata(StateSequenceMetadata::new(
+                vec!["220".to_string()],
+                vec![Some(0)],
+            ));
+            let id = state.corpus_mut().add(testcase).unwrap();
+            scheduler.on_add(&mut state, id).unwrap();
+            ids.push(id);
+        }
+        
+        let testcase = state.corpus_mut().remove(ids[0]).unwrap();
+        scheduler.on_remove(&mut state, ids[0], &Some(testcase)).unwrap();
+        
+        for _ in 0..64 {
+            assert_eq!(scheduler.next(&mut state).unwrap(), ids[1]);
+        }
+        
+        /* The replacement reaches no state beyond the initial one */
+        let prev = state.corpus_mut().replace(ids[1], Testcase::new(DragonflyInput::new(vec![TestPacket(true)]))).unwrap();
+        scheduler.on_replace(&mut state, ids[1], &prev).unwrap();
+        
+        let metadata = state.metadata::<StateSchedulerMetadata>().unwrap();
+        assert!(metadata.states.iter().all(|x| x.entries.len() == usize::from(x.name == INITIAL_STATE)));
+    }
+}
@@ -0,0 +1,213 @@
+use libafl::prelude::{
+    Error,
+    ExitKind,
+};
+use nix::{
+    sys::{
+        signal::{kill, Signal},
+        time::{TimeSpec, TimeValLike},
+    },
+    unistd::Pid,
+};
+use ahash::AHasher;
+use std::hash::Hasher;
+
+pub(crate) const SNAPSHOT_FDS_ENV_VAR: &str = "__LIBDRAGONFLY_SNAPSHOT_FDS";
+
+pub(crate) fn hash_prefix(prefix: &[u8]) -> u64 {
+    let mut hasher = AHasher::default();
+    hasher.write(prefix);
+    hasher.finish()
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    hash: u64,
+    server: Pid,
+}
+
+/// The pipes to the snapshot server that libdragonfly forks when the target
+/// reaches a snapshot marker in the packet channel.
+/// Only one snapshot can be alive at a time since all snapshot servers would
+/// have to share the same pipes.
+#[derive(Debug)]
+pub(crate) struct SnapshotChannel {
+    ctl_read: i32,
+    ctl_write: i32,
+    /* Our own read end of the ctl pipe to drop commands the old server never read */
+    ctl_drain: i32,
+    st_read: i32,
+    st_write: i32,
+    snapshot: Option<Snapshot>,
+}
+
+fn create_pipe() -> Result<(i32, i32), Error> {
+    let mut fds = [-1; 2];
+    
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(Error::unknown(format!("Could not create snapshot pipe: {}", std::io::Error::last_os_error())));
+    }
+    
+    Ok((fds[0], fds[1]))
+}
+
+fn read_timed(fd: i32, timeout: &TimeSpec) -> Option<u32> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.num_milliseconds() as i32;
+    
+    if unsafe { libc::poll(&mut pollfd, 1, timeout) } <= 0 || (pollfd.revents & libc::POLLIN) == 0 {
+        return None;
+    }
+    
+    let mut value = 0u32;
+    let ret = unsafe { libc::read(fd, &mut value as *mut u32 as *mut libc::c_void, 4) };
+    
+    if ret == 4 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+impl SnapshotChannel {
+    pub(crate) fn new() -> Result<Self, Error> {
+        let (ctl_read, ctl_write) = create_pipe()?;
+        let (st_read, st_write) = create_pipe()?;
+        let ctl_drain = unsafe { libc::fcntl(ctl_read, libc::F_DUPFD_CLOEXEC, 0) };
+        
+        if ctl_drain < 0 {
+            return Err(Error::unknown(format!("Could not create snapshot pipe: {}", std::io::Error::last_os_error())));
+        }
+        
+        /* The target only inherits its ends of the pipes */
+        for fd in [ctl_read, st_write] {
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } != 0 {
+                return Err(Error::unknown(format!("Could not share snapshot pipe: {}", std::io::Error::last_os_error())));
+            }
+        }
+        
+        Ok(Self {
+            ctl_read,
+            ctl_write,
+            ctl_drain,
+            st_read,
+            st_write,
+            snapshot: None,
+        })
+    }
+    
+    /// Value of [`SNAPSHOT_FDS_ENV_VAR`] for the target
+    pub(crate) fn env_value(&self) -> String {
+        format!("{},{}", self.ctl_read, self.st_write)
+    }
+    
+    /// Must be called once the target has been spawned
+    pub(crate) fn close_child_ends(&mut self) {
+        for fd in [&mut self.ctl_read, &mut self.st_write] {
+            if *fd >= 0 {
+                unsafe { libc::close(*fd) };
+                *fd = -1;
+            }
+        }
+    }
+    
+    /// Hash of the prefix of the current snapshot
+    pub(crate) fn current(&self) -> Option<u64> {
+        self.snapshot.as_ref().map(|x| x.hash)
+    }
+    
+    pub(crate) fn invalidate(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            let _ = kill(snapshot.server, Signal::SIGKILL);
+        }
+        
+        /* Drop stale messages in both directions such that the next server starts in sync */
+        while read_timed(self.st_read, &TimeSpec::new(0, 0)).is_some() {}
+        while read_timed(self.ctl_drain, &TimeSpec::new(0, 0)).is_some() {}
+    }
+    
+    fn write(&mut self, value: u32) -> bool {
+        let ret = unsafe { libc::write(self.ctl_write, &value as *const u32 as *const libc::c_void, 4) };
+        ret == 4
+    }
+    
+    /// Picks up the snapshot the target created during the last regular execution, if any
+    pub(crate) fn collect(&mut self, hash: u64) {
+        if let Some(server) = read_timed(self.st_read, &TimeSpec::new(0, 0)) {
+            self.snapshot = Some(Snapshot {
+                hash,
+                server: Pid::from_raw(server as i32),
+            });
+        }
+    }
+    
+    /// Executes the current input from the current snapshot if its prefix has the given hash.
+    /// Returns `None` if the snapshot is not usable and has been invalidated.
+    pub(crate) fn run(&mut self, hash: u64, timeout: &TimeSpec, signal: Signal) -> Option<ExitKind> {
+        if self.current() != Some(hash) {
+            self.invalidate();
+            return None;
+        }
+        
+        if !self.write(0) {
+            self.invalidate();
+            return None;
+        }
+        
+        let Some(child) = read_timed(self.st_read, timeout) else {
+            self.invalidate();
+            return None;
+        };
+        
+        if let Some(status) = read_timed(self.st_read, timeout) {
+            if libc::WIFSIGNALED(status as i32) {
+                Some(ExitKind::Crash)
+            } else {
+                Some(ExitKind::Ok)
+            }
+        } else {
+            let _ = kill(Pid::from_raw(child as i32), signal);
+            
+            if read_timed(self.st_read, timeout).is_none() {
+                self.invalidate();
+            }
+            
+            Some(ExitKind::Timeout)
+        }
+    }
+}
+
+impl Drop for SnapshotChannel {
+    fn drop(&mut self) {
+        self.invalidate();
+        self.close_child_ends();
+        unsafe {
+            libc::close(self.ctl_write);
+            libc::close(self.ctl_drain);
+            libc::close(self.st_read);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn invalidate_drains_pipes() {
+        let mut channel = SnapshotChannel::new().unwrap();
+        
+        /* A command the old server never picked up */
+        assert!(channel.write(0));
+        
+        channel.invalidate();
+        assert!(read_timed(channel.ctl_drain, &TimeSpec::new(0, 0)).is_none());
+        
+        assert!(channel.write(0));
+        assert_eq!(read_timed(channel.ctl_drain, &TimeSpec::new(0, 0)), Some(0));
+    }
+}
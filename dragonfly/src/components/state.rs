@@ -0,0 +1,373 @@
+use libafl_bolts::{
+    impl_serdeany,
+    prelude::Named,
+};
+use libafl::prelude::{
+    Error,
+    EventFirer,
+    ExitKind,
+    Feedback,
+    HasMetadata,
+    HasNamedMetadata,
+    Observer,
+    ObserversTuple,
+    State,
+    Testcase,
+    UsesInput,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+use crate::components::{
+    reply_codes,
+    Response,
+    ResponseCodeExtractor,
+    ResponseObserver,
+};
+
+/// Name of the implicit state every execution starts in
+pub const INITIAL_STATE: &str = "INIT";
+
+fn default_extractor() -> ResponseCodeExtractor {
+    reply_codes
+}
+
+/// Derives the sequence of protocol states the target went through from its responses.
+/// A state is identified by each value the extractor returns for a response, e.g. an FTP reply code.
+/// This wraps a [`ResponseObserver`] such that both share the same response channel.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateObserver<'a> {
+    name: String,
+    responses: ResponseObserver<'a>,
+    #[serde(skip, default = "default_extractor")]
+    extractor: ResponseCodeExtractor,
+    states: Vec<String>,
+    packets: Vec<Option<usize>>,
+}
+
+impl<'a> StateObserver<'a> {
+    pub fn new<S: Into<String>>(name: S, responses: ResponseObserver<'a>) -> Self {
+        Self::with_extractor(name, responses, reply_codes)
+    }
+    
+    pub fn with_extractor<S: Into<String>>(name: S, responses: ResponseObserver<'a>, extractor: ResponseCodeExtractor) -> Self {
+        Self {
+            name: name.into(),
+            responses,
+            extractor,
+            states: Vec::new(),
+            packets: Vec::new(),
+        }
+    }
+    
+    /// The states observed in the last execution, without the initial state
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+    
+    /// For each state, the data packet whose response revealed it. See [`Response::packet()`].
+    pub fn state_packets(&self) -> &[Option<usize>] {
+        &self.packets
+    }
+    
+    pub fn responses(&self) -> &[Response] {
+        self.responses.responses()
+    }
+    
+    fn extract_states(&mut self) {
+        self.states.clear();
+        self.packets.clear();
+        
+        for response in self.responses.responses() {
+            for state in (self.extractor)(response.data()) {
+                self.states.push(String::from_utf8_lossy(state).into_owned());
+                self.packets.push(response.packet());
+            }
+        }
+    }
+}
+
+impl<'a> Named for StateObserver<'a> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<'a, S> Observer<S> for StateObserver<'a>
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, state: &mut S, input: &S::Input) -> Result<(), Error> {
+        self.states.clear();
+        self.packets.clear();
+        self.responses.pre_exec(state, input)
+    }
+    
+    fn post_exec(&mut self, state: &mut S, input: &S::Input, exit_kind: &ExitKind) -> Result<(), Error> {
+        self.responses.post_exec(state, input, exit_kind)?;
+        self.extract_states();
+        Ok(())
+    }
+}
+
+/// The protocol state graph built from all executions so far
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateGraphMetadata {
+    states: Vec<String>,
+    transitions: HashMap<(usize, usize), usize>,
+}
+
+impl StateGraphMetadata {
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+    
+    /// Maps a transition between two state indices to the number of times it was taken
+    pub fn transitions(&self) -> &HashMap<(usize, usize), usize> {
+        &self.transitions
+    }
+    
+    fn state_index(&self, state: &str) -> Option<usize> {
+        self.states.iter().position(|x| x == state)
+    }
+    
+    fn add_state(&mut self, state: &str) -> usize {
+        if let Some(idx) = self.state_index(state) {
+            idx
+        } else {
+            self.states.push(state.to_string());
+            self.states.len() - 1
+        }
+    }
+    
+    /// Adds the path through the given states to the graph. Returns whether a new transition was discovered.
+    fn add_path(&mut self, states: &[String]) -> bool {
+        let mut last = self.add_state(INITIAL_STATE);
+        let mut novel = false;
+        
+        for state in states {
+            let next = self.add_state(state);
+            let hits = self.transitions.entry((last, next)).or_insert(0);
+            novel |= *hits == 0;
+            *hits += 1;
+            last = next;
+        }
+        
+        novel
+    }
+    
+    fn sorted_transitions(&self) -> Vec<(usize, usize, usize)> {
+        let mut transitions: Vec<_> = self.transitions.iter().map(|((from, to), hits)| (*from, *to, *hits)).collect();
+        transitions.sort_unstable();
+        transitions
+    }
+    
+    /// Renders the state graph in the Graphviz format
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph states {\n");
+        
+        for state in &self.states {
+            writeln!(&mut dot, "    {:?};", state).unwrap();
+        }
+        
+        for (from, to, hits) in self.sorted_transitions() {
+            writeln!(&mut dot, "    {:?} -> {:?} [label=\"{}\"];", self.states[from], self.states[to], hits).unwrap();
+        }
+        
+        dot.push_str("}\n");
+        dot
+    }
+    
+    /// Renders the state graph as JSON
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Transition<'a> {
+            from: &'a str,
+            to: &'a str,
+            hits: usize,
+        }
+        
+        #[derive(Serialize)]
+        struct Graph<'a> {
+            states: &'a [String],
+            transitions: Vec<Transition<'a>>,
+        }
+        
+        let graph = Graph {
+            states: &self.states,
+            transitions: self.sorted_transitions().into_iter().map(|(from, to, hits)| Transition {
+                from: &self.states[from],
+                to: &self.states[to],
+                hits,
+            }).collect(),
+        };
+        
+        serde_json::to_string_pretty(&graph).unwrap()
+    }
+    
+    /// Writes the state graph as `state-graph.dot` and `state-graph.json` into the given directory
+    pub fn dump<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("state-graph.dot"), self.to_dot())?;
+        std::fs::write(dir.join("state-graph.json"), self.to_json())?;
+        Ok(())
+    }
+}
+
+impl_serdeany!(StateGraphMetadata);
+
+/// The states a testcase went through when it was added to the corpus
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateSequenceMetadata {
+    states: Vec<String>,
+    packets: Vec<Option<usize>>,
+}
+
+impl StateSequenceMetadata {
+    pub fn new(states: Vec<String>, packets: Vec<Option<usize>>) -> Self {
+        debug_assert_eq!(states.len(), packets.len());
+        
+        Self {
+            states,
+            packets,
+        }
+    }
+    
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+    
+    /// For each state, the data packet whose response revealed it
+    pub fn state_packets(&self) -> &[Option<usize>] {
+        &self.packets
+    }
+}
+
+impl_serdeany!(StateSequenceMetadata);
+
+/// Considers an input interesting if it makes the target take a state transition
+/// that has not been observed before.
+#[derive(Debug)]
+pub struct StateFeedback {
+    observer_name: String,
+    output_dir: Option<PathBuf>,
+}
+
+impl StateFeedback {
+    pub fn new(observer: &StateObserver) -> Self {
+        Self {
+            observer_name: observer.name().to_string(),
+            output_dir: None,
+        }
+    }
+    
+    /// Like [`StateFeedback::new`] but also dumps the state graph into `output_dir`
+    /// everytime a new transition is found.
+    pub fn with_output_dir<P: AsRef<Path>>(observer: &StateObserver, output_dir: P) -> Self {
+        Self {
+            observer_name: observer.name().to_string(),
+            output_dir: Some(output_dir.as_ref().to_owned()),
+        }
+    }
+    
+    fn observer<'o, S, OT>(&self, observers: &'o OT) -> Result<&'o StateObserver<'static>, Error>
+    where
+        S: UsesInput,
+        OT: ObserversTuple<S>,
+    {
+        observers.match_name::<StateObserver>(&self.observer_name).ok_or_else(|| Error::illegal_argument(format!("StateFeedback: observer {} not found", self.observer_name)))
+    }
+}
+
+impl Named for StateFeedback {
+    fn name(&self) -> &str {
+        "StateFeedback"
+    }
+}
+
+impl<S> Feedback<S> for StateFeedback
+where
+    S: State + HasNamedMetadata,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        state.add_named_metadata(self.name(), StateGraphMetadata::default());
+        Ok(())
+    }
+    
+    fn is_interesting<EM, OT>(&mut self, state: &mut S, _manager: &mut EM, _input: &S::Input, observers: &OT, _exit_kind: &ExitKind) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        let observer = self.observer::<S, OT>(observers)?;
+        let graph = state.named_metadata_mut::<StateGraphMetadata>(self.name())?;
+        let novel = graph.add_path(observer.states());
+        
+        if novel {
+            if let Some(output_dir) = &self.output_dir {
+                graph.dump(output_dir)?;
+            }
+        }
+        
+        Ok(novel)
+    }
+    
+    fn append_metadata<EM, OT>(&mut self, _state: &mut S, _manager: &mut EM, observers: &OT, testcase: &mut Testcase<S::Input>) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+        EM: EventFirer<State = S>,
+    {
+        let observer = self.observer::<S, OT>(observers)?;
+        testcase.add_metadata(StateSequenceMetadata::new(
+            observer.states().to_vec(),
+            observer.state_packets().to_vec(),
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn path(states: &[&str]) -> Vec<String> {
+        states.iter().map(|x| x.to_string()).collect()
+    }
+    
+    #[test]
+    fn novel_transitions() {
+        let mut graph = StateGraphMetadata::default();
+        
+        assert!(graph.add_path(&path(&["220", "331", "230"])));
+        assert!(!graph.add_path(&path(&["220", "331"])));
+        assert!(graph.add_path(&path(&["220", "331", "530"])));
+        assert!(graph.add_path(&path(&["220", "220"])));
+        assert!(!graph.add_path(&path(&[])));
+        
+        assert_eq!(graph.states().len(), 5);
+        assert_eq!(graph.transitions().len(), 5);
+        assert_eq!(graph.transitions()[&(0, 1)], 4);
+    }
+    
+    #[test]
+    fn dump_graph() {
+        let mut graph = StateGraphMetadata::default();
+        graph.add_path(&path(&["220", "530"]));
+        
+        let dot = graph.to_dot();
+        println!("{}", dot);
+        assert!(dot.contains("\"INIT\" -> \"220\" [label=\"1\"];"));
+        
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+        println!("{}", json);
+        assert_eq!(json["transitions"][1]["from"], "220");
+        assert_eq!(json["transitions"][1]["to"], "530");
+    }
+}
@@ -0,0 +1,97 @@
+use libafl::prelude::{
+    AggregatorOps,
+    Error,
+    Event,
+    EventFirer,
+    HasMetadata,
+    Stage,
+    UsesState,
+    UserStats,
+    UserStatsValue,
+};
+use libafl_bolts::prelude::current_time;
+use std::{
+    marker::PhantomData,
+    time::Duration,
+};
+use crate::components::{TokenMutationStats, TokenMutation};
+
+/// Prefix of the monitor statistics that hold the success rate of every token mutation
+pub const TOKEN_MUTATION_STAT_PREFIX: &str = "token mutation";
+
+/// Periodically sends the [`TokenMutationStats`] to the monitor as one ratio
+/// of new corpus entries to applications per mutation and per stack size
+pub struct TokenMutationStatsStage<EM, Z> {
+    interval: Duration,
+    last_report: Duration,
+    phantom: PhantomData<(EM, Z)>,
+}
+
+impl<EM, Z> TokenMutationStatsStage<EM, Z> {
+    #[allow(clippy::new_without_default)]
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_report: Duration::ZERO,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<EM, Z> UsesState for TokenMutationStatsStage<EM, Z>
+where
+    EM: UsesState,
+{
+    type State = EM::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for TokenMutationStatsStage<EM, Z>
+where
+    E: UsesState<State = Z::State>,
+    EM: EventFirer<State = Z::State>,
+    Z: UsesState,
+    Z::State: HasMetadata,
+{
+    fn perform(&mut self, _fuzzer: &mut Z, _executor: &mut E, state: &mut Z::State, manager: &mut EM) -> Result<(), Error> {
+        let now = current_time();
+        
+        if now.saturating_sub(self.last_report) < self.interval {
+            return Ok(());
+        }
+        
+        self.last_report = now;
+        
+        let Some(stats) = state.metadata_map().get::<TokenMutationStats>() else {
+            return Ok(());
+        };
+        let mut values = Vec::new();
+        
+        for mutation in TokenMutation::ALL {
+            let op = stats.mutation(mutation);
+            values.push((format!("{} {}", TOKEN_MUTATION_STAT_PREFIX, mutation.name()), op.successes(), op.attempts()));
+        }
+        
+        for (size, stack) in stats.stacks() {
+            values.push((format!("{} stack {}", TOKEN_MUTATION_STAT_PREFIX, size), stack.successes(), stack.attempts()));
+        }
+        
+        for (name, successes, attempts) in values {
+            manager.fire(state, Event::UpdateUserStats {
+                name,
+                value: UserStats::new(UserStatsValue::Ratio(successes, attempts), AggregatorOps::Sum),
+                phantom: PhantomData,
+            })?;
+        }
+        
+        Ok(())
+    }
+    
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, Error> {
+        /* Does not execute the target */
+        Ok(true)
+    }
+    
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use libafl::prelude::Error;
+use serde::{Serialize, Deserialize};
+use std::ops::Range;
+
+/// Number of values libdragonfly can capture per input
+pub const MAX_CAPTURE_SLOTS: u32 = 16;
+
+/// Number of regex groups libdragonfly can capture from
+const MAX_CAPTURE_GROUPS: usize = 10;
+
+/// Longest value libdragonfly keeps per capture, longer values are cut off
+pub const MAX_CAPTURE_SIZE: usize = 256;
+
+const CAPTURE_REGEX: u32 = 1;
+const CAPTURE_OFFSET: u32 = 2;
+const SEGMENT_BYTES: u32 = 1;
+const SEGMENT_REFERENCE: u32 = 2;
+const CAPTURE_HEADER_SIZE: usize = 32;
+const SEGMENT_HEADER_SIZE: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+enum CapturePattern {
+    Regex {
+        pattern: String,
+        group: usize,
+    },
+    Offset {
+        offset: usize,
+        length: usize,
+    },
+}
+
+/// Tells libdragonfly to store a value from the responses on the connection of the packet
+/// that declares the capture, see [`Packet::captures`](crate::components::Packet::captures).
+/// Every write of the target is matched separately and the last match wins.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Capture {
+    slot: u32,
+    pattern: CapturePattern,
+}
+
+impl Capture {
+    /// Captures `group` of the first match of the POSIX extended regular expression `pattern`.
+    /// Group 0 is the whole match.
+    pub fn regex<S: Into<String>>(slot: u32, pattern: S, group: usize) -> Result<Self, Error> {
+        let pattern = pattern.into();
+        
+        if slot >= MAX_CAPTURE_SLOTS {
+            return Err(Error::illegal_argument(format!("Capture slot must be less than {}", MAX_CAPTURE_SLOTS)));
+        }
+        
+        if group >= MAX_CAPTURE_GROUPS {
+            return Err(Error::illegal_argument(format!("Capture group must be less than {}", MAX_CAPTURE_GROUPS)));
+        }
+        
+        if pattern.contains('\0') {
+            return Err(Error::illegal_argument("Capture pattern must not contain a NUL byte"));
+        }
+        
+        Ok(Self {
+            slot,
+            pattern: CapturePattern::Regex {
+                pattern,
+                group,
+            },
+        })
+    }
+    
+    /// Captures `length` bytes at `offset` of a response, or everything after `offset` if `length` is 0
+    pub fn offset(slot: u32, offset: usize, length: usize) -> Result<Self, Error> {
+        if slot >= MAX_CAPTURE_SLOTS {
+            return Err(Error::illegal_argument(format!("Capture slot must be less than {}", MAX_CAPTURE_SLOTS)));
+        }
+        
+        Ok(Self {
+            slot,
+            pattern: CapturePattern::Offset {
+                offset,
+                length,
+            },
+        })
+    }
+    
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+    
+    /// Writes the capture in the format of libdragonfly. Returns `None` if it does not fit into `buffer`.
+    pub(crate) fn serialize_into(&self, buffer: &mut [u8]) -> Option<usize> {
+        let (kind, group, offset, length, pattern) = match &self.pattern {
+            CapturePattern::Regex { pattern, group } => (CAPTURE_REGEX, *group, 0, 0, pattern.as_bytes()),
+            CapturePattern::Offset { offset, length } => (CAPTURE_OFFSET, 0, *offset, *length, &[][..]),
+        };
+        let size = CAPTURE_HEADER_SIZE + pattern.len() + 1;
+        
+        if size > buffer.len() {
+            return None;
+        }
+        
+        buffer[0..4].copy_from_slice(&self.slot.to_ne_bytes());
+        buffer[4..8].copy_from_slice(&kind.to_ne_bytes());
+        buffer[8..12].copy_from_slice(&(group as u32).to_ne_bytes());
+        buffer[12..16].copy_from_slice(&0u32.to_ne_bytes());
+        buffer[16..24].copy_from_slice(&(offset as u64).to_ne_bytes());
+        buffer[24..32].copy_from_slice(&(length as u64).to_ne_bytes());
+        buffer[CAPTURE_HEADER_SIZE..size - 1].copy_from_slice(pattern);
+        buffer[size - 1] = 0;
+        
+        Some(size)
+    }
+}
+
+/// A placeholder of `length` bytes at `offset` of the serialized content of a packet that libdragonfly
+/// replaces with the value of a [`Capture`] when the target reads the packet, see [`Packet::references`](crate::components::Packet::references).
+/// The placeholder itself is sent as long as nothing was captured, so it should be a sensible default value,
+/// e.g. the value from the recorded exchange.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Reference {
+    slot: u32,
+    offset: usize,
+    length: usize,
+}
+
+impl Reference {
+    pub fn new(slot: u32, offset: usize, length: usize) -> Self {
+        Self {
+            slot,
+            offset,
+            length,
+        }
+    }
+    
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+    
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.length
+    }
+}
+
+/// Splits `content` at the placeholders of `references` into the segments of a template packet.
+/// Returns the segments as (slot, bytes), where bytes without a slot are literal.
+/// Empty placeholders, placeholders that are not fully inside the content and
+/// placeholders that overlap an earlier one are ignored.
+fn segments<'a>(content: &'a [u8], references: &[Reference]) -> Vec<(Option<u32>, &'a [u8])> {
+    let mut references: Vec<&Reference> = references.iter().filter(|x| x.length > 0 && x.range().end <= content.len()).collect();
+    references.sort_by_key(|x| x.offset);
+    
+    let mut segments = Vec::new();
+    let mut start = 0;
+    
+    for reference in references {
+        if reference.offset < start {
+            continue;
+        }
+        
+        if start < reference.offset {
+            segments.push((None, &content[start..reference.offset]));
+        }
+        
+        segments.push((Some(reference.slot), &content[reference.range()]));
+        start = reference.range().end;
+    }
+    
+    if start < content.len() {
+        segments.push((None, &content[start..]));
+    }
+    
+    segments
+}
+
+/// Encodes `content` as a template into `buffer`. Returns `None` if the content
+/// contains no placeholder or the template does not fit.
+pub(crate) fn serialize_template(content: &[u8], references: &[Reference], buffer: &mut [u8]) -> Option<usize> {
+    let segments = segments(content, references);
+    
+    if segments.iter().all(|(slot, _)| slot.is_none()) {
+        return None;
+    }
+    
+    let size: usize = segments.iter().map(|(_, bytes)| SEGMENT_HEADER_SIZE + bytes.len()).sum();
+    
+    if size > buffer.len() {
+        return None;
+    }
+    
+    let mut cursor = 0;
+    
+    for (slot, bytes) in segments {
+        let kind = if slot.is_some() { SEGMENT_REFERENCE } else { SEGMENT_BYTES };
+        buffer[cursor..cursor + 4].copy_from_slice(&kind.to_ne_bytes());
+        buffer[cursor + 4..cursor + 8].copy_from_slice(&slot.unwrap_or(0).to_ne_bytes());
+        buffer[cursor + 8..cursor + 16].copy_from_slice(&(bytes.len() as u64).to_ne_bytes());
+        cursor += SEGMENT_HEADER_SIZE;
+        buffer[cursor..cursor + bytes.len()].copy_from_slice(bytes);
+        cursor += bytes.len();
+    }
+    
+    Some(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn templates() {
+        let references = [Reference::new(0, 5, 6), Reference::new(1, 12, 7), Reference::new(0, 19, 6)];
+        let content = b"PORT <port> <token><port>\r\n";
+        
+        assert_eq!(segments(content, &references), vec![
+            (None, &b"PORT "[..]),
+            (Some(0), &b"<port>"[..]),
+            (None, &b" "[..]),
+            (Some(1), &b"<token>"[..]),
+            (Some(0), &b"<port>"[..]),
+            (None, &b"\r\n"[..]),
+        ]);
+        
+        /* Equal bytes elsewhere in the content are no placeholder, overlapping and cut off references are ignored */
+        let overlapping = [Reference::new(0, 6, 2), Reference::new(1, 7, 4), Reference::new(2, 9, 16), Reference::new(3, 0, 0)];
+        assert_eq!(segments(b"21 21 21 21\r\n", &overlapping), vec![
+            (None, &b"21 21 "[..]),
+            (Some(0), &b"21"[..]),
+            (None, &b" 21\r\n"[..]),
+        ]);
+        
+        let mut buffer = vec![0; 256];
+        let size = serialize_template(content, &references, &mut buffer).unwrap();
+        assert_eq!(size, 6 * SEGMENT_HEADER_SIZE + content.len());
+        assert_eq!(&buffer[16..21], b"PORT ");
+        assert_eq!(u32::from_ne_bytes(buffer[21..25].try_into().unwrap()), SEGMENT_REFERENCE);
+        
+        assert!(serialize_template(b"NOOP\r\n", &references, &mut buffer).is_none());
+        assert!(serialize_template(content, &references, &mut buffer[..32]).is_none());
+    }
+    
+    #[test]
+    fn captures() {
+        assert!(Capture::regex(MAX_CAPTURE_SLOTS, "x", 0).is_err());
+        assert!(Capture::regex(0, "x", MAX_CAPTURE_GROUPS).is_err());
+        
+        let capture = Capture::regex(3, "\\(([0-9,]+)\\)", 1).unwrap();
+        let mut buffer = vec![0xff; 64];
+        let size = capture.serialize_into(&mut buffer).unwrap();
+        assert_eq!(size, CAPTURE_HEADER_SIZE + 14);
+        assert_eq!(u32::from_ne_bytes(buffer[0..4].try_into().unwrap()), 3);
+        assert_eq!(&buffer[CAPTURE_HEADER_SIZE..size], b"\\(([0-9,]+)\\)\0");
+        assert!(capture.serialize_into(&mut buffer[..40]).is_none());
+    }
+}
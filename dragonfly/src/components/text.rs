@@ -0,0 +1,203 @@
+use libafl::prelude::Error;
+use std::{
+    fmt::Write as _,
+    path::Path,
+};
+use crate::components::{
+    DragonflyInput,
+    Packet,
+};
+
+/// Packets that can be rebuilt from the bytes they serialize to.
+/// This is what the text format of [`DragonflyInput`] needs.
+///
+/// The conversion only has to preserve the serialized content, not the internal structure.
+/// For example, a [`TokenStream`](crate::tokens::TokenStream) is re-tokenized from its bytes,
+/// so token kinds chosen by a grammar or a mutator are not restored.
+pub trait HasRawBytes: Packet + Sized {
+    /// Returns the content of the packet with its [fixups](Packet::fixup_content) applied
+    /// or `None` if it does not place any data into the packet channel
+    fn raw_bytes(&self) -> Option<Vec<u8>> {
+        let mut buffer = vec![0; 4096];
+        
+        loop {
+            let len = self.serialize_content(&mut buffer)?;
+            
+            if len < buffer.len() {
+                buffer.truncate(len);
+                self.fixup_content(&mut buffer);
+                return Some(buffer);
+            }
+            
+            buffer.resize(buffer.len() * 2, 0);
+        }
+    }
+    
+    /// Creates a packet from its properties. `data` is `None` for packets without content,
+    /// in which case `connection` has no meaning.
+    /// The new packet must serialize to `data` but may be structured differently than the original packet.
+    fn from_raw_bytes(connection: usize, terminates_group: bool, data: Option<&[u8]>) -> Result<Self, Error>;
+}
+
+pub(crate) const TEXT_HEADER: &str = "# dragonfly input v1";
+
+pub(crate) fn escape(data: &[u8], out: &mut String) {
+    for (i, byte) in data.iter().enumerate() {
+        /* Trailing spaces would get lost in most editors */
+        let trailing_space = *byte == b' ' && i + 1 == data.len();
+        
+        if (byte.is_ascii_graphic() && *byte != b'\\') || (*byte == b' ' && !trailing_space) {
+            out.push(*byte as char);
+        } else {
+            write!(out, "\\x{:02x}", byte).unwrap();
+        }
+    }
+}
+
+fn unescape(data: &str, line: usize) -> Result<Vec<u8>, Error> {
+    let data = data.as_bytes();
+    let mut ret = Vec::with_capacity(data.len());
+    let mut i = 0;
+    
+    while i < data.len() {
+        if data[i] == b'\\' {
+            let byte = data.get(i + 1..i + 4)
+                .filter(|x| x[0] == b'x')
+                .and_then(|x| std::str::from_utf8(&x[1..]).ok())
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .ok_or_else(|| Error::illegal_argument(format!("Line {}: invalid escape sequence", line)))?;
+            ret.push(byte);
+            i += 4;
+        } else {
+            ret.push(data[i]);
+            i += 1;
+        }
+    }
+    
+    Ok(ret)
+}
+
+impl<P> DragonflyInput<P>
+where
+    P: HasRawBytes,
+{
+    /// Renders the input in a human-readable format with one packet per line:
+    /// `<connection>:<flags>:<data>`.
+    /// The connection is `-` for packets without content. The flag `s` marks packets that terminate a group.
+    /// Non-printable bytes and backslashes in the data are escaped as `\xHH`.
+    /// Only the serialized content of the packets is stored, see [`HasRawBytes`].
+    /// Inputs that [skip their fixups](DragonflyInput::set_skip_fixups) start with the line `!skip-fixups`.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", TEXT_HEADER);
+        
+        if self.skip_fixups() {
+            text.push_str("!skip-fixups\n");
+        }
+        
+        for packet in self.packets() {
+            let flags = if packet.terminates_group() { "s" } else { "" };
+            
+            match packet.raw_bytes() {
+                Some(data) => {
+                    write!(&mut text, "{}:{}:", packet.connection(), flags).unwrap();
+                    escape(&data, &mut text);
+                },
+                None => write!(&mut text, "-:{}:", flags).unwrap(),
+            }
+            
+            text.push('\n');
+        }
+        
+        text
+    }
+    
+    /// Parses the format of [`DragonflyInput::to_text`]. Empty lines and lines starting with `#` are ignored.
+    pub fn from_text(text: &str) -> Result<Self, Error> {
+        let mut packets = Vec::new();
+        let mut skip_fixups = false;
+        
+        for (i, line) in text.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            
+            if line == "!skip-fixups" {
+                skip_fixups = true;
+                continue;
+            }
+            
+            let mut fields = line.splitn(3, ':');
+            let (Some(connection), Some(flags), Some(data)) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(Error::illegal_argument(format!("Line {}: expected <connection>:<flags>:<data>", i + 1)));
+            };
+            
+            let mut terminates_group = false;
+            
+            for flag in flags.chars() {
+                match flag {
+                    's' | 'S' => terminates_group = true,
+                    _ => return Err(Error::illegal_argument(format!("Line {}: invalid flag {:?}", i + 1, flag))),
+                }
+            }
+            
+            let packet = if connection == "-" {
+                P::from_raw_bytes(0, terminates_group, None)?
+            } else {
+                let connection = connection.parse::<usize>().map_err(|_| Error::illegal_argument(format!("Line {}: invalid connection {:?}", i + 1, connection)))?;
+                P::from_raw_bytes(connection, terminates_group, Some(&unescape(data, i + 1)?))?
+            };
+            
+            packets.push(packet);
+        }
+        
+        let mut input = Self::new(packets);
+        input.set_skip_fixups(skip_fixups);
+        Ok(input)
+    }
+    
+    pub fn to_text_file<Q: AsRef<Path>>(&self, path: Q) -> Result<(), Error> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+    
+    pub fn from_text_file<Q: AsRef<Path>>(path: Q) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_text(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::RawPacket;
+    
+    #[test]
+    fn round_trip() {
+        let input = DragonflyInput::new(vec![
+            RawPacket::new(0, b"USER ftp\r\n".to_vec(), false),
+            RawPacket::new(1, b"a\\b:c\x00\xff ".to_vec(), true),
+            RawPacket::new(0, Vec::new(), true),
+        ]);
+        let text = input.to_text();
+        println!("{}", text);
+        assert!(text.contains("\n0::USER ftp\\x0d\\x0a\n"));
+        assert!(text.contains("\n1:s:a\\x5cb:c\\x00\\xff\\x20\n"));
+        
+        let parsed = DragonflyInput::<RawPacket>::from_text(&text).unwrap();
+        assert_eq!(parsed.to_text(), text);
+        
+        for (a, b) in input.packets().iter().zip(parsed.packets()) {
+            assert_eq!(a.data(), b.data());
+            assert_eq!(a.connection(), b.connection());
+            assert_eq!(a.terminates_group(), b.terminates_group());
+        }
+        
+        let mut input = input;
+        input.set_skip_fixups(true);
+        let parsed = DragonflyInput::<RawPacket>::from_text(&input.to_text()).unwrap();
+        assert!(parsed.skip_fixups());
+        
+        assert!(DragonflyInput::<RawPacket>::from_text("0::\\x4").is_err());
+        assert!(DragonflyInput::<RawPacket>::from_text("0:x:a").is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use libafl::prelude::ExitKind;
+use std::{
+    fmt::Write as _,
+    time::Duration,
+};
+use crate::components::{
+    DragonflyInput,
+    HasRawBytes,
+    Response,
+    text::escape,
+};
+
+pub const TRACE_CHANNEL_SIZE: usize = 64 * 1024;
+pub const TRACE_CHANNEL_ENV_VAR: &str = "__LIBDRAGONFLY_TRACE_CHANNEL";
+
+const CHANNEL_HEADER_SIZE: usize = 16;
+const EVENT_SIZE: usize = 24;
+
+/// Reads the current value of the clock that libdragonfly uses for its timestamps
+pub(crate) fn monotonic_now() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+/// The moment the target started reading a data packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ReadEvent {
+    packet: usize,
+    conn: usize,
+    fd: i32,
+    timestamp: u64,
+}
+
+pub(crate) fn reset_trace_channel(channel: &mut [u8]) {
+    let capacity = channel.len() as u64;
+    channel[0..8].copy_from_slice(&capacity.to_ne_bytes());
+    channel[8..16].copy_from_slice(&0u64.to_ne_bytes());
+}
+
+/// Returns the first read event of every data packet in chronological order
+pub(crate) fn parse_trace_channel(channel: &[u8]) -> Vec<ReadEvent> {
+    let used = u64::from_ne_bytes(channel[8..16].try_into().unwrap()) as usize;
+    let end = std::cmp::min(CHANNEL_HEADER_SIZE.saturating_add(used), channel.len());
+    let mut cursor = CHANNEL_HEADER_SIZE;
+    let mut events: Vec<ReadEvent> = Vec::new();
+    
+    while cursor + EVENT_SIZE <= end {
+        let event = &channel[cursor..cursor + EVENT_SIZE];
+        let packet = u32::from_ne_bytes(event[0..4].try_into().unwrap()) as usize;
+        
+        if !events.iter().any(|x| x.packet == packet) {
+            events.push(ReadEvent {
+                packet,
+                conn: u32::from_ne_bytes(event[4..8].try_into().unwrap()) as usize,
+                fd: i32::from_ne_bytes(event[8..12].try_into().unwrap()),
+                timestamp: u64::from_ne_bytes(event[16..24].try_into().unwrap()),
+            });
+        }
+        
+        cursor += EVENT_SIZE;
+    }
+    
+    events
+}
+
+/// What happened to a single data packet during a replay
+#[derive(Clone, Debug)]
+pub struct PacketTrace {
+    packet: usize,
+    connection: usize,
+    data: Vec<u8>,
+    fd: Option<i32>,
+    elapsed: Option<Duration>,
+    responses: Vec<Response>,
+}
+
+impl PacketTrace {
+    /// Index of the packet in the input
+    pub fn packet(&self) -> usize {
+        self.packet
+    }
+    
+    pub fn connection(&self) -> usize {
+        self.connection
+    }
+    
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    
+    /// The file descriptor the target read the packet from. `None` if the packet was never delivered.
+    pub fn fd(&self) -> Option<i32> {
+        self.fd
+    }
+    
+    /// Time between the first read of this packet and the first read of the next packet or the end of the execution
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.elapsed
+    }
+    
+    /// Everything the target wrote after reading this packet
+    pub fn responses(&self) -> &[Response] {
+        &self.responses
+    }
+}
+
+/// The result of replaying an input with tracing enabled
+#[derive(Clone, Debug)]
+pub struct ReplayTrace {
+    exit_kind: ExitKind,
+    duration: Duration,
+    banner: Vec<Response>,
+    packets: Vec<PacketTrace>,
+}
+
+impl ReplayTrace {
+    /// Assigns the read events and responses to the data packets of the input.
+    /// `start` and `end` are timestamps of [`monotonic_now`] around the execution.
+    pub(crate) fn new<P>(input: &DragonflyInput<P>, exit_kind: ExitKind, events: &[ReadEvent], responses: &[Response], start: u64, end: u64) -> Self
+    where
+        P: HasRawBytes,
+    {
+        let mut banner = Vec::new();
+        let mut packets: Vec<PacketTrace> = input.data_packets().into_iter().map(|i| {
+            let packet = &input.packets()[i];
+            
+            PacketTrace {
+                packet: i,
+                connection: packet.connection(),
+                data: packet.raw_bytes().unwrap_or_default(),
+                fd: None,
+                elapsed: None,
+                responses: Vec::new(),
+            }
+        }).collect();
+        
+        for (i, event) in events.iter().enumerate() {
+            let Some(packet) = packets.get_mut(event.packet) else {
+                continue;
+            };
+            let next = events.get(i + 1).map(|x| x.timestamp).unwrap_or(end);
+            packet.fd = Some(event.fd);
+            packet.connection = event.conn;
+            packet.elapsed = Some(Duration::from_nanos(next.saturating_sub(event.timestamp)));
+        }
+        
+        for response in responses {
+            match response.packet().and_then(|x| packets.get_mut(x)) {
+                Some(packet) => packet.responses.push(response.clone()),
+                None => banner.push(response.clone()),
+            }
+        }
+        
+        Self {
+            exit_kind,
+            duration: Duration::from_nanos(end.saturating_sub(start)),
+            banner,
+            packets,
+        }
+    }
+    
+    pub fn exit_kind(&self) -> &ExitKind {
+        &self.exit_kind
+    }
+    
+    /// Wall-clock time of the whole execution
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+    
+    /// Responses that were written before the target read any data
+    pub fn banner(&self) -> &[Response] {
+        &self.banner
+    }
+    
+    /// One entry per data packet of the input
+    pub fn packets(&self) -> &[PacketTrace] {
+        &self.packets
+    }
+    
+    /// Renders the trace as a transcript that interleaves requests (`>>`) with responses (`<<`).
+    /// Data is escaped like in the text format of inputs but split into lines after each `\n`.
+    pub fn transcript(&self) -> String {
+        let mut text = format!("# dragonfly transcript\n# exit: {:?}, duration: {:?}\n", self.exit_kind, self.duration);
+        
+        write_responses(&mut text, &self.banner);
+        
+        for packet in &self.packets {
+            write!(&mut text, ">> packet {} conn {}", packet.packet, packet.connection).unwrap();
+            
+            match (packet.fd, packet.elapsed) {
+                (Some(fd), Some(elapsed)) => writeln!(&mut text, " fd {} took {:?}", fd, elapsed).unwrap(),
+                _ => text.push_str(" not delivered\n"),
+            }
+            
+            write_data(&mut text, &packet.data);
+            write_responses(&mut text, &packet.responses);
+        }
+        
+        text
+    }
+}
+
+fn write_responses(text: &mut String, responses: &[Response]) {
+    for response in responses {
+        writeln!(text, "<< conn {}", response.connection()).unwrap();
+        write_data(text, response.data());
+    }
+}
+
+fn write_data(text: &mut String, data: &[u8]) {
+    for line in data.split_inclusive(|x| *x == b'\n') {
+        text.push_str("   ");
+        escape(line, text);
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        components::ResponseObserver,
+        pcap::RawPacket,
+    };
+    
+    fn write_event(channel: &mut [u8], packet: u32, conn: u32, fd: i32, timestamp: u64) {
+        let used = u64::from_ne_bytes(channel[8..16].try_into().unwrap()) as usize;
+        let cursor = CHANNEL_HEADER_SIZE + used;
+        channel[cursor..cursor + 4].copy_from_slice(&packet.to_ne_bytes());
+        channel[cursor + 4..cursor + 8].copy_from_slice(&conn.to_ne_bytes());
+        channel[cursor + 8..cursor + 12].copy_from_slice(&fd.to_ne_bytes());
+        channel[cursor + 16..cursor + 24].copy_from_slice(&timestamp.to_ne_bytes());
+        channel[8..16].copy_from_slice(&((used + EVENT_SIZE) as u64).to_ne_bytes());
+    }
+    
+    fn write_response(channel: &mut [u8], conn: u32, packet: u32, data: &[u8]) {
+        let used = u64::from_ne_bytes(channel[8..16].try_into().unwrap()) as usize;
+        let cursor = CHANNEL_HEADER_SIZE + used;
+        channel[cursor..cursor + 4].copy_from_slice(&conn.to_ne_bytes());
+        channel[cursor + 4..cursor + 8].copy_from_slice(&packet.to_ne_bytes());
+        channel[cursor + 8..cursor + 16].copy_from_slice(&(data.len() as u64).to_ne_bytes());
+        channel[cursor + 16..cursor + 16 + data.len()].copy_from_slice(data);
+        let used = used + 16 + data.len().div_ceil(8) * 8;
+        channel[8..16].copy_from_slice(&(used as u64).to_ne_bytes());
+    }
+    
+    #[test]
+    fn transcript() {
+        let input = DragonflyInput::new(vec![
+            RawPacket::new(0, b"USER ftp\r\n".to_vec(), true),
+            RawPacket::new(1, b"data".to_vec(), false),
+            RawPacket::new(0, b"QUIT\r\n".to_vec(), false),
+        ]);
+        
+        let mut channel = vec![0u8; 256];
+        reset_trace_channel(&mut channel);
+        write_event(&mut channel, 0, 0, 5, 1000);
+        write_event(&mut channel, 1, 1, 7, 3000);
+        write_event(&mut channel, 0, 0, 5, 3500);
+        let events = parse_trace_channel(&channel);
+        assert_eq!(events.len(), 2);
+        
+        let mut buffer = vec![0u8; 256];
+        let mut observer = ResponseObserver::new("responses", &mut buffer);
+        observer.reset_channel();
+        write_response(observer.channel_mut(), 0, u32::MAX, b"220 ready\r\n");
+        write_response(observer.channel_mut(), 0, 0, b"331 password\r\n");
+        observer.parse_channel();
+        let trace = ReplayTrace::new(&input, ExitKind::Ok, &events, observer.responses(), 500, 10000);
+        
+        assert_eq!(trace.packets().len(), 3);
+        assert_eq!(trace.packets()[0].fd(), Some(5));
+        assert_eq!(trace.packets()[0].elapsed(), Some(Duration::from_nanos(2000)));
+        assert_eq!(trace.packets()[1].packet(), 1);
+        assert_eq!(trace.packets()[1].elapsed(), Some(Duration::from_nanos(7000)));
+        assert_eq!(trace.packets()[2].fd(), None);
+        
+        let transcript = trace.transcript();
+        println!("{}", transcript);
+        assert!(transcript.contains(">> packet 0 conn 0 fd 5 took 2µs\n   USER ftp\\x0d\\x0a\n"));
+        assert!(transcript.contains("<< conn 0\n   220 ready\\x0d\\x0a\n>> packet 0"));
+        assert!(transcript.contains("USER ftp\\x0d\\x0a\n<< conn 0\n   331 password"));
+        assert!(transcript.contains(">> packet 2 conn 0 not delivered\n"));
+    }
+}
@@ -1,5 +1,7 @@
 pub mod tokens;
+pub mod binary;
 pub mod components;
+pub mod pcap;
 
 #[cfg(test)]
 mod tests;
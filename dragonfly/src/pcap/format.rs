@@ -0,0 +1,159 @@
+use libafl::prelude::Error;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+/// A captured frame together with the link type of the interface it was captured on
+#[derive(Debug)]
+pub(crate) struct Frame<'a> {
+    pub(crate) linktype: u32,
+    pub(crate) data: &'a [u8],
+}
+
+fn malformed(what: &str) -> Error {
+    Error::illegal_argument(format!("Malformed capture: {}", what))
+}
+
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], Error> {
+        self.data.get(offset..offset.checked_add(len).ok_or_else(|| malformed("length overflow"))?).ok_or_else(|| malformed("unexpected end of file"))
+    }
+    
+    fn u16(&self, offset: usize) -> Result<u16, Error> {
+        let bytes = self.bytes(offset, 2)?.try_into().unwrap();
+        
+        if self.big_endian {
+            Ok(u16::from_be_bytes(bytes))
+        } else {
+            Ok(u16::from_le_bytes(bytes))
+        }
+    }
+    
+    fn u32(&self, offset: usize) -> Result<u32, Error> {
+        let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+        
+        if self.big_endian {
+            Ok(u32::from_be_bytes(bytes))
+        } else {
+            Ok(u32::from_le_bytes(bytes))
+        }
+    }
+}
+
+fn read_pcap(data: &[u8], big_endian: bool) -> Result<Vec<Frame<'_>>, Error> {
+    let reader = Reader {
+        data,
+        big_endian,
+    };
+    let linktype = reader.u32(20)?;
+    let mut cursor = 24;
+    let mut frames = Vec::new();
+    
+    while cursor < data.len() {
+        let len = reader.u32(cursor + 8)? as usize;
+        frames.push(Frame {
+            linktype,
+            data: reader.bytes(cursor + 16, len)?,
+        });
+        cursor += 16 + len;
+    }
+    
+    Ok(frames)
+}
+
+fn interface(interfaces: &[u32], id: usize) -> Result<u32, Error> {
+    interfaces.get(id).copied().ok_or_else(|| malformed("unknown interface"))
+}
+
+fn read_pcapng(data: &[u8]) -> Result<Vec<Frame<'_>>, Error> {
+    let mut reader = Reader {
+        data,
+        big_endian: false,
+    };
+    let mut interfaces = Vec::new();
+    let mut frames = Vec::new();
+    let mut cursor = 0;
+    
+    while cursor < data.len() {
+        /* Every section may have a different byte order */
+        if reader.u32(cursor)? == PCAPNG_SECTION_HEADER {
+            let magic = u32::from_le_bytes(reader.bytes(cursor + 8, 4)?.try_into().unwrap());
+            
+            if magic == PCAPNG_BYTE_ORDER_MAGIC {
+                reader.big_endian = false;
+            } else if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC {
+                reader.big_endian = true;
+            } else {
+                return Err(malformed("invalid byte order magic"));
+            }
+            
+            interfaces.clear();
+        }
+        
+        let typ = reader.u32(cursor)?;
+        let len = reader.u32(cursor + 4)? as usize;
+        
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(malformed("invalid block length"));
+        }
+        
+        let body = cursor + 8;
+        
+        match typ {
+            PCAPNG_INTERFACE_DESCRIPTION => interfaces.push(reader.u16(body)? as u32),
+            PCAPNG_ENHANCED_PACKET => frames.push(Frame {
+                linktype: interface(&interfaces, reader.u32(body)? as usize)?,
+                data: reader.bytes(body + 20, reader.u32(body + 12)? as usize)?,
+            }),
+            PCAPNG_PACKET => frames.push(Frame {
+                linktype: interface(&interfaces, reader.u16(body)? as usize)?,
+                data: reader.bytes(body + 20, reader.u32(body + 12)? as usize)?,
+            }),
+            PCAPNG_SIMPLE_PACKET => {
+                if len < 16 {
+                    return Err(malformed("simple packet block too short"));
+                }
+                
+                frames.push(Frame {
+                    linktype: interface(&interfaces, 0)?,
+                    data: reader.bytes(body + 4, std::cmp::min(reader.u32(body)? as usize, len - 16))?,
+                });
+            },
+            _ => {},
+        }
+        
+        cursor += len;
+    }
+    
+    Ok(frames)
+}
+
+/// Splits a pcap or pcapng file into its frames
+pub(crate) fn read_frames(data: &[u8]) -> Result<Vec<Frame<'_>>, Error> {
+    let Some(magic) = data.get(..4) else {
+        return Err(malformed("file too short"));
+    };
+    let magic: [u8; 4] = magic.try_into().unwrap();
+    
+    if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+        read_pcapng(data)
+    } else if [PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(&u32::from_le_bytes(magic)) {
+        read_pcap(data, false)
+    } else if [PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(&u32::from_be_bytes(magic)) {
+        read_pcap(data, true)
+    } else {
+        Err(Error::illegal_argument("Not a pcap or pcapng file"))
+    }
+}
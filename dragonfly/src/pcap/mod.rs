@@ -0,0 +1,507 @@
+//! Turns network captures into [`DragonflyInput`]s
+mod format;
+mod net;
+
+use libafl::prelude::{
+    Error,
+    Input,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    collections::BTreeMap,
+    hash::Hash,
+    net::IpAddr,
+    path::Path,
+};
+use crate::components::{
+    DragonflyInput,
+    HasConnection,
+    HasGroupBoundary,
+    HasRawBytes,
+    Packet,
+};
+use net::{
+    Segment,
+    Transport,
+    TCP_ACK,
+    TCP_SYN,
+};
+
+/// A client-to-server segment extracted from a capture
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedPacket {
+    connection: usize,
+    data: Vec<u8>,
+    terminates_group: bool,
+}
+
+impl CapturedPacket {
+    /// The index of the connection in order of appearance
+    pub fn connection(&self) -> usize {
+        self.connection
+    }
+    
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    
+    /// Whether the server replied after this packet
+    pub fn terminates_group(&self) -> bool {
+        self.terminates_group
+    }
+}
+
+/// A packet that simply carries the bytes of a [`CapturedPacket`]
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+pub struct RawPacket {
+    connection: usize,
+    data: Vec<u8>,
+    terminates_group: bool,
+}
+
+impl RawPacket {
+    pub fn new(connection: usize, data: Vec<u8>, terminates_group: bool) -> Self {
+        Self {
+            connection,
+            data,
+            terminates_group,
+        }
+    }
+    
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl From<CapturedPacket> for RawPacket {
+    fn from(packet: CapturedPacket) -> Self {
+        Self::new(packet.connection, packet.data, packet.terminates_group)
+    }
+}
+
+impl Packet for RawPacket {
+    fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
+        let len = std::cmp::min(buffer.len(), self.data.len());
+        buffer[..len].copy_from_slice(&self.data[..len]);
+        Some(len)
+    }
+    
+    fn connection(&self) -> usize {
+        self.connection
+    }
+    
+    fn terminates_group(&self) -> bool {
+        self.terminates_group
+    }
+}
+
+impl HasConnection for RawPacket {
+    fn set_connection(&mut self, connection: usize) -> bool {
+        self.connection = connection;
+        true
+    }
+}
+
+impl HasGroupBoundary for RawPacket {
+    fn set_terminates_group(&mut self, terminates_group: bool) -> bool {
+        self.terminates_group = terminates_group;
+        true
+    }
+}
+
+impl HasRawBytes for RawPacket {
+    fn raw_bytes(&self) -> Option<Vec<u8>> {
+        Some(self.data.clone())
+    }
+    
+    fn from_raw_bytes(connection: usize, terminates_group: bool, data: Option<&[u8]>) -> Result<Self, Error> {
+        match data {
+            Some(data) => Ok(Self::new(connection, data.to_vec(), terminates_group)),
+            None => Err(Error::illegal_argument("RawPacket must have content")),
+        }
+    }
+}
+
+type Endpoint = (IpAddr, u16);
+
+#[derive(Debug)]
+struct Stream {
+    client: Endpoint,
+    server: Endpoint,
+    tcp: bool,
+    connection: Option<usize>,
+    /// Next expected sequence number of the client
+    next_seq: Option<u32>,
+    /// Out-of-order segments of the client
+    pending: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Reads pcap and pcapng files, reassembles the TCP streams and collects UDP datagrams.
+/// Every client-to-server segment becomes a packet and a group ends whenever the server replies.
+#[derive(Debug, Clone)]
+pub struct PcapImporter {
+    server_port: Option<u16>,
+    tcp: bool,
+    udp: bool,
+}
+
+impl PcapImporter {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            server_port: None,
+            tcp: true,
+            udp: true,
+        }
+    }
+    
+    /// Only import traffic to and from the given server port.
+    /// Without a server port, the client is the side that sent the first SYN or the first datagram.
+    pub fn server_port(mut self, port: u16) -> Self {
+        self.server_port = Some(port);
+        self
+    }
+    
+    pub fn tcp(mut self, tcp: bool) -> Self {
+        self.tcp = tcp;
+        self
+    }
+    
+    pub fn udp(mut self, udp: bool) -> Self {
+        self.udp = udp;
+        self
+    }
+    
+    /// Extracts all client-to-server packets from the content of a capture file
+    pub fn import(&self, capture: &[u8]) -> Result<Vec<CapturedPacket>, Error> {
+        let mut streams: Vec<Stream> = Vec::new();
+        let mut packets: Vec<CapturedPacket> = Vec::new();
+        let mut connections = 0;
+        
+        for frame in format::read_frames(capture)? {
+            let segment = match net::parse_frame(frame.linktype, frame.data) {
+                Ok(Some(segment)) => segment,
+                Ok(None) => continue,
+                Err(linktype) => return Err(Error::illegal_argument(format!("Unsupported link type in capture: {}", linktype))),
+            };
+            
+            let tcp = matches!(segment.transport, Transport::Tcp { .. });
+            
+            if (tcp && !self.tcp) || (!tcp && !self.udp) {
+                continue;
+            }
+            
+            if let Some(port) = self.server_port {
+                if segment.src.1 != port && segment.dst.1 != port {
+                    continue;
+                }
+            }
+            
+            let syn = matches!(segment.transport, Transport::Tcp { flags, .. } if flags & TCP_SYN != 0 && flags & TCP_ACK == 0);
+            let idx = match streams.iter().position(|x| x.tcp == tcp && ((x.client, x.server) == (segment.src, segment.dst) || (x.client, x.server) == (segment.dst, segment.src))) {
+                /* A new handshake on a reused 4-tuple starts a new connection */
+                Some(idx) if syn => {
+                    streams[idx] = self.new_stream(&segment);
+                    idx
+                },
+                Some(idx) => idx,
+                None => {
+                    streams.push(self.new_stream(&segment));
+                    streams.len() - 1
+                },
+            };
+            let stream = &mut streams[idx];
+            
+            /* A reply of the server ends the current group */
+            if segment.src == stream.server {
+                if !segment.payload.is_empty() {
+                    if let Some(last) = packets.last_mut() {
+                        last.terminates_group = true;
+                    }
+                }
+                
+                continue;
+            }
+            
+            let mut emit = |data: Vec<u8>| {
+                let connection = *stream.connection.get_or_insert_with(|| {
+                    connections += 1;
+                    connections - 1
+                });
+                packets.push(CapturedPacket {
+                    connection,
+                    data,
+                    terminates_group: false,
+                });
+            };
+            
+            match segment.transport {
+                Transport::Udp => {
+                    emit(segment.payload.to_vec());
+                },
+                Transport::Tcp { seq, flags } => {
+                    if flags & TCP_SYN != 0 {
+                        stream.next_seq = Some(seq.wrapping_add(1));
+                        stream.pending.clear();
+                    }
+                    
+                    if segment.payload.is_empty() {
+                        continue;
+                    }
+                    
+                    let next_seq = *stream.next_seq.get_or_insert(seq);
+                    stream.pending.entry(seq).or_insert_with(|| segment.payload.to_vec());
+                    
+                    let (data, next_seq) = reassemble(&mut stream.pending, next_seq);
+                    stream.next_seq = Some(next_seq);
+                    
+                    for data in data {
+                        emit(data);
+                    }
+                },
+            }
+        }
+        
+        Ok(packets)
+    }
+    
+    fn new_stream(&self, segment: &Segment) -> Stream {
+        let from_client = match (self.server_port, segment.transport) {
+            (Some(port), _) => segment.dst.1 == port,
+            (None, Transport::Tcp { flags, .. }) if flags & TCP_SYN != 0 => flags & TCP_ACK == 0,
+            (None, _) => true,
+        };
+        let (client, server) = if from_client {
+            (segment.src, segment.dst)
+        } else {
+            (segment.dst, segment.src)
+        };
+        
+        Stream {
+            client,
+            server,
+            tcp: matches!(segment.transport, Transport::Tcp { .. }),
+            connection: None,
+            next_seq: None,
+            pending: BTreeMap::new(),
+        }
+    }
+    
+    /// Like [`PcapImporter::import`] but reads the capture from a file
+    pub fn import_file<Q: AsRef<Path>>(&self, path: Q) -> Result<Vec<CapturedPacket>, Error> {
+        let capture = std::fs::read(path)?;
+        self.import(&capture)
+    }
+    
+    /// Converts a capture file into an input using the given packet constructor
+    pub fn input_from_file<Q, P, F>(&self, path: Q, constructor: F) -> Result<DragonflyInput<P>, Error>
+    where
+        Q: AsRef<Path>,
+        P: Packet,
+        F: FnMut(CapturedPacket) -> P,
+    {
+        let packets = self.import_file(path)?;
+        Ok(DragonflyInput::new(packets.into_iter().map(constructor).collect()))
+    }
+    
+    /// Converts each capture file into an input and writes it into `output_dir`.
+    /// Returns the number of inputs written.
+    pub fn convert<IT, Q, R, P, F>(&self, captures: IT, output_dir: R, mut constructor: F) -> Result<usize, Error>
+    where
+        IT: IntoIterator<Item = Q>,
+        Q: AsRef<Path>,
+        R: AsRef<Path>,
+        P: Packet + std::fmt::Debug + Serialize + for<'a> Deserialize<'a> + Clone + Hash,
+        F: FnMut(CapturedPacket) -> P,
+    {
+        let output_dir = output_dir.as_ref();
+        std::fs::create_dir_all(output_dir)?;
+        let mut count = 0;
+        
+        for capture in captures {
+            let input = self.input_from_file(capture, &mut constructor)?;
+            
+            if input.packets().is_empty() {
+                continue;
+            }
+            
+            input.to_file(output_dir.join(input.generate_name(count)))?;
+            count += 1;
+        }
+        
+        Ok(count)
+    }
+}
+
+/// Removes all segments from `pending` that continue the stream at `seq`, trimming retransmitted bytes.
+/// Also returns the next expected sequence number.
+fn reassemble(pending: &mut BTreeMap<u32, Vec<u8>>, mut seq: u32) -> (Vec<Vec<u8>>, u32) {
+    let mut ret = Vec::new();
+    
+    loop {
+        let mut progress = false;
+        let keys: Vec<u32> = pending.keys().copied().collect();
+        
+        for key in keys {
+            let offset = key.wrapping_sub(seq) as i32;
+            
+            if offset > 0 {
+                continue;
+            }
+            
+            let data = pending.remove(&key).unwrap();
+            let skip = offset.unsigned_abs() as usize;
+            
+            /* Drop retransmissions of data we already have */
+            if skip < data.len() {
+                seq = seq.wrapping_add((data.len() - skip) as u32);
+                ret.push(data[skip..].to_vec());
+                progress = true;
+            }
+        }
+        
+        if !progress {
+            break;
+        }
+    }
+    
+    (ret, seq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn tcp_frame(src_port: u16, dst_port: u16, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 14];
+        frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        
+        let mut ip = vec![0u8; 20];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        ip[9] = 6;
+        ip[12..16].copy_from_slice(&[10, 0, 0, if src_port == 21 { 1 } else { 2 }]);
+        ip[16..20].copy_from_slice(&[10, 0, 0, if dst_port == 21 { 1 } else { 2 }]);
+        frame.extend(ip);
+        
+        let mut tcp = vec![0u8; 20];
+        tcp[0..2].copy_from_slice(&src_port.to_be_bytes());
+        tcp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        tcp[4..8].copy_from_slice(&seq.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = flags;
+        frame.extend(tcp);
+        frame.extend_from_slice(payload);
+        frame
+    }
+    
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend(0xa1b2c3d4u32.to_le_bytes());
+        file.extend(2u16.to_le_bytes());
+        file.extend(4u16.to_le_bytes());
+        file.extend([0; 12]);
+        file.extend(1u32.to_le_bytes());
+        
+        for frame in frames {
+            file.extend([0; 8]);
+            file.extend((frame.len() as u32).to_le_bytes());
+            file.extend((frame.len() as u32).to_le_bytes());
+            file.extend(frame);
+        }
+        
+        file
+    }
+    
+    fn pcapng(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = Vec::new();
+        
+        /* Section header */
+        file.extend(0x0a0d0d0au32.to_be_bytes());
+        file.extend(28u32.to_be_bytes());
+        file.extend(0x1a2b3c4du32.to_be_bytes());
+        file.extend([0, 1, 0, 0]);
+        file.extend([0xff; 8]);
+        file.extend(28u32.to_be_bytes());
+        
+        /* Interface description */
+        file.extend(1u32.to_be_bytes());
+        file.extend(20u32.to_be_bytes());
+        file.extend(1u16.to_be_bytes());
+        file.extend([0; 6]);
+        file.extend(20u32.to_be_bytes());
+        
+        for frame in frames {
+            let padded = frame.len().div_ceil(4) * 4;
+            let len = 32 + padded as u32;
+            file.extend(6u32.to_be_bytes());
+            file.extend(len.to_be_bytes());
+            file.extend([0; 12]);
+            file.extend((frame.len() as u32).to_be_bytes());
+            file.extend((frame.len() as u32).to_be_bytes());
+            file.extend(frame);
+            file.extend(vec![0; padded - frame.len()]);
+            file.extend(len.to_be_bytes());
+        }
+        
+        file
+    }
+    
+    fn session() -> Vec<Vec<u8>> {
+        vec![
+            tcp_frame(4000, 21, 100, TCP_SYN, b""),
+            tcp_frame(21, 4000, 500, TCP_SYN | TCP_ACK, b""),
+            tcp_frame(21, 4000, 501, TCP_ACK, b"220 hi\r\n"),
+            tcp_frame(4000, 21, 101, TCP_ACK, b"USER "),
+            tcp_frame(4000, 21, 106, TCP_ACK, b"ftp\r\n"),
+            tcp_frame(4000, 21, 101, TCP_ACK, b"USER "),
+            tcp_frame(21, 4000, 509, TCP_ACK, b"331 ok\r\n"),
+            tcp_frame(4000, 21, 117, TCP_ACK, b"QUIT\r\n"),
+            tcp_frame(4000, 21, 111, TCP_ACK, b"PASS x"),
+        ]
+    }
+    
+    #[test]
+    fn import_tcp() {
+        for capture in [pcap(&session()), pcapng(&session())] {
+            let packets = PcapImporter::new().server_port(21).import(&capture).unwrap();
+            let data: Vec<&[u8]> = packets.iter().map(|x| x.data()).collect();
+            let groups: Vec<bool> = packets.iter().map(|x| x.terminates_group()).collect();
+            
+            assert_eq!(data, vec![&b"USER "[..], b"ftp\r\n", b"PASS x", b"QUIT\r\n"]);
+            assert_eq!(groups, vec![false, true, false, false]);
+            assert!(packets.iter().all(|x| x.connection() == 0));
+        }
+    }
+    
+    #[test]
+    fn reused_ports() {
+        let mut frames = session();
+        frames.push(tcp_frame(4000, 21, 900, TCP_SYN, b""));
+        frames.push(tcp_frame(4000, 21, 901, TCP_ACK, b"NOOP\r\n"));
+        
+        let packets = PcapImporter::new().server_port(21).import(&pcap(&frames)).unwrap();
+        let connections: Vec<usize> = packets.iter().map(|x| x.connection()).collect();
+        
+        assert_eq!(connections, vec![0, 0, 0, 0, 1]);
+        assert_eq!(packets[4].data(), b"NOOP\r\n");
+    }
+    
+    #[test]
+    fn truncated_blocks() {
+        /* A simple packet block that is too short to hold its original length */
+        let mut capture = pcapng(&[]);
+        capture.extend(3u32.to_be_bytes());
+        capture.extend(12u32.to_be_bytes());
+        capture.extend(12u32.to_be_bytes());
+        assert!(PcapImporter::new().import(&capture).is_err());
+        
+        /* An enhanced packet block that ends in the middle of its frame */
+        let mut capture = pcapng(&session()[..1]);
+        capture.truncate(capture.len() - 8);
+        assert!(PcapImporter::new().import(&capture).is_err());
+    }
+}
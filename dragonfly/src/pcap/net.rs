@@ -0,0 +1,156 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_RAW_OPENBSD: u32 = 14;
+const LINKTYPE_RAW_BSD: u32 = 12;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: [u16; 3] = [0x8100, 0x88a8, 0x9100];
+
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+pub(crate) const TCP_SYN: u8 = 0x02;
+pub(crate) const TCP_ACK: u8 = 0x10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Transport {
+    Tcp {
+        seq: u32,
+        flags: u8,
+    },
+    Udp,
+}
+
+/// A TCP segment or UDP datagram
+#[derive(Debug)]
+pub(crate) struct Segment<'a> {
+    pub(crate) src: (IpAddr, u16),
+    pub(crate) dst: (IpAddr, u16),
+    pub(crate) transport: Transport,
+    pub(crate) payload: &'a [u8],
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+fn parse_transport(protocol: u8, src: IpAddr, dst: IpAddr, data: &[u8]) -> Option<Segment<'_>> {
+    match protocol {
+        PROTO_TCP => {
+            let header_len = (*data.get(12)? >> 4) as usize * 4;
+            
+            if header_len < 20 {
+                return None;
+            }
+            
+            Some(Segment {
+                src: (src, be16(data, 0)?),
+                dst: (dst, be16(data, 2)?),
+                transport: Transport::Tcp {
+                    seq: be32(data, 4)?,
+                    flags: *data.get(13)?,
+                },
+                payload: data.get(header_len..)?,
+            })
+        },
+        PROTO_UDP => {
+            let len = be16(data, 4)? as usize;
+            
+            Some(Segment {
+                src: (src, be16(data, 0)?),
+                dst: (dst, be16(data, 2)?),
+                transport: Transport::Udp,
+                payload: data.get(8..len)?,
+            })
+        },
+        _ => None,
+    }
+}
+
+fn parse_ipv4(data: &[u8]) -> Option<Segment<'_>> {
+    let header_len = (*data.first()? & 0xf) as usize * 4;
+    let total_len = be16(data, 2)? as usize;
+    let fragment = be16(data, 6)?;
+    
+    /* Fragmented packets are not supported */
+    if fragment & 0x3fff != 0 || header_len < 20 {
+        return None;
+    }
+    
+    let src: [u8; 4] = data.get(12..16)?.try_into().unwrap();
+    let dst: [u8; 4] = data.get(16..20)?.try_into().unwrap();
+    let payload = data.get(header_len..std::cmp::min(total_len, data.len()))?;
+    
+    parse_transport(data[9], IpAddr::V4(Ipv4Addr::from(src)), IpAddr::V4(Ipv4Addr::from(dst)), payload)
+}
+
+fn parse_ipv6(data: &[u8]) -> Option<Segment<'_>> {
+    let payload_len = be16(data, 4)? as usize;
+    let src: [u8; 16] = data.get(8..24)?.try_into().unwrap();
+    let dst: [u8; 16] = data.get(24..40)?.try_into().unwrap();
+    let mut protocol = *data.get(6)?;
+    let mut cursor = 40;
+    let end = std::cmp::min(40 + payload_len, data.len());
+    
+    /* Skip extension headers */
+    while matches!(protocol, 0 | 43 | 60) {
+        protocol = *data.get(cursor)?;
+        cursor += (*data.get(cursor + 1)? as usize + 1) * 8;
+    }
+    
+    parse_transport(protocol, IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)), data.get(cursor..end)?)
+}
+
+fn parse_ip(data: &[u8]) -> Option<Segment<'_>> {
+    match *data.first()? >> 4 {
+        4 => parse_ipv4(data),
+        6 => parse_ipv6(data),
+        _ => None,
+    }
+}
+
+fn parse_ethertype(ethertype: u16, data: &[u8]) -> Option<Segment<'_>> {
+    match ethertype {
+        ETHERTYPE_IPV4 => parse_ipv4(data),
+        ETHERTYPE_IPV6 => parse_ipv6(data),
+        _ => None,
+    }
+}
+
+/// Extracts the TCP or UDP payload of a frame. Returns `None` for all other frames.
+/// Returns `Err` if the link type is not supported.
+pub(crate) fn parse_frame(linktype: u32, data: &[u8]) -> Result<Option<Segment<'_>>, u32> {
+    let segment = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut cursor = 12;
+            let mut ethertype = be16(data, cursor);
+            
+            while ethertype.is_some_and(|x| ETHERTYPE_VLAN.contains(&x)) {
+                cursor += 4;
+                ethertype = be16(data, cursor);
+            }
+            
+            ethertype.and_then(|x| parse_ethertype(x, data.get(cursor + 2..)?))
+        },
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..).and_then(parse_ip),
+        LINKTYPE_RAW | LINKTYPE_RAW_BSD | LINKTYPE_RAW_OPENBSD | LINKTYPE_IPV4 | LINKTYPE_IPV6 => parse_ip(data),
+        LINKTYPE_LINUX_SLL => be16(data, 14).and_then(|x| parse_ethertype(x, data.get(16..)?)),
+        LINKTYPE_LINUX_SLL2 => be16(data, 0).and_then(|x| parse_ethertype(x, data.get(20..)?)),
+        _ => return Err(linktype),
+    };
+    
+    Ok(segment)
+}
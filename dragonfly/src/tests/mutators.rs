@@ -15,8 +15,9 @@ use libafl::{
 use libafl_bolts::{current_nanos, rands::StdRand, tuples::tuple_list};
 use crate::{components::{DragonflyInput, PacketCopyMutator, PacketDeleteMutator, PacketRepeatMutator, PacketSwapMutator, TokenStreamMutator, PacketContentMutator}, tokens::TokenStream};
 
-static mut SIGNALS: [u8; 16] = [0; 16];
-static mut SIGNALS_PTR: *mut u8 = unsafe { SIGNALS.as_mut_ptr() };
+const SIGNALS_LEN: usize = 16;
+static mut SIGNALS: [u8; SIGNALS_LEN] = [0; SIGNALS_LEN];
+static mut SIGNALS_PTR: *mut u8 = std::ptr::addr_of_mut!(SIGNALS) as *mut u8;
 
 #[test]
 fn simple_harness() {
@@ -30,7 +31,7 @@ fn simple_harness() {
     };
 
     // Create an observation channel using the signals map
-    let observer = unsafe { StdMapObserver::from_mut_ptr("signals", SIGNALS_PTR, SIGNALS.len()) };
+    let observer = unsafe { StdMapObserver::from_mut_ptr("signals", SIGNALS_PTR, SIGNALS_LEN) };
 
     // Feedback to rate the interestingness of an input
     let mut feedback = MaxMapFeedback::new(&observer);
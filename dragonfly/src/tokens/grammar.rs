@@ -0,0 +1,608 @@
+use libafl_bolts::{
+    impl_serdeany,
+    prelude::{Rand, StdRand},
+};
+use libafl::prelude::{Error, HasRand, HasMetadata};
+use serde::{Serialize, Deserialize};
+use std::{
+    path::Path,
+    str::FromStr,
+};
+use crate::{
+    components::PacketCreator,
+    tokens::{TokenStream, TextToken, DerivationTree},
+};
+
+/// Rules that are nested deeper than this always expand to the alternative that terminates the quickest
+const MAX_DEPTH: usize = 16;
+
+/// A symbol on the right-hand side of a rule, as used by [`GrammarBuilder`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    /// Becomes a whitespace token if it only consists of whitespace, else a constant
+    Literal(Vec<u8>),
+    /// A reference to another rule by name
+    Rule(String),
+    /// A decimal number
+    Number,
+    /// A random text token
+    Text,
+    /// Random whitespace
+    Whitespace,
+    /// Text tokens separated by slashes
+    Path,
+    /// Six comma-separated numbers as in FTP's PORT command
+    HostPort,
+}
+
+impl Term {
+    pub fn literal<T: Into<Vec<u8>>>(data: T) -> Self {
+        Term::Literal(data.into())
+    }
+    
+    pub fn rule<T: Into<String>>(name: T) -> Self {
+        Term::Rule(name.into())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum Symbol {
+    /// A quoted string. Becomes a whitespace token if it only consists of whitespace, else a constant.
+    Literal(Vec<u8>),
+    /// A reference to another rule
+    Rule(usize),
+    /// `<number>`: a decimal number
+    Number,
+    /// `<text>`: a random text token
+    Text,
+    /// `<whitespace>`: random whitespace
+    Whitespace,
+    /// `<path>`: text tokens separated by slashes
+    Path,
+    /// `<host-port>`: six comma-separated numbers as in FTP's PORT command
+    HostPort,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Rule {
+    name: String,
+    alternatives: Vec<Vec<Symbol>>,
+    /// The alternative with the shallowest derivation
+    shortest: usize,
+}
+
+/// A context-free grammar that generates [`TokenStream`]s.
+///
+/// The format is a small BNF with one rule per line. Lines starting with `|` add alternatives to the previous rule:
+/// ```text
+/// # Comment
+/// <command> ::= "USER " <text> "\r\n" | "TYPE " <type> "\r\n"
+///     | "QUIT\r\n"
+/// <type> ::= "A" | "I" | "L " <number>
+/// ```
+/// The first rule is the start rule. Deeply nested rules expand to the alternative with the shallowest derivation,
+/// so every rule needs a way to terminate. Literals support the escapes `\r`, `\n`, `\t`, `\\`, `\"` and `\xHH`.
+/// The builtin rules `<number>`, `<text>`, `<whitespace>`, `<path>` and `<host-port>` generate
+/// tokens of the corresponding [`TextToken`] kinds such that the token mutators know what they are working with.
+/// Literals are split into [`TextToken::Whitespace`], [`TextToken::Number`] and [`TextToken::Constant`] tokens,
+/// e.g. `"L 8"` becomes a constant, a whitespace and a number token.
+///
+/// Grammars can also be described in Rust with a [`GrammarBuilder`].
+///
+/// Add the grammar to the metadata of the state to use the [`PacketCreator`]s of [`TokenStream`] and [`DerivationTree`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Grammar {
+    rules: Vec<Rule>,
+}
+
+impl_serdeany!(Grammar);
+
+fn builtin(name: &str) -> Option<Term> {
+    match name {
+        "number" => Some(Term::Number),
+        "text" => Some(Term::Text),
+        "whitespace" => Some(Term::Whitespace),
+        "path" => Some(Term::Path),
+        "host-port" => Some(Term::HostPort),
+        _ => None,
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r')
+}
+
+/// Splits a literal into runs of whitespace, digits and everything else
+fn literal_tokens(data: &[u8], tokens: &mut Vec<TextToken>) {
+    let kind = |x: u8| if is_whitespace(x) { 0 } else if x.is_ascii_digit() { 1 } else { 2 };
+    let mut start = 0;
+    
+    while start < data.len() {
+        let current = kind(data[start]);
+        let end = data[start..].iter().position(|x| kind(*x) != current).map(|x| start + x).unwrap_or(data.len());
+        let run = data[start..end].to_vec();
+        
+        tokens.push(match current {
+            0 => TextToken::Whitespace(run),
+            1 => TextToken::Number(run),
+            _ => TextToken::Constant(run),
+        });
+        start = end;
+    }
+}
+
+/// Splits an alternative into its symbols
+fn parse_alternative(text: &str, line: usize) -> Result<Vec<Term>, Error> {
+    let bytes = text.as_bytes();
+    let mut symbols = Vec::new();
+    let mut i = 0;
+    
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' => i += 1,
+            b'<' => {
+                let end = text[i..].find('>').ok_or_else(|| Error::illegal_argument(format!("Line {}: unterminated rule reference", line)))?;
+                let name = &text[i + 1..i + end];
+                
+                symbols.push(builtin(name).unwrap_or_else(|| Term::rule(name)));
+                i += end + 1;
+            },
+            b'"' => {
+                let mut literal = Vec::new();
+                i += 1;
+                
+                loop {
+                    match bytes.get(i) {
+                        None => return Err(Error::illegal_argument(format!("Line {}: unterminated literal", line))),
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            let (byte, len) = match bytes.get(i + 1) {
+                                Some(b'r') => (b'\r', 2),
+                                Some(b'n') => (b'\n', 2),
+                                Some(b't') => (b'\t', 2),
+                                Some(b'\\') => (b'\\', 2),
+                                Some(b'"') => (b'"', 2),
+                                Some(b'x') => {
+                                    let byte = text.get(i + 2..i + 4)
+                                        .and_then(|x| u8::from_str_radix(x, 16).ok())
+                                        .ok_or_else(|| Error::illegal_argument(format!("Line {}: invalid escape sequence", line)))?;
+                                    (byte, 4)
+                                },
+                                _ => return Err(Error::illegal_argument(format!("Line {}: invalid escape sequence", line))),
+                            };
+                            literal.push(byte);
+                            i += len;
+                        },
+                        Some(byte) => {
+                            literal.push(*byte);
+                            i += 1;
+                        },
+                    }
+                }
+                
+                if literal.is_empty() {
+                    return Err(Error::illegal_argument(format!("Line {}: empty literal", line)));
+                }
+                
+                symbols.push(Term::Literal(literal));
+                i += 1;
+            },
+            c => return Err(Error::illegal_argument(format!("Line {}: unexpected character {:?}", line, c as char))),
+        }
+    }
+    
+    Ok(symbols)
+}
+
+/// Splits the right-hand side of a rule at `|` outside of literals
+fn split_alternatives(text: &str) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let mut in_literal = false;
+    let mut escaped = false;
+    let mut start = 0;
+    
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if in_literal && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_literal = !in_literal;
+        } else if c == '|' && !in_literal {
+            ret.push(&text[start..i]);
+            start = i + 1;
+        }
+    }
+    
+    ret.push(&text[start..]);
+    ret
+}
+
+fn parse_alternatives(definition: &str, line: usize) -> Result<Vec<Vec<Term>>, Error> {
+    let mut alternatives = Vec::new();
+    
+    for alternative in split_alternatives(definition) {
+        let symbols = parse_alternative(alternative, line)?;
+        
+        if symbols.is_empty() {
+            return Err(Error::illegal_argument(format!("Line {}: empty alternative", line)));
+        }
+        
+        alternatives.push(symbols);
+    }
+    
+    Ok(alternatives)
+}
+
+impl FromStr for Grammar {
+    type Err = Error;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut builder = GrammarBuilder::new();
+        
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            
+            /* Lines starting with | continue the previous rule */
+            if let Some(definition) = line.strip_prefix('|') {
+                let (_, alternatives) = builder.rules.last_mut().ok_or_else(|| Error::illegal_argument(format!("Line {}: continuation without a rule", i + 1)))?;
+                alternatives.extend(parse_alternatives(definition, i + 1)?);
+                continue;
+            }
+            
+            let (name, definition) = line.split_once("::=").ok_or_else(|| Error::illegal_argument(format!("Line {}: expected <name> ::= ...", i + 1)))?;
+            let name = name.trim();
+            
+            if !name.starts_with('<') || !name.ends_with('>') || name.len() < 3 {
+                return Err(Error::illegal_argument(format!("Line {}: invalid rule name {}", i + 1, name)));
+            }
+            
+            builder = builder.rule(&name[1..name.len() - 1], parse_alternatives(definition, i + 1)?);
+        }
+        
+        builder.build()
+    }
+}
+
+/// Describes a [`Grammar`] in Rust. The first rule is the start rule.
+/// ```
+/// # use dragonfly::tokens::{GrammarBuilder, Term};
+/// let grammar = GrammarBuilder::new()
+///     .rule("command", vec![
+///         vec![Term::literal("TYPE "), Term::rule("type"), Term::literal("\r\n")],
+///         vec![Term::literal("QUIT\r\n")],
+///     ])
+///     .rule("type", vec![
+///         vec![Term::literal("A")],
+///         vec![Term::literal("L "), Term::Number],
+///     ])
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GrammarBuilder {
+    rules: Vec<(String, Vec<Vec<Term>>)>,
+}
+
+impl GrammarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Adds a rule with the given alternatives
+    pub fn rule<T: Into<String>>(mut self, name: T, alternatives: Vec<Vec<Term>>) -> Self {
+        self.rules.push((name.into(), alternatives));
+        self
+    }
+    
+    /// Resolves the references between rules
+    pub fn build(self) -> Result<Grammar, Error> {
+        if self.rules.is_empty() {
+            return Err(Error::illegal_argument("Grammar does not contain any rules"));
+        }
+        
+        let names: Vec<&str> = self.rules.iter().map(|(name, _)| name.as_str()).collect();
+        let mut rules = Vec::with_capacity(self.rules.len());
+        
+        for (i, (name, alternatives)) in self.rules.iter().enumerate() {
+            if builtin(name).is_some() || names[..i].contains(&name.as_str()) {
+                return Err(Error::illegal_argument(format!("Rule <{}> is already defined", name)));
+            }
+            
+            if alternatives.is_empty() || alternatives.iter().any(Vec::is_empty) {
+                return Err(Error::illegal_argument(format!("Rule <{}> has an empty alternative", name)));
+            }
+            
+            let mut resolved = Vec::with_capacity(alternatives.len());
+            
+            for alternative in alternatives {
+                let mut symbols = Vec::with_capacity(alternative.len());
+                
+                for term in alternative {
+                    symbols.push(match term {
+                        Term::Literal(data) if data.is_empty() => return Err(Error::illegal_argument(format!("Rule <{}> contains an empty literal", name))),
+                        Term::Literal(data) => Symbol::Literal(data.clone()),
+                        Term::Rule(reference) => Symbol::Rule(names.iter().position(|x| x == reference).ok_or_else(|| Error::illegal_argument(format!("Rule <{}> references undefined rule <{}>", name, reference)))?),
+                        Term::Number => Symbol::Number,
+                        Term::Text => Symbol::Text,
+                        Term::Whitespace => Symbol::Whitespace,
+                        Term::Path => Symbol::Path,
+                        Term::HostPort => Symbol::HostPort,
+                    });
+                }
+                
+                resolved.push(symbols);
+            }
+            
+            rules.push(Rule {
+                name: name.clone(),
+                alternatives: resolved,
+                shortest: 0,
+            });
+        }
+        
+        /* Find the shallowest derivation of every rule. Rules without one can only recurse forever. */
+        let mut heights: Vec<Option<usize>> = vec![None; rules.len()];
+        let mut shortest = vec![0; rules.len()];
+        let mut changed = true;
+        
+        while changed {
+            changed = false;
+            
+            for i in 0..rules.len() {
+                for (j, alternative) in rules[i].alternatives.iter().enumerate() {
+                    let mut height = Some(0);
+                    
+                    for symbol in alternative {
+                        if let Symbol::Rule(rule) = symbol {
+                            height = height.zip(heights[*rule]).map(|(x, y)| std::cmp::max(x, y + 1));
+                        }
+                    }
+                    
+                    if let Some(height) = height {
+                        if heights[i].is_none_or(|x| height < x) {
+                            heights[i] = Some(height);
+                            shortest[i] = j;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        
+        if let Some(i) = heights.iter().position(Option::is_none) {
+            return Err(Error::illegal_argument(format!("Rule <{}> never terminates", rules[i].name)));
+        }
+        
+        for (rule, shortest) in rules.iter_mut().zip(shortest) {
+            rule.shortest = shortest;
+        }
+        
+        Ok(Grammar {
+            rules,
+        })
+    }
+}
+
+/// A node of a derivation tree. The children of a rule correspond to the symbols of the chosen alternative.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) enum DerivationNode {
+    Rule {
+        rule: usize,
+        alternative: usize,
+        children: Vec<DerivationNode>,
+    },
+    Tokens(Vec<TextToken>),
+}
+
+impl DerivationNode {
+    pub(crate) fn render(&self, tokens: &mut Vec<TextToken>) {
+        match self {
+            DerivationNode::Rule { children, .. } => {
+                for child in children {
+                    child.render(tokens);
+                }
+            },
+            DerivationNode::Tokens(data) => tokens.extend_from_slice(data),
+        }
+    }
+}
+
+impl Grammar {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)?;
+        text.parse()
+    }
+    
+    /// Expands the start rule
+    pub fn generate<R: Rand>(&self, rand: &mut R) -> TokenStream {
+        self.derive(rand).into_token_stream()
+    }
+    
+    /// Expands the rule with the given name, if it exists
+    pub fn generate_rule<R: Rand>(&self, rand: &mut R, name: &str) -> Option<TokenStream> {
+        self.derive_rule(rand, name).map(DerivationTree::into_token_stream)
+    }
+    
+    /// Expands the start rule and keeps the derivation tree
+    pub fn derive<R: Rand>(&self, rand: &mut R) -> DerivationTree {
+        DerivationTree::new(self.expand(rand, 0, 0))
+    }
+    
+    /// Expands the rule with the given name and keeps the derivation tree
+    pub fn derive_rule<R: Rand>(&self, rand: &mut R, name: &str) -> Option<DerivationTree> {
+        let rule = self.rule_index(name)?;
+        Some(DerivationTree::new(self.expand(rand, rule, 0)))
+    }
+    
+    pub(crate) fn rule_index(&self, name: &str) -> Option<usize> {
+        self.rules.iter().position(|x| x.name == name)
+    }
+    
+    pub(crate) fn num_rules(&self) -> usize {
+        self.rules.len()
+    }
+    
+    /// Creates a new derivation of `rule`. `depth` is the depth of the node in the tree.
+    pub(crate) fn expand<R: Rand>(&self, rand: &mut R, rule: usize, depth: usize) -> DerivationNode {
+        let alternatives = &self.rules[rule].alternatives;
+        let alternative = if depth >= MAX_DEPTH {
+            self.rules[rule].shortest
+        } else {
+            rand.below(alternatives.len() as u64) as usize
+        };
+        let mut children = Vec::with_capacity(alternatives[alternative].len());
+        
+        for symbol in &alternatives[alternative] {
+            let mut tokens = Vec::new();
+            
+            match symbol {
+                Symbol::Literal(data) => literal_tokens(data, &mut tokens),
+                Symbol::Rule(rule) => {
+                    children.push(self.expand(rand, *rule, depth + 1));
+                    continue;
+                },
+                Symbol::Number => tokens.push(TextToken::Number(rand.below(65536).to_string().into_bytes())),
+                Symbol::Text => tokens.push(TextToken::random_text::<_, 1, 16>(rand)),
+                Symbol::Whitespace => tokens.push(TextToken::random_whitespace::<_, 1, 2>(rand)),
+                Symbol::Path => {
+                    if rand.below(2) == 0 {
+                        tokens.push(TextToken::Constant(b"/".to_vec()));
+                    }
+                    
+                    for i in 0..rand.between(1, 3) {
+                        if i > 0 {
+                            tokens.push(TextToken::Constant(b"/".to_vec()));
+                        }
+                        
+                        tokens.push(TextToken::random_text::<_, 1, 8>(rand));
+                    }
+                },
+                Symbol::HostPort => {
+                    for i in 0..6 {
+                        if i > 0 {
+                            tokens.push(TextToken::Constant(b",".to_vec()));
+                        }
+                        
+                        tokens.push(TextToken::Number(rand.below(256).to_string().into_bytes()));
+                    }
+                },
+            }
+            
+            children.push(DerivationNode::Tokens(tokens));
+        }
+        
+        DerivationNode::Rule {
+            rule,
+            alternative,
+            children,
+        }
+    }
+}
+
+/// Generates a token stream with the [`Grammar`] in the metadata of the state.
+/// If `rule` is `None` the start rule gets expanded.
+/// Returns `None` if the state has no grammar or the rule does not exist.
+pub fn generate_from_grammar<S>(state: &mut S, rule: Option<&str>) -> Option<TokenStream>
+where
+    S: HasRand + HasMetadata,
+{
+    let mut rand = StdRand::with_seed(state.rand_mut().next());
+    let grammar = state.metadata_map().get::<Grammar>()?;
+    
+    match rule {
+        Some(rule) => grammar.generate_rule(&mut rand, rule),
+        None => Some(grammar.generate(&mut rand)),
+    }
+}
+
+impl<S> PacketCreator<S> for TokenStream
+where
+    S: HasRand + HasMetadata,
+{
+    fn create_packets(state: &mut S) -> Vec<Self> {
+        generate_from_grammar(state, None).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl_bolts::prelude::current_nanos;
+    
+    const GRAMMAR: &str = r#"
+        # A subset of FTP
+        <command> ::= "USER " <text> "\r\n" | "TYPE " <type> "\r\n" | "PORT " <host-port> "\r\n"
+            | "CWD " <path> "\r\n" | "NOOP" <nested> "\r\n"
+        <type> ::= "A" | "I" | "L " <number>
+        <nested> ::= "" | "|" <nested>
+    "#;
+    
+    #[test]
+    fn parse_errors() {
+        assert!(GRAMMAR.parse::<Grammar>().is_err());
+        assert!("<a> ::= <b>".parse::<Grammar>().is_err());
+        assert!("<a> ::= \"x".parse::<Grammar>().is_err());
+        assert!("<a> ::= \"x\"\n<a> ::= \"y\"".parse::<Grammar>().is_err());
+        assert!("<text> ::= \"x\"".parse::<Grammar>().is_err());
+        assert!("".parse::<Grammar>().is_err());
+        assert!("| \"x\"".parse::<Grammar>().is_err());
+        assert!(GrammarBuilder::new().rule("a", vec![vec![Term::rule("a")], vec![]]).build().is_err());
+        assert!("<a> ::= <a> \"x\"".parse::<Grammar>().is_err());
+        assert!("<a> ::= <b> \"x\"\n<b> ::= \"y\" <a>".parse::<Grammar>().is_err());
+    }
+    
+    #[test]
+    fn deep_recursion() {
+        /* The recursive alternative comes first and must not be taken at the depth limit */
+        let grammar = "<a> ::= <a> \"x\" | \"y\"".parse::<Grammar>().unwrap();
+        let mut rand = StdRand::with_seed(current_nanos());
+        
+        for _ in 0..100 {
+            let stream = grammar.generate(&mut rand);
+            assert_eq!(stream.tokens()[0].data(), b"y");
+            assert!(stream.len() <= MAX_DEPTH + 1);
+        }
+    }
+    
+    #[test]
+    fn literal_kinds() {
+        let mut tokens = Vec::new();
+        literal_tokens(b"SIZE x1 \r\n", &mut tokens);
+        
+        let kinds: Vec<(bool, bool, bool)> = tokens.iter().map(|x| (x.is_constant(), x.is_number(), x.is_whitespace())).collect();
+        let data: Vec<&[u8]> = tokens.iter().map(|x| x.data()).collect();
+        assert_eq!(data, vec![&b"SIZE"[..], b" ", b"x", b"1", b" \r\n"]);
+        assert_eq!(kinds, vec![(true, false, false), (false, false, true), (true, false, false), (false, true, false), (false, false, true)]);
+        
+        tokens.clear();
+        literal_tokens(b"", &mut tokens);
+        assert!(tokens.is_empty());
+    }
+    
+    #[test]
+    fn generate() {
+        let grammar = GRAMMAR.replace("\"\" | ", "\"!\" | ").parse::<Grammar>().unwrap();
+        let mut rand = StdRand::with_seed(current_nanos());
+        
+        for _ in 0..1000 {
+            let stream = grammar.generate(&mut rand);
+            let mut buffer = vec![0; stream.serialized_len()];
+            stream.serialize_into_buffer(&mut buffer);
+            println!("{}", String::from_utf8_lossy(&buffer));
+            
+            assert!(buffer.ends_with(b"\r\n"));
+            assert!(stream.tokens()[0].is_constant());
+            assert!(stream.tokens().last().unwrap().is_whitespace());
+            assert!(stream.tokens().iter().all(TextToken::verify));
+        }
+        
+        let stream = grammar.generate_rule(&mut rand, "type").unwrap();
+        assert!(matches!(stream.tokens()[0].data(), b"A" | b"I" | b"L"));
+        assert!(grammar.generate_rule(&mut rand, "missing").is_none());
+    }
+}
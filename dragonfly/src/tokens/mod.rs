@@ -1,3 +1,7 @@
 mod token;
+mod grammar;
+mod tree;
 pub mod mutators;
 pub use token::*;
+pub use grammar::*;
+pub use tree::*;
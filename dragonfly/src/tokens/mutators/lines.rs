@@ -0,0 +1,170 @@
+use crate::tokens::{TokenStream, TextToken};
+use libafl_bolts::prelude::Rand;
+use std::ops::Range;
+
+/// A line ends after a token that contains a `\n`, usually the CRLF of a command
+fn lines(stream: &TokenStream) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    
+    for (i, token) in stream.tokens().iter().enumerate() {
+        if ends_line(token) {
+            lines.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    
+    if start < stream.len() {
+        lines.push(start..stream.len());
+    }
+    
+    lines
+}
+
+#[inline]
+fn ends_line(token: &TextToken) -> bool {
+    token.data().contains(&b'\n')
+}
+
+/// The command of a line is its first token that is not whitespace.
+/// Returns the index of the command and the range of its arguments, which
+/// excludes the terminating newline.
+fn command(stream: &TokenStream, line: &Range<usize>) -> Option<(usize, Range<usize>)> {
+    let tokens = &stream.tokens()[line.clone()];
+    let cmd = tokens.iter().position(|x| !x.is_whitespace() && !ends_line(x))?;
+    let mut end = line.end;
+    
+    if ends_line(&tokens[tokens.len() - 1]) {
+        end -= 1;
+    }
+    
+    Some((line.start + cmd, line.start + cmd + 1..end))
+}
+
+/// Replaces a whole line with a line from `other` or inserts the line between two lines
+pub fn mutate_crossover_lines<R: Rand>(rand: &mut R, stream: &mut TokenStream, other: &TokenStream, max_len: usize) -> bool {
+    if other.is_empty() {
+        return false;
+    }
+    
+    let dst_lines = lines(stream);
+    let src_lines = lines(other);
+    let src_range = src_lines[rand.below(src_lines.len() as u64) as usize].clone();
+    
+    let dst_range = if dst_lines.is_empty() || rand.below(2) == 0 {
+        let idx = rand.below(dst_lines.len() as u64 + 1) as usize;
+        let idx = dst_lines.get(idx).map(|x| x.start).unwrap_or(stream.len());
+        idx..idx
+    } else {
+        dst_lines[rand.below(dst_lines.len() as u64) as usize].clone()
+    };
+    
+    if stream.len() - dst_range.len() + src_range.len() > max_len || stream.tokens()[dst_range.clone()] == other.tokens()[src_range.clone()] {
+        return false;
+    }
+    
+    stream.tokens_mut().splice(dst_range, other.tokens()[src_range].to_owned());
+    
+    debug_assert!(stream.len() <= max_len);
+    true
+}
+
+/// Replaces the arguments of a command with the arguments of a command in `other`.
+/// Commands with the same name are preferred so that e.g. the argument of a `CWD`
+/// is exchanged with the argument of another `CWD`.
+pub fn mutate_crossover_arguments<R: Rand>(rand: &mut R, stream: &mut TokenStream, other: &TokenStream, max_len: usize) -> bool {
+    let dst_commands: Vec<_> = lines(stream).iter().filter_map(|x| command(stream, x)).collect();
+    let src_commands: Vec<_> = lines(other).iter().filter_map(|x| command(other, x)).collect();
+    
+    if dst_commands.is_empty() || src_commands.is_empty() {
+        return false;
+    }
+    
+    let (dst_cmd, dst_args) = dst_commands[rand.below(dst_commands.len() as u64) as usize].clone();
+    let same: Vec<_> = src_commands.iter().filter(|(x, _)| other.tokens()[*x] == stream.tokens()[dst_cmd]).collect();
+    
+    let (_, src_args) = if same.is_empty() {
+        &src_commands[rand.below(src_commands.len() as u64) as usize]
+    } else {
+        same[rand.below(same.len() as u64) as usize]
+    };
+    
+    if stream.len() - dst_args.len() + src_args.len() > max_len || stream.tokens()[dst_args.clone()] == other.tokens()[src_args.clone()] {
+        return false;
+    }
+    
+    stream.tokens_mut().splice(dst_args, other.tokens()[src_args.clone()].to_owned());
+    
+    debug_assert!(stream.len() <= max_len);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl_bolts::prelude::{StdRand, current_nanos};
+    
+    fn render(stream: &TokenStream) -> String {
+        let mut buffer = vec![0; stream.serialized_len()];
+        stream.serialize_into_buffer(&mut buffer);
+        String::from_utf8(buffer).unwrap()
+    }
+    
+    #[test]
+    fn test_lines() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let stream1 = "USER ftp\r\nPASS x\r\n".parse::<TokenStream>().unwrap();
+        let stream2 = "PORT 127,0,0,1,80,80\r\nLIST\r\n".parse::<TokenStream>().unwrap();
+        let candidates = ["USER ftp\r\n", "PASS x\r\n", "PORT 127,0,0,1,80,80\r\n", "LIST\r\n"];
+        
+        for _ in 0..100 {
+            let mut stream = stream1.clone();
+            
+            if mutate_crossover_lines(&mut rand, &mut stream, &stream2, 64) {
+                let text = render(&stream);
+                let mut rest = text.as_str();
+                
+                while !rest.is_empty() {
+                    let line = candidates.iter().find(|x| rest.starts_with(**x)).unwrap();
+                    rest = &rest[line.len()..];
+                }
+            }
+        }
+    }
+    
+    #[test]
+    fn test_lines_at_limit() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let stream1 = "PORT 127,0,0,1,80,80\r\n".parse::<TokenStream>().unwrap();
+        let stream2 = "LIST\r\n".parse::<TokenStream>().unwrap();
+        let mut replaced = false;
+        
+        /* A full stream can still get shorter lines */
+        for _ in 0..100 {
+            let mut stream = stream1.clone();
+            
+            if mutate_crossover_lines(&mut rand, &mut stream, &stream2, stream1.len()) {
+                assert_eq!(render(&stream), "LIST\r\n");
+                replaced = true;
+            }
+        }
+        
+        assert!(replaced);
+    }
+    
+    #[test]
+    fn test_arguments() {
+        let mut rand = StdRand::with_seed(current_nanos());
+        let stream1 = "CWD /tmp\r\nUSER ftp\r\n".parse::<TokenStream>().unwrap();
+        let stream2 = "NOOP\r\nCWD ../../etc\r\n".parse::<TokenStream>().unwrap();
+        
+        for _ in 0..100 {
+            let mut stream = stream1.clone();
+            
+            if mutate_crossover_arguments(&mut rand, &mut stream, &stream2, 64) {
+                let text = render(&stream);
+                assert!(["CWD ../../etc\r\nUSER ftp\r\n", "CWD /tmp\r\nUSER\r\n", "CWD /tmp\r\nUSER ../../etc\r\n"].contains(&text.as_str()), "{:?}", text);
+            }
+        }
+    }
+}
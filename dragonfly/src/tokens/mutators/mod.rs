@@ -11,6 +11,7 @@ mod special;
 mod dict;
 mod flip;
 mod truncate;
+mod lines;
 
 pub use split::*;
 pub use crossover::*;
@@ -24,6 +25,7 @@ pub use special::*;
 pub use dict::*;
 pub use flip::*;
 pub use truncate::*;
+pub use lines::*;
 
 #[cfg(test)]
 mod tests {
@@ -52,7 +54,7 @@ mod tests {
             let mut stream = stream.clone();
             
             for _ in 0..1000 {
-                let mutation = rand.below(19);
+                let mutation = rand.below(21);
                 
                 let mutated = match mutation {
                     0 => mutate_copy(&mut rand, &mut stream, MAX_LEN),
@@ -71,8 +73,8 @@ mod tests {
                     7 => mutate_interesting(&mut rand, &mut stream),
                     8 => mutate_random_insert(&mut rand, &mut stream, MAX_LEN),
                     9 => mutate_random_replace(&mut rand, &mut stream),
-                    10 => mutate_repeat_char::<_, 8>(&mut rand, &mut stream),
-                    11 => mutate_repeat_token::<_, 8>(&mut rand, &mut stream, MAX_LEN),
+                    10 => mutate_repeat_char(&mut rand, &mut stream, 8),
+                    11 => mutate_repeat_token(&mut rand, &mut stream, MAX_LEN, 8),
                     12 => mutate_special_insert(&mut rand, &mut stream),
                     13 => mutate_special_replace(&mut rand, &mut stream),
                     14 => mutate_split(&mut rand, &mut stream, MAX_LEN),
@@ -80,6 +82,14 @@ mod tests {
                     16 => mutate_swap_tokens(&mut rand, &mut stream),
                     17 => mutate_swap_words(&mut rand, &mut stream),
                     18 => mutate_truncate(&mut rand, &mut stream),
+                    19 => {
+                        let other = stream.clone();
+                        mutate_crossover_lines(&mut rand, &mut stream, &other, MAX_LEN)
+                    },
+                    20 => {
+                        let other = stream.clone();
+                        mutate_crossover_arguments(&mut rand, &mut stream, &other, MAX_LEN)
+                    },
                     _ => unreachable!(),
                 };
                 
@@ -109,7 +119,7 @@ mod tests {
         const MAX_LEN: usize = 128;
         
         for _ in 0..10 {
-            match rand.below(19) {
+            match rand.below(21) {
                 0 => mutate_copy(&mut rand, &mut stream, MAX_LEN),
                 1 => {
                     let other = stream.clone();
@@ -126,8 +136,8 @@ mod tests {
                 7 => mutate_interesting(&mut rand, &mut stream),
                 8 => mutate_random_insert(&mut rand, &mut stream, MAX_LEN),
                 9 => mutate_random_replace(&mut rand, &mut stream),
-                10 => mutate_repeat_char::<_, 8>(&mut rand, &mut stream),
-                11 => mutate_repeat_token::<_, 8>(&mut rand, &mut stream, MAX_LEN),
+                10 => mutate_repeat_char(&mut rand, &mut stream, 8),
+                11 => mutate_repeat_token(&mut rand, &mut stream, MAX_LEN, 8),
                 12 => mutate_special_insert(&mut rand, &mut stream),
                 13 => mutate_special_replace(&mut rand, &mut stream),
                 14 => mutate_split(&mut rand, &mut stream, MAX_LEN),
@@ -135,6 +145,14 @@ mod tests {
                 16 => mutate_swap_tokens(&mut rand, &mut stream),
                 17 => mutate_swap_words(&mut rand, &mut stream),
                 18 => mutate_truncate(&mut rand, &mut stream),
+                19 => {
+                    let other = stream.clone();
+                    mutate_crossover_lines(&mut rand, &mut stream, &other, MAX_LEN)
+                },
+                20 => {
+                    let other = stream.clone();
+                    mutate_crossover_arguments(&mut rand, &mut stream, &other, MAX_LEN)
+                },
                 _ => unreachable!(),
             };
         }
@@ -161,7 +179,7 @@ mod tests {
         const MAX_LEN: usize = 128;
         
         for _ in 0..2 {
-            match rand.below(19) {
+            match rand.below(21) {
                 0 => mutate_copy(&mut rand, &mut stream, MAX_LEN),
                 1 => {
                     let other = stream.clone();
@@ -178,8 +196,8 @@ mod tests {
                 7 => mutate_interesting(&mut rand, &mut stream),
                 8 => mutate_random_insert(&mut rand, &mut stream, MAX_LEN),
                 9 => mutate_random_replace(&mut rand, &mut stream),
-                10 => mutate_repeat_char::<_, 8>(&mut rand, &mut stream),
-                11 => mutate_repeat_token::<_, 8>(&mut rand, &mut stream, MAX_LEN),
+                10 => mutate_repeat_char(&mut rand, &mut stream, 8),
+                11 => mutate_repeat_token(&mut rand, &mut stream, MAX_LEN, 8),
                 12 => mutate_special_insert(&mut rand, &mut stream),
                 13 => mutate_special_replace(&mut rand, &mut stream),
                 14 => mutate_split(&mut rand, &mut stream, MAX_LEN),
@@ -187,6 +205,14 @@ mod tests {
                 16 => mutate_swap_tokens(&mut rand, &mut stream),
                 17 => mutate_swap_words(&mut rand, &mut stream),
                 18 => mutate_truncate(&mut rand, &mut stream),
+                19 => {
+                    let other = stream.clone();
+                    mutate_crossover_lines(&mut rand, &mut stream, &other, MAX_LEN)
+                },
+                20 => {
+                    let other = stream.clone();
+                    mutate_crossover_arguments(&mut rand, &mut stream, &other, MAX_LEN)
+                },
                 _ => unreachable!(),
             };
         }
@@ -1,7 +1,7 @@
 use crate::tokens::TokenStream;
 use libafl_bolts::prelude::Rand;
 
-pub fn mutate_repeat_token<R: Rand, const AMNT: usize>(rand: &mut R, stream: &mut TokenStream, max_len: usize) -> bool {
+pub fn mutate_repeat_token<R: Rand>(rand: &mut R, stream: &mut TokenStream, max_len: usize, amount: usize) -> bool {
     if stream.is_empty() || stream.len() >= max_len {
         return false;
     }
@@ -15,7 +15,7 @@ pub fn mutate_repeat_token<R: Rand, const AMNT: usize>(rand: &mut R, stream: &mu
     
     let elem = elem.clone();
     
-    let n = 1 + (AMNT / elem.len());
+    let n = 1 + (amount / elem.len());
     let n = std::cmp::min(n, max_len - stream.len());
     
     stream.tokens_mut().splice(idx..idx, vec![elem; n]);
@@ -24,7 +24,7 @@ pub fn mutate_repeat_token<R: Rand, const AMNT: usize>(rand: &mut R, stream: &mu
     true
 }
 
-pub fn mutate_repeat_char<R: Rand, const AMNT: usize>(rand: &mut R, stream: &mut TokenStream) -> bool {
+pub fn mutate_repeat_char<R: Rand>(rand: &mut R, stream: &mut TokenStream, amount: usize) -> bool {
     if stream.is_empty() {
         return false;
     }
@@ -38,11 +38,11 @@ pub fn mutate_repeat_char<R: Rand, const AMNT: usize>(rand: &mut R, stream: &mut
     
     let elem_len = elem.len();
     
-    if elem_len == 0 || elem_len >= AMNT {
+    if elem_len == 0 || elem_len >= amount {
         return false;
     }
     
-    let n = AMNT - elem_len;
+    let n = amount - elem_len;
     let idx = rand.below(elem_len as u64) as usize;
     
     if idx == 0 && elem.is_number() {
@@ -68,7 +68,7 @@ mod tests {
         
         for _ in 0..10 {
             let mut stream = stream.clone();
-            mutate_repeat_token::<_, 16>(&mut rand, &mut stream, 32);
+            mutate_repeat_token(&mut rand, &mut stream, 32, 16);
             let size = stream.serialize_into_buffer(&mut buffer);
             let s = std::str::from_utf8(&buffer[0..size]).unwrap();
             println!("{}", s);
@@ -83,7 +83,7 @@ mod tests {
         
         for _ in 0..10 {
             let mut stream = stream.clone();
-            mutate_repeat_char::<_, 16>(&mut rand, &mut stream);
+            mutate_repeat_char(&mut rand, &mut stream, 16);
             let size = stream.serialize_into_buffer(&mut buffer);
             let s = std::str::from_utf8(&buffer[0..size]).unwrap();
             println!("{}", s);
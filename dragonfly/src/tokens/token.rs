@@ -1,9 +1,13 @@
 use serde::{Serialize, Deserialize};
 use std::str::FromStr;
 use libafl_bolts::prelude::Rand;
-use crate::components::Packet;
+use libafl::prelude::Error;
+use crate::components::{
+    HasRawBytes,
+    Packet,
+};
 
-#[derive(Clone, Serialize, Deserialize, Debug, Hash)]
+#[derive(Clone, Serialize, Deserialize, Debug, Hash, PartialEq, Eq)]
 pub enum TextToken {
     Constant(Vec<u8>),
     Number(Vec<u8>),
@@ -276,6 +280,12 @@ impl FromStr for TokenStream {
     }
 }
 
+impl From<Vec<TextToken>> for TokenStream {
+    fn from(tokens: Vec<TextToken>) -> Self {
+        TokenStream(tokens)
+    }
+}
+
 impl TokenStream {
     #[inline]
     pub fn tokens(&self) -> &[TextToken] {
@@ -327,6 +337,16 @@ impl Packet for TokenStream {
     }
 }
 
+/// The bytes are tokenized like [`TokenStream::from_str`] does. This is lossy:
+/// The kinds of the original tokens are not preserved, only their content.
+impl HasRawBytes for TokenStream {
+    fn from_raw_bytes(_connection: usize, _terminates_group: bool, data: Option<&[u8]>) -> Result<Self, Error> {
+        let data = data.ok_or_else(|| Error::illegal_argument("TokenStream must have content"))?;
+        let text = std::str::from_utf8(data).map_err(|_| Error::illegal_argument("TokenStream must be valid UTF-8"))?;
+        TokenStream::from_str(text).map_err(|c| Error::illegal_argument(format!("Invalid character in TokenStream: {:#04x}", c)))
+    }
+}
+
 pub trait HasTokenStream {
     fn token_stream(&self) -> &TokenStream;
     fn token_stream_mut(&mut self) -> &mut TokenStream;
@@ -367,6 +387,15 @@ mod tests {
         test_roundtrip("12 + 12 = 24");
     }
     
+    #[test]
+    fn raw_bytes_are_retokenized() {
+        let stream = TokenStream(vec![TextToken::Constant(b"USER".to_vec()), TextToken::Text(b" 42".to_vec())]);
+        let parsed = TokenStream::from_raw_bytes(0, false, stream.raw_bytes().as_deref()).unwrap();
+        
+        assert_eq!(parsed.raw_bytes(), stream.raw_bytes());
+        assert!(matches!(parsed.tokens(), [TextToken::Text(_), TextToken::Whitespace(_), TextToken::Number(_)]));
+    }
+    
     #[test]
     fn random_number() {
         let mut rand = StdRand::with_seed(current_nanos());
@@ -0,0 +1,329 @@
+use libafl_bolts::prelude::{Rand, StdRand};
+use libafl::prelude::{Error, HasRand, HasMetadata};
+use serde::{Serialize, Deserialize};
+use crate::{
+    components::{Packet, PacketCreator, HasRawBytes},
+    tokens::{TokenStream, HasTokenStream, Grammar, DerivationNode},
+};
+
+/// A packet that remembers how it was derived from a [`Grammar`] such that it can be
+/// mutated on the level of rules. It serializes to the token stream of its leaves.
+///
+/// Once the token stream has been modified directly via [`HasTokenStream::token_stream_mut`],
+/// the derivation tree no longer matches it and gets discarded. From then on it behaves like a plain [`TokenStream`].
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+pub struct DerivationTree {
+    root: Option<DerivationNode>,
+    stream: TokenStream,
+}
+
+impl DerivationTree {
+    pub(crate) fn new(root: DerivationNode) -> Self {
+        let mut ret = Self {
+            root: Some(root),
+            stream: TokenStream::default(),
+        };
+        ret.render();
+        ret
+    }
+    
+    /// Whether the derivation tree is still available, i.e. it renders to the current token stream
+    pub fn is_derived(&self) -> bool {
+        match &self.root {
+            Some(root) => {
+                let mut tokens = Vec::new();
+                root.render(&mut tokens);
+                tokens == self.stream.tokens()
+            },
+            None => false,
+        }
+    }
+    
+    /// Discards the derivation tree if the token stream has been modified
+    fn check_derived(&mut self) -> bool {
+        if !self.is_derived() {
+            self.root = None;
+        }
+        
+        self.root.is_some()
+    }
+    
+    pub fn into_token_stream(self) -> TokenStream {
+        self.stream
+    }
+    
+    fn render(&mut self) {
+        if let Some(root) = &self.root {
+            let mut tokens = Vec::new();
+            root.render(&mut tokens);
+            self.stream = TokenStream::from(tokens);
+        }
+    }
+    
+    /// Paths to all rule nodes together with their rule
+    fn rule_nodes(&self) -> Vec<(Vec<usize>, usize)> {
+        fn visit(node: &DerivationNode, path: &mut Vec<usize>, out: &mut Vec<(Vec<usize>, usize)>) {
+            if let DerivationNode::Rule { rule, children, .. } = node {
+                out.push((path.clone(), *rule));
+                
+                for (i, child) in children.iter().enumerate() {
+                    path.push(i);
+                    visit(child, path, out);
+                    path.pop();
+                }
+            }
+        }
+        
+        let mut out = Vec::new();
+        
+        if let Some(root) = &self.root {
+            visit(root, &mut Vec::new(), &mut out);
+        }
+        
+        out
+    }
+    
+    fn node(&self, path: &[usize]) -> &DerivationNode {
+        let mut node = self.root.as_ref().unwrap();
+        
+        for i in path {
+            match node {
+                DerivationNode::Rule { children, .. } => node = &children[*i],
+                DerivationNode::Tokens(_) => unreachable!(),
+            }
+        }
+        
+        node
+    }
+    
+    fn node_mut(&mut self, path: &[usize]) -> &mut DerivationNode {
+        node_mut(self.root.as_mut().unwrap(), path)
+    }
+    
+    /// Replaces a random subtree with a fresh derivation of the same rule
+    pub(crate) fn replace_subtree<R: Rand>(&mut self, rand: &mut R, grammar: &Grammar) -> bool {
+        if !self.check_derived() {
+            return false;
+        }
+        
+        let nodes = self.rule_nodes();
+        
+        if nodes.is_empty() {
+            return false;
+        }
+        
+        let (path, rule) = &nodes[rand.below(nodes.len() as u64) as usize];
+        
+        if *rule >= grammar.num_rules() {
+            return false;
+        }
+        
+        *self.node_mut(path) = grammar.expand(rand, *rule, path.len());
+        self.render();
+        true
+    }
+    
+    /// Replaces a random subtree with a subtree of `other` that was derived from the same rule
+    pub(crate) fn splice<R: Rand>(&mut self, rand: &mut R, other: &DerivationTree) -> bool {
+        if !self.check_derived() {
+            return false;
+        }
+        
+        let nodes = self.rule_nodes();
+        
+        if nodes.is_empty() || !other.is_derived() {
+            return false;
+        }
+        
+        let (path, rule) = &nodes[rand.below(nodes.len() as u64) as usize];
+        let candidates: Vec<Vec<usize>> = other.rule_nodes().into_iter().filter(|(_, x)| x == rule).map(|(x, _)| x).collect();
+        
+        if candidates.is_empty() {
+            return false;
+        }
+        
+        let replacement = other.node(&candidates[rand.below(candidates.len() as u64) as usize]);
+        
+        if self.node(path) == replacement {
+            return false;
+        }
+        
+        *self.node_mut(path) = replacement.clone();
+        self.render();
+        true
+    }
+    
+    /// Picks a rule node that contains a derivation of its own rule and repeats the
+    /// recursion between the two a random number of times
+    pub(crate) fn expand_recursion<R: Rand>(&mut self, rand: &mut R) -> bool {
+        if !self.check_derived() {
+            return false;
+        }
+        
+        let nodes = self.rule_nodes();
+        let mut recursions = Vec::new();
+        
+        for (outer, rule) in &nodes {
+            for (inner, other) in &nodes {
+                if rule == other && inner.len() > outer.len() && inner.starts_with(outer) {
+                    recursions.push((outer, inner));
+                }
+            }
+        }
+        
+        if recursions.is_empty() {
+            return false;
+        }
+        
+        let (outer, inner) = recursions[rand.below(recursions.len() as u64) as usize];
+        let relative = &inner[outer.len()..];
+        let template = self.node(outer).clone();
+        let mut expansion = self.node(inner).clone();
+        
+        for _ in 0..=rand.between(1, 4) {
+            let mut wrapper = template.clone();
+            *node_mut(&mut wrapper, relative) = expansion;
+            expansion = wrapper;
+        }
+        
+        *self.node_mut(outer) = expansion;
+        self.render();
+        true
+    }
+}
+
+fn node_mut<'a>(mut node: &'a mut DerivationNode, path: &[usize]) -> &'a mut DerivationNode {
+    for i in path {
+        match node {
+            DerivationNode::Rule { children, .. } => node = &mut children[*i],
+            DerivationNode::Tokens(_) => unreachable!(),
+        }
+    }
+    
+    node
+}
+
+impl Packet for DerivationTree {
+    fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.stream.serialize_content(buffer)
+    }
+}
+
+impl HasTokenStream for DerivationTree {
+    fn token_stream(&self) -> &TokenStream {
+        &self.stream
+    }
+    
+    fn token_stream_mut(&mut self) -> &mut TokenStream {
+        &mut self.stream
+    }
+}
+
+impl HasRawBytes for DerivationTree {
+    fn from_raw_bytes(connection: usize, terminates_group: bool, data: Option<&[u8]>) -> Result<Self, Error> {
+        Ok(Self {
+            root: None,
+            stream: TokenStream::from_raw_bytes(connection, terminates_group, data)?,
+        })
+    }
+}
+
+/// Packets that may contain a [`DerivationTree`]
+pub trait HasDerivationTree {
+    fn derivation_tree(&self) -> Option<&DerivationTree>;
+    fn derivation_tree_mut(&mut self) -> Option<&mut DerivationTree>;
+}
+
+impl HasDerivationTree for DerivationTree {
+    fn derivation_tree(&self) -> Option<&DerivationTree> {
+        Some(self)
+    }
+    
+    fn derivation_tree_mut(&mut self) -> Option<&mut DerivationTree> {
+        Some(self)
+    }
+}
+
+/// Derives a packet with the [`Grammar`] in the metadata of the state.
+/// If `rule` is `None` the start rule gets expanded.
+/// Returns `None` if the state has no grammar or the rule does not exist.
+pub fn derive_from_grammar<S>(state: &mut S, rule: Option<&str>) -> Option<DerivationTree>
+where
+    S: HasRand + HasMetadata,
+{
+    let mut rand = StdRand::with_seed(state.rand_mut().next());
+    let grammar = state.metadata_map().get::<Grammar>()?;
+    
+    match rule {
+        Some(rule) => grammar.derive_rule(&mut rand, rule),
+        None => Some(grammar.derive(&mut rand)),
+    }
+}
+
+impl<S> PacketCreator<S> for DerivationTree
+where
+    S: HasRand + HasMetadata,
+{
+    fn create_packets(state: &mut S) -> Vec<Self> {
+        derive_from_grammar(state, None).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libafl_bolts::prelude::current_nanos;
+    use crate::tokens::TextToken;
+    
+    const GRAMMAR: &str = r#"
+        <command> ::= "LIST " <list> "\r\n" | "CWD " <path> "\r\n"
+        <list> ::= <item> | <item> "," <list>
+        <item> ::= <number> | <text>
+    "#;
+    
+    fn render(tree: &DerivationTree) -> String {
+        let stream = tree.token_stream();
+        let mut buffer = vec![0; stream.serialized_len()];
+        stream.serialize_into_buffer(&mut buffer);
+        String::from_utf8(buffer).unwrap()
+    }
+    
+    #[test]
+    fn tree_mutations() {
+        let grammar = GRAMMAR.parse::<Grammar>().unwrap();
+        let mut rand = StdRand::with_seed(current_nanos());
+        let other = grammar.derive_rule(&mut rand, "list").unwrap();
+        let mut expanded = false;
+        
+        for _ in 0..1000 {
+            let mut tree = grammar.derive(&mut rand);
+            
+            tree.replace_subtree(&mut rand, &grammar);
+            tree.splice(&mut rand, &other);
+            let original = render(&tree);
+            
+            if tree.expand_recursion(&mut rand) {
+                let text = render(&tree);
+                assert!(text.matches(',').count() > original.matches(',').count());
+                expanded = true;
+            }
+            
+            let text = render(&tree);
+            assert!(text.starts_with("LIST ") || text.starts_with("CWD "));
+            assert!(text.ends_with("\r\n"));
+            assert!(tree.is_derived());
+        }
+        
+        assert!(expanded);
+        
+        /* Only an actual modification of the token stream discards the tree */
+        let mut tree = grammar.derive(&mut rand);
+        tree.token_stream_mut();
+        assert!(tree.is_derived());
+        
+        tree.token_stream_mut().tokens_mut().push(TextToken::Constant(b"x".to_vec()));
+        assert!(!tree.is_derived());
+        assert!(!tree.replace_subtree(&mut rand, &grammar));
+        assert!(render(&tree).ends_with("\r\nx"));
+    }
+}
@@ -1,12 +1,22 @@
 use serde::{Serialize, Deserialize};
 use dragonfly::{
-    tokens::{TokenStream, HasTokenStream},
+    tokens::{TokenStream, HasTokenStream, Grammar, generate_from_grammar},
     components::{
         Packet, DragonflyInput, PacketCopyMutator,
         PacketDeleteMutator, PacketRepeatMutator, 
         PacketSwapMutator, TokenStreamMutator,
         PacketContentMutator, DragonflyForkserverExecutor,
-        DragonflyDebugExecutor, PacketCreator, PacketInsertionMutator,
+        PacketCreator, PacketInsertionMutator,
+        ResponseObserver, ResponseCodeFeedback, StateObserver, StateFeedback,
+        RESPONSE_CHANNEL_SIZE, RESPONSE_CHANNEL_ENV_VAR, StateAwareScheduler,
+        FilesystemSandbox, ResetPolicy, HasRawBytes,
+        DragonflyMinimizer, SanitizerObserver, UniqueCrashFeedback,
+        ReplayCommand, ReplayState, TargetCommand,
+        PacketCrossoverInsertMutator, PacketCrossoverReplaceMutator,
+        HasGroupBoundary, GroupDeleteMutator, GroupCopyMutator,
+        GroupRepeatMutator, GroupSwapMutator, GroupBoundaryMutator,
+        TokenMutationStatsStage, TokenMutationStatsRecorder, MutationRecorder, MutationProvenance,
+        TokenMutationConfig, FixupSkipMutator, Capture, Reference,
     },
 };
 use clap::Parser;
@@ -14,17 +24,18 @@ use nix::sys::signal::Signal;
 use libafl::prelude::{
     LlmpRestartingEventManager, HitcountsMapObserver, StdMapObserver,
     TimeObserver, MaxMapFeedback, TimeFeedback, CalibrationStage,
-    feedback_or, CrashFeedback, TimeoutFeedback, StdState,
+    feedback_or, TimeoutFeedback, StdState,
     CachedOnDiskCorpus, OnDiskCorpus, Tokens, HasMetadata,
-    StdScheduledMutator, StdMutationalStage, QueueScheduler,
+    StdScheduledMutator, StdMutationalStage,
     StdFuzzer, Fuzzer, OnDiskJSONMonitor, NopMonitor, Launcher,
-    Error, EventConfig, Evaluator, Input, SimpleEventManager,
-    InMemoryCorpus, HasRand, CanTrack,
+    Error, EventConfig, Evaluator, Input,
+    HasRand, CanTrack, ExitKind, HasObservers,
+    Mutator, HasCorpus, HasExecutions, UsesInput, InMemoryCorpus, ConstFeedback,
 };
 use libafl_bolts::prelude::{
     current_nanos, UnixShMemProvider, shmem::{ShMemProvider, ShMem},
     AsMutSlice, StdRand, tuple_list, current_time, StdShMemProvider,
-    Cores, Rand,
+    Cores, Rand, CoreId,
 };
 use std::time::Duration;
 use std::path::PathBuf;
@@ -57,20 +68,26 @@ enum Subcommand {
         cores: String,
     },
     
-    Print {
-        file: String,
-    },
+    #[command(flatten)]
+    Replay(ReplayCommand),
     
-    Replay {
-        #[arg(long)]
-        gdb: bool,
-        
+    Minimize {
         file: String,
     },
     
     GenerateCorpus {
         dir: String,
     },
+    
+    /// Re-derive a corpus entry or crash from its parent with a provenance file
+    /// from the provenance directory of a fuzzing run
+    Derive {
+        file: String,
+        
+        /// The token-mutations.json of the run, if it was not the default configuration
+        #[arg(long)]
+        config: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
@@ -80,6 +97,19 @@ enum FTPPacket {
     Sep,
 }
 
+/// Capture slot of the address in the last reply to PASV
+const PASV_SLOT: u32 = 0;
+
+impl FTPPacket {
+    /// Returns the command if this is a control packet
+    fn command(&self) -> Option<Vec<u8>> {
+        match self {
+            FTPPacket::Ctrl(stream) => stream.raw_bytes(),
+            _ => None,
+        }
+    }
+}
+
 impl Packet for FTPPacket {
     fn serialize_content(&self, buffer: &mut [u8]) -> Option<usize> {
         match self {
@@ -109,6 +139,53 @@ impl Packet for FTPPacket {
             FTPPacket::Sep => true,
         }
     }
+    
+    /// A PASV command captures the address tuple of its reply "227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)"
+    fn captures(&self) -> Vec<Capture> {
+        match self.command() {
+            Some(command) if command.len() >= 4 && command[..4].eq_ignore_ascii_case(b"PASV") => {
+                vec![Capture::regex(PASV_SLOT, "227 [^(]*\\(([0-9]+(,[0-9]+){5})\\)", 1).unwrap()]
+            },
+            _ => Vec::new(),
+        }
+    }
+    
+    /// The address of a PORT command is replaced with the one of the last PASV reply
+    fn references(&self) -> Vec<Reference> {
+        match self.command() {
+            Some(command) if command.len() >= 5 && command[..5].eq_ignore_ascii_case(b"PORT ") => {
+                let length = command[5..].iter().take_while(|x| x.is_ascii_digit() || **x == b',').count();
+                
+                if length > 0 {
+                    vec![Reference::new(PASV_SLOT, 5, length)]
+                } else {
+                    Vec::new()
+                }
+            },
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl HasGroupBoundary for FTPPacket {
+    fn set_terminates_group(&mut self, _terminates_group: bool) -> bool {
+        false
+    }
+    
+    fn group_separator() -> Option<Self> {
+        Some(FTPPacket::Sep)
+    }
+}
+
+impl HasRawBytes for FTPPacket {
+    fn from_raw_bytes(connection: usize, terminates_group: bool, data: Option<&[u8]>) -> Result<Self, Error> {
+        match (connection, terminates_group, data) {
+            (_, true, None) => Ok(FTPPacket::Sep),
+            (0, false, Some(data)) => Ok(FTPPacket::Ctrl(TokenStream::from_raw_bytes(connection, terminates_group, Some(data))?)),
+            (1, false, Some(b"data")) => Ok(FTPPacket::Data),
+            _ => Err(Error::illegal_argument("Not an FTPPacket")),
+        }
+    }
 }
 
 impl HasTokenStream for FTPPacket {
@@ -133,255 +210,32 @@ impl HasTokenStream for FTPPacket {
 
 impl<S> PacketCreator<S> for FTPPacket
 where
-    S: HasRand,
+    S: HasRand + HasMetadata,
 {
     fn create_packets(state: &mut S) -> Vec<Self> {
-        match state.rand_mut().below(59) {
+        match state.rand_mut().below(30) {
             0 => vec![
-                FTPPacket::Ctrl("USER x\r\n".parse().unwrap()),
+                FTPPacket::Sep
             ],
             1 => vec![
-                FTPPacket::Ctrl("PASS x\r\n".parse().unwrap()),
-            ],
-            2 => vec![
-                FTPPacket::Ctrl("ACCT x\r\n".parse().unwrap()),
-            ],
-            3 => vec![
-                FTPPacket::Ctrl("CWD x\r\n".parse().unwrap()),
-            ],
-            4 => vec![
-                FTPPacket::Ctrl("CDUP\r\n".parse().unwrap()),
-            ],
-            5 => vec![
-                FTPPacket::Ctrl("SMNT x\r\n".parse().unwrap()),
-            ],
-            6 => vec![
-                FTPPacket::Ctrl("REIN\r\n".parse().unwrap()),
-            ],
-            7 => vec![
-                FTPPacket::Ctrl("QUIT\r\n".parse().unwrap()),
-            ],
-            8 => vec![
-                FTPPacket::Ctrl("PORT 0,0,0,0,0,0\r\n".parse().unwrap()),
-            ],
-            9 => vec![
-                FTPPacket::Ctrl("EPRT |0|0.0.0.0|0|\r\n".parse().unwrap()),
-            ],
-            10 => vec![
-                FTPPacket::Ctrl("PASV\r\n".parse().unwrap()),
-            ],
-            11 => vec![
-                FTPPacket::Ctrl("EPSV\r\n".parse().unwrap()),
-            ],
-            12 => {
-                let form_code = state.rand_mut().choose([" N", " T", " C", ""]);
-                
-                match state.rand_mut().below(4) {
-                    0 => vec![
-                        FTPPacket::Ctrl(format!("TYPE A{}\r\n", form_code).parse().unwrap()),
-                    ],
-                    1 => vec![
-                        FTPPacket::Ctrl(format!("TYPE E{}\r\n", form_code).parse().unwrap()),
-                    ],
-                    2 => vec![
-                        FTPPacket::Ctrl("TYPE I\r\n".parse().unwrap()),
-                    ],
-                    3 => vec![
-                        FTPPacket::Ctrl("TYPE L 0\r\n".parse().unwrap()),
-                    ],
-                    _ => unreachable!(),
-                }
-            },
-            13 => match state.rand_mut().below(3) {
-                0 => vec![
-                    FTPPacket::Ctrl("STRU F\r\n".parse().unwrap()),
-                ],
-                1 => vec![
-                    FTPPacket::Ctrl("STRU R\r\n".parse().unwrap()),
-                ],
-                2 => vec![
-                    FTPPacket::Ctrl("STRU P\r\n".parse().unwrap()),
-                ],
-                _ => unreachable!(),
-            },
-            14 => match state.rand_mut().below(3) {
-                0 => vec![
-                    FTPPacket::Ctrl("MODE S\r\n".parse().unwrap()),
-                ],
-                1 => vec![
-                    FTPPacket::Ctrl("MODE B\r\n".parse().unwrap()),
-                ],
-                2 => vec![
-                    FTPPacket::Ctrl("MODE C\r\n".parse().unwrap()),
-                ],
-                _ => unreachable!(),
-            },
-            15 => vec![
-                FTPPacket::Ctrl("RETR x\r\n".parse().unwrap()),
-            ],
-            16 => vec![
-                FTPPacket::Sep,
-                FTPPacket::Ctrl("STOR x\r\n".parse().unwrap()),
-                FTPPacket::Data,
-                FTPPacket::Sep,
-            ],
-            17 => vec![
-                FTPPacket::Ctrl("STOU\r\n".parse().unwrap()),
-            ],
-            18 => vec![
-                FTPPacket::Sep,
-                FTPPacket::Ctrl("APPE x\r\n".parse().unwrap()),
-                FTPPacket::Data,
-                FTPPacket::Sep,
-            ],
-            19 => match state.rand_mut().below(2) {
-                0 => vec![
-                    FTPPacket::Ctrl("ALLO 0\r\n".parse().unwrap()),
-                ],
-                1 => vec![
-                    FTPPacket::Ctrl("ALLO 0 R 0\r\n".parse().unwrap()),
-                ],
-                _ => unreachable!(),
-            },
-            20 => vec![
-                FTPPacket::Ctrl("REST x\r\n".parse().unwrap()),
-            ],
-            21 => vec![
-                FTPPacket::Ctrl("RNFR x\r\n".parse().unwrap()),
-            ],
-            22 => vec![
-                FTPPacket::Ctrl("RNTO x\r\n".parse().unwrap()),
-            ],
-            23 => vec![
-                FTPPacket::Ctrl("ABOR\r\n".parse().unwrap()),
-            ],
-            24 => vec![
-                FTPPacket::Ctrl("DELE x\r\n".parse().unwrap()),
-            ],
-            25 => vec![
-                FTPPacket::Ctrl("MDTM x\r\n".parse().unwrap()),
-            ],
-            26 => vec![
-                FTPPacket::Ctrl("RMD x\r\n".parse().unwrap()),
-            ],
-            27 => vec![
-                FTPPacket::Ctrl("XRMD x\r\n".parse().unwrap()),
-            ],
-            28 => vec![
-                FTPPacket::Ctrl("MKD x\r\n".parse().unwrap()),
-            ],
-            29 => vec![
-                FTPPacket::Ctrl("MLST x\r\n".parse().unwrap()),
-            ],
-            30 => vec![
-                FTPPacket::Ctrl("MLSD x\r\n".parse().unwrap()),
-            ],
-            31 => vec![
-                FTPPacket::Ctrl("XMKD x\r\n".parse().unwrap()),
-            ],
-            32 => vec![
-                FTPPacket::Ctrl("PWD\r\n".parse().unwrap()),
-            ],
-            33 => vec![
-                FTPPacket::Ctrl("XPWD\r\n".parse().unwrap()),
-            ],
-            34 => vec![
-                FTPPacket::Ctrl("SIZE x\r\n".parse().unwrap()),
-            ],
-            35 => vec![
-                FTPPacket::Ctrl("LIST\r\n".parse().unwrap()),
-            ],
-            36 => vec![
-                FTPPacket::Ctrl("NLST\r\n".parse().unwrap()),
-            ],
-            37 => vec![
-                FTPPacket::Ctrl("SITE x\r\n".parse().unwrap()),
-            ],
-            38 => vec![
-                FTPPacket::Ctrl("SYST\r\n".parse().unwrap()),
-            ],
-            39 => vec![
-                FTPPacket::Ctrl("STAT x\r\n".parse().unwrap()),
-            ],
-            40 => vec![
-                FTPPacket::Ctrl("FEAT\r\n".parse().unwrap()),
-            ],
-            41 => vec![
-                FTPPacket::Ctrl("OPTS x\r\n".parse().unwrap()),
-            ],
-            42 => vec![
-                FTPPacket::Ctrl("LANG x-x-x\r\n".parse().unwrap()),
-            ],
-            43 => vec![
-                FTPPacket::Ctrl("ADAT eA==\r\n".parse().unwrap()),
-            ],
-            44 => vec![
-                FTPPacket::Ctrl("AUTH x\r\n".parse().unwrap()),
-            ],
-            45 => vec![
-                FTPPacket::Ctrl("CCC\r\n".parse().unwrap()),
-            ],
-            46 => vec![
-                FTPPacket::Ctrl("CONF eA==\r\n".parse().unwrap()),
-            ],
-            47 => vec![
-                FTPPacket::Ctrl("ENC eA==\r\n".parse().unwrap()),
-            ],
-            48 => vec![
-                FTPPacket::Ctrl("MIC eA==\r\n".parse().unwrap()),
-            ],
-            49 => vec![
-                FTPPacket::Ctrl("PBSZ 0\r\n".parse().unwrap()),
+                FTPPacket::Data
             ],
-            50 => match state.rand_mut().below(4) {
-                0 => vec![
-                    FTPPacket::Ctrl("PROT C\r\n".parse().unwrap()),
-                ],
-                1 => vec![
-                    FTPPacket::Ctrl("PROT S\r\n".parse().unwrap()),
-                ],
-                2 => vec![
-                    FTPPacket::Ctrl("PROT E\r\n".parse().unwrap()),
+            2 => match generate_from_grammar(state, Some("transfer")) {
+                Some(stream) => vec![
+                    FTPPacket::Sep,
+                    FTPPacket::Ctrl(stream),
+                    FTPPacket::Data,
+                    FTPPacket::Sep,
                 ],
-                3 => vec![
-                    FTPPacket::Ctrl("PROT P\r\n".parse().unwrap()),
-                ],
-                _ => unreachable!(),
+                None => Vec::new(),
             },
-            51 => vec![
-                FTPPacket::Ctrl("MFF x\r\n".parse().unwrap()),
-            ],
-            52 => vec![
-                FTPPacket::Ctrl("MFMT 0 x\r\n".parse().unwrap()),
-            ],
-            53 => vec![
-                FTPPacket::Ctrl("HOST x\r\n".parse().unwrap()),
-            ],
-            54 => {
-                let name = state.rand_mut().choose(["Version", "Name", "Vendor"]);
-                vec![
-                    FTPPacket::Ctrl(format!("CSID {}=x;\r\n", name).parse().unwrap()),
-                ]
-            },
-            55 => vec![
-                FTPPacket::Ctrl("CLNT\r\n".parse().unwrap()),
-            ],
-            56 => vec![
-                FTPPacket::Ctrl("RANG\r\n".parse().unwrap()),
-            ],
-            57 => vec![
-                FTPPacket::Sep
-            ],
-            58 => vec![
-                FTPPacket::Data
-            ],
-            _ => unreachable!(),
+            _ => generate_from_grammar(state, None).map(FTPPacket::Ctrl).into_iter().collect(),
         }
     }
 }
 
 fn fuzz(output: String, corpus: Option<String>, debug_child: bool, cores: String) {
-    let mut run_client = |state: Option<_>, mut mgr: LlmpRestartingEventManager<_, _, _>, _core_id| {
+    let mut run_client = |state: Option<_>, mut mgr: LlmpRestartingEventManager<_, _, _>, core_id: CoreId| {
         let timeout = Duration::from_millis(10000);
         let signal = str::parse::<Signal>("SIGKILL").unwrap();
         let seed = current_nanos();
@@ -398,21 +252,30 @@ fn fuzz(output: String, corpus: Option<String>, debug_child: bool, cores: String
         let shmem_buf = shmem.as_mut_slice();
         std::env::set_var("AFL_MAP_SIZE", format!("{}", MAP_SIZE));
         
+        let mut response_shmem = shmem_provider.new_shmem(RESPONSE_CHANNEL_SIZE)?;
+        response_shmem.write_to_env(RESPONSE_CHANNEL_ENV_VAR)?;
+        
         let edges_observer = HitcountsMapObserver::new(unsafe { StdMapObserver::new("shared_mem", shmem_buf) }).track_indices();
         let time_observer = TimeObserver::new("time");
+        let state_observer = StateObserver::new("states", ResponseObserver::new("responses", response_shmem.as_mut_slice()));
+        let sanitizer_observer = SanitizerObserver::new("sanitizer", 3);
         
         let map_feedback = MaxMapFeedback::new(&edges_observer);
         let time_feedback = TimeFeedback::with_observer(&time_observer);
+        let state_feedback = StateFeedback::with_output_dir(&state_observer, format!("{}/states/{}", &output, core_id.0));
+        let response_feedback = ResponseCodeFeedback::with_state_observer(&state_observer);
         
         let calibration = CalibrationStage::new(&map_feedback);
         
         let mut feedback = feedback_or!(
             map_feedback,
+            response_feedback,
+            state_feedback,
             time_feedback
         );
         
         let mut objective = feedback_or!(
-            CrashFeedback::new(),
+            UniqueCrashFeedback::with_summary(&sanitizer_observer, format!("{}/triage/unique-crashes-{}.json", &output, core_id.0)),
             TimeoutFeedback::new()
         );
         
@@ -431,30 +294,31 @@ fn fuzz(output: String, corpus: Option<String>, debug_child: bool, cores: String
         let dictionary = Tokens::from_file("./ftp.dict")?;
         state.add_metadata(dictionary);
         
-        let max_packets = 16;
-        let mutators = tuple_list!(
-            PacketCopyMutator::new(max_packets),
-            PacketDeleteMutator::new(0),
-            PacketRepeatMutator::new(max_packets),
-            PacketSwapMutator::new(),
-            PacketContentMutator::new(TokenStreamMutator::new(128)),
-            PacketInsertionMutator::new()
-        );
-        let mutator = StdScheduledMutator::with_max_stack_pow(mutators, 2);
+        let grammar = Grammar::from_file("./ftp.grammar")?;
+        state.add_metadata(grammar);
         
-        let mut stages = tuple_list!(calibration, StdMutationalStage::new(mutator));
+        let token_mutator = TokenStreamMutator::builder()
+            .max_tokens(128)
+            .build()?;
+        token_mutator.config().to_file(format!("{}/token-mutations.json", &output))?;
         
-        let scheduler = QueueScheduler::new();
+        let mutator = ftp_mutator(token_mutator).output_dir(format!("{}/provenance", &output))?;
+        
+        let mut stages = tuple_list!(calibration, StdMutationalStage::new(mutator), TokenMutationStatsStage::new(Duration::from_secs(15)));
+        
+        let scheduler = StateAwareScheduler::new();
         
         let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
         
         let mut executor = DragonflyForkserverExecutor::builder()
-            .observers(tuple_list!(edges_observer, time_observer))
+            .observers(tuple_list!(edges_observer, time_observer, state_observer, sanitizer_observer))
             .shmem_provider(&mut shmem_provider)
             .timeout(timeout)
             .signal(signal)
             .debug_child(debug_child)
+            .capture_stderr(!debug_child)
             .env("LD_PRELOAD", "./libdragonfly.so")
+            .sandbox(FilesystemSandbox::new("/ftproot")?.reset_policy(ResetPolicy::AfterCrash).reset_policy(ResetPolicy::Every(1000)))
             .program("./proftpd-fuzzing")
             .args(["-d", loglevel, "-q", "-X", "-c", "/proftpd/config", "-n"])
             .is_deferred_forkserver(true)
@@ -512,91 +376,114 @@ fn fuzz(output: String, corpus: Option<String>, debug_child: bool, cores: String
     }
 }
 
-fn print(file: String) {
-    let input = DragonflyInput::<FTPPacket>::from_file(file).unwrap();
-    
-    for packet in input.packets() {
-        match packet {
-            FTPPacket::Ctrl(stream) => {
-                let mut buf = vec![0; stream.serialized_len()];
-                let len = stream.serialize_into_buffer(&mut buf);
-                let str = std::str::from_utf8(&buf[..len]).unwrap();
-                println!("ctrl: {:?}", str);
-            },
-            FTPPacket::Data => println!("<data>"),
-            FTPPacket::Sep => println!("<sep>"),
-        }
-    }
+fn ftp_mutator<S>(token_mutator: TokenStreamMutator) -> MutationRecorder<impl Mutator<DragonflyInput<FTPPacket>, S>>
+where
+    S: HasRand + HasMetadata + HasCorpus + HasExecutions,
+    S: UsesInput<Input = DragonflyInput<FTPPacket>>,
+{
+    let max_packets = 16;
+    let mutators = tuple_list!(
+        PacketCopyMutator::new(max_packets),
+        PacketDeleteMutator::new(0),
+        PacketRepeatMutator::new(max_packets),
+        PacketSwapMutator::new(),
+        PacketContentMutator::new(token_mutator),
+        PacketInsertionMutator::new(),
+        PacketCrossoverInsertMutator::new(max_packets),
+        PacketCrossoverReplaceMutator::new(max_packets),
+        GroupDeleteMutator::new(0),
+        GroupCopyMutator::new(max_packets),
+        GroupRepeatMutator::new(max_packets),
+        GroupSwapMutator::new(),
+        GroupBoundaryMutator::new(max_packets)
+    );
+    let mutator = TokenMutationStatsRecorder::new(StdScheduledMutator::with_max_stack_pow(mutators, 2));
+    MutationRecorder::new(FixupSkipMutator::new(mutator, 0.05))
 }
 
-fn replay(file: String, gdb: bool) {
-    let timeout = Duration::from_secs(999999);
-    let signal = str::parse::<Signal>("SIGKILL").unwrap();
-    let input = DragonflyInput::<FTPPacket>::from_file(file).unwrap();
+fn target_command() -> TargetCommand {
+    TargetCommand::new("./proftpd-fuzzing")
+        .debug_program("./proftpd-debug")
+        .args(["-d", "10", "-q", "-X", "-c", "/proftpd/config", "-n"])
+        .env("LD_PRELOAD", "./libdragonfly.so")
+        .sandbox("/ftproot")
+        .is_deferred_forkserver(true)
+}
+
+fn minimize(file: String) {
+    let input = DragonflyInput::<FTPPacket>::from_file(&file).unwrap();
+    let mut shmem_provider = UnixShMemProvider::new().unwrap();
+    let mut executor: DragonflyForkserverExecutor<_, ReplayState<FTPPacket>, _, _> = target_command()
+        .timeout(Duration::from_millis(5000))
+        .reset_policy(ResetPolicy::OnDivergence)
+        .executor(&mut shmem_provider)
+        .unwrap()
+        .observers(tuple_list!(SanitizerObserver::new("sanitizer", 3)))
+        .capture_stderr(true)
+        .build()
+        .unwrap();
     
-    let monitor = NopMonitor::new();
-    let mut mgr = SimpleEventManager::new(monitor);
+    if executor.run_input(&input).unwrap() != ExitKind::Crash {
+        panic!("{} does not crash", file);
+    }
     
-    let mut objective = feedback_or!(
-        CrashFeedback::new(),
-        TimeoutFeedback::new()
-    );
+    /* The minimized input must crash with the same signal and sanitizer report */
+    let signal = executor.last_signal();
+    let signature = executor.observers().0.signature().map(|x| x.hash());
+    let minimizer = DragonflyMinimizer::new(input, |candidate: &DragonflyInput<FTPPacket>| {
+        Ok(executor.run_input(candidate)? == ExitKind::Crash && executor.last_signal() == signal && executor.observers().0.signature().map(|x| x.hash()) == signature)
+    });
+    let minimized = minimizer.minimize().unwrap();
     
+    let output = format!("{}.min", file);
+    minimized.to_file(&output).unwrap();
+    println!("Wrote {} packets to {}", minimized.packets().len(), output);
+}
+
+fn derive(file: String, config: Option<String>) {
+    let provenance = MutationProvenance::from_file(&file).unwrap();
     let mut state = StdState::new(
-        StdRand::with_seed(1234),
-        InMemoryCorpus::<DragonflyInput<FTPPacket>>::new(),
+        StdRand::with_seed(0),
         InMemoryCorpus::<DragonflyInput<FTPPacket>>::new(),
-        &mut (),
-        &mut objective,
+        InMemoryCorpus::new(),
+        &mut ConstFeedback::new(false),
+        &mut ConstFeedback::new(false),
     ).unwrap();
+    state.add_metadata(Tokens::from_file("./ftp.dict").unwrap());
+    state.add_metadata(Grammar::from_file("./ftp.grammar").unwrap());
     
-    let scheduler = QueueScheduler::new();
+    let mut builder = TokenStreamMutator::builder().max_tokens(128);
     
-    let mut fuzzer = StdFuzzer::new(scheduler, (), objective);
+    if let Some(config) = config {
+        builder = builder.config(TokenMutationConfig::from_file(config).unwrap());
+    }
     
-    let mut shmem_provider = UnixShMemProvider::new().unwrap();
+    let mut mutator = ftp_mutator(builder.build().unwrap());
+    let child = mutator.rederive(&mut state, &provenance).unwrap();
     
-    if gdb {
-        let mut executor = DragonflyDebugExecutor::new(&mut shmem_provider).unwrap();
-        executor.arg("/usr/bin/gdb");
-        executor.arg("-ex");
-        executor.arg("set environment LD_PRELOAD ./libdragonfly.so");
-        executor.arg("--args");
-        executor.arg("./proftpd-debug");
-        executor.arg("-d");
-        executor.arg("10");
-        executor.arg("-q");
-        executor.arg("-X");
-        executor.arg("-c");
-        executor.arg("/proftpd/config");
-        executor.arg("-n");
-        
-        fuzzer.evaluate_input(
-            &mut state,
-            &mut executor,
-            &mut mgr,
-            input,
-        ).unwrap();
-    } else {
-        let mut executor = DragonflyForkserverExecutor::builder()
-            .observers(tuple_list!())
-            .shmem_provider(&mut shmem_provider)
-            .timeout(timeout)
-            .signal(signal)
-            .debug_child(true)
-            .is_deferred_forkserver(true)
-            .env("LD_PRELOAD", "./libdragonfly.so")
-            .program("./proftpd-fuzzing")
-            .args(["-d", "10", "-q", "-X", "-c", "/proftpd/config", "-n"])
-            .build()
-            .unwrap();
+    println!("Parent: {:?}", provenance.parent());
+    println!("Seed: {:#018x}, protected prefix: {}", provenance.seed(), provenance.prefix());
+    
+    for donor in provenance.donors().iter().flatten() {
+        println!("Donor: {:?}", donor.path());
+    }
+    
+    for mutation in provenance.mutations() {
+        println!("  {}", mutation);
+    }
+    
+    let output = format!("{}.derived", file);
+    child.to_file(&output).unwrap();
+    println!("Wrote re-derived input to {}", output);
+    
+    if let Some(path) = provenance.child() {
+        let expected = DragonflyInput::<FTPPacket>::from_file(path).unwrap();
         
-        fuzzer.evaluate_input(
-            &mut state,
-            &mut executor,
-            &mut mgr,
-            input,
-        ).unwrap();
+        if format!("{:?}", expected) == format!("{:?}", child) {
+            println!("Re-derived input matches {:?}", path);
+        } else {
+            println!("Re-derived input differs from {:?}", path);
+        }
     }
 }
 
@@ -675,8 +562,9 @@ fn main() {
 
     match args.command {
         Subcommand::Fuzz { output, corpus, debug, cores } => fuzz(output, corpus, debug, cores),
-        Subcommand::Print { file } => print(file),
-        Subcommand::Replay { file, gdb } => replay(file, gdb),
+        Subcommand::Replay(command) => command.execute::<FTPPacket>(&target_command()).unwrap(),
+        Subcommand::Minimize { file } => minimize(file),
         Subcommand::GenerateCorpus { dir } => generate_corpus(dir),
+        Subcommand::Derive { file, config } => derive(file, config),
     }
 }
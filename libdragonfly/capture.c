@@ -0,0 +1,210 @@
+#include <stddef.h>
+#include <stdint.h>
+#include <string.h>
+#include <regex.h>
+
+#include "capture.h"
+
+#define MAX_RULES 16
+#define MAX_SLOTS 16
+#define MAX_GROUPS 10
+#define MAX_VALUE_SIZE 256
+#define MAX_RESPONSE_SIZE 4096
+
+typedef enum {
+    CAPTURE_REGEX = 1,
+    CAPTURE_OFFSET = 2,
+} CaptureKind;
+
+typedef enum {
+    SEGMENT_BYTES = 1,
+    SEGMENT_REFERENCE = 2,
+} SegmentKind;
+
+/* Content of a capture packet */
+typedef struct {
+    uint32_t slot;
+    uint32_t kind;
+    uint32_t group;
+    uint32_t reserved;
+    uint64_t offset;
+    uint64_t length;
+    char pattern[];
+} __attribute__((packed)) CaptureRule;
+
+/* Content of a template packet is a sequence of segments.
+   A reference carries the bytes to use while its slot has not captured anything. */
+typedef struct {
+    uint32_t kind;
+    uint32_t slot;
+    uint64_t size;
+    char content[];
+} __attribute__((packed)) Segment;
+
+typedef struct {
+    uint32_t conn;
+    uint32_t slot;
+    uint32_t kind;
+    uint32_t group;
+    uint64_t offset;
+    uint64_t length;
+    int compiled;
+    regex_t regex;
+} Rule;
+
+typedef struct {
+    int set;
+    uint64_t size;
+    char content[MAX_VALUE_SIZE];
+} Value;
+
+static Rule rules[MAX_RULES];
+static size_t num_rules = 0;
+static Value values[MAX_SLOTS];
+
+void capture_init (void) {
+    for (size_t i = 0; i < num_rules; ++i) {
+        if (rules[i].compiled) {
+            regfree(&rules[i].regex);
+        }
+    }
+    
+    num_rules = 0;
+    
+    for (size_t i = 0; i < MAX_SLOTS; ++i) {
+        values[i].set = 0;
+        values[i].size = 0;
+    }
+}
+
+void capture_add_rule (uint32_t conn, const char* buf, uint64_t size) {
+    if (num_rules >= MAX_RULES || size < sizeof(CaptureRule)) {
+        return;
+    }
+    
+    const CaptureRule* spec = (const CaptureRule*) buf;
+    Rule* rule = &rules[num_rules];
+    
+    if (spec->slot >= MAX_SLOTS || spec->group >= MAX_GROUPS) {
+        return;
+    }
+    
+    rule->conn = conn;
+    rule->slot = spec->slot;
+    rule->kind = spec->kind;
+    rule->group = spec->group;
+    rule->offset = spec->offset;
+    rule->length = spec->length;
+    rule->compiled = 0;
+    
+    switch (spec->kind) {
+        case CAPTURE_REGEX: {
+            /* The pattern must be terminated inside of the packet */
+            if (!memchr(spec->pattern, 0, size - sizeof(CaptureRule))) {
+                return;
+            }
+            
+            if (regcomp(&rule->regex, spec->pattern, REG_EXTENDED) != 0) {
+                return;
+            }
+            
+            rule->compiled = 1;
+            break;
+        }
+        
+        case CAPTURE_OFFSET: {
+            break;
+        }
+        
+        default: {
+            return;
+        }
+    }
+    
+    ++num_rules;
+}
+
+static void set_value (uint32_t slot, const char* buf, uint64_t size) {
+    Value* value = &values[slot];
+    
+    if (size > MAX_VALUE_SIZE) {
+        size = MAX_VALUE_SIZE;
+    }
+    
+    memcpy(value->content, buf, size);
+    value->size = size;
+    value->set = 1;
+}
+
+/* Applies the rules to a single write of the target. Later matches overwrite earlier ones. */
+void capture_response (size_t conn, const char* buf, size_t size) {
+    char text[MAX_RESPONSE_SIZE + 1];
+    int have_text = 0;
+    
+    if (!buf) {
+        return;
+    }
+    
+    for (size_t i = 0; i < num_rules; ++i) {
+        Rule* rule = &rules[i];
+        
+        if (rule->conn != conn) {
+            continue;
+        }
+        
+        if (rule->kind == CAPTURE_OFFSET) {
+            if (rule->offset < size) {
+                uint64_t rem_bytes = size - rule->offset;
+                uint64_t length = (rule->length && rule->length < rem_bytes) ? rule->length : rem_bytes;
+                set_value(rule->slot, &buf[rule->offset], length);
+            }
+        } else {
+            /* regexec() needs a terminated string */
+            if (!have_text) {
+                size_t len = (size < MAX_RESPONSE_SIZE) ? size : MAX_RESPONSE_SIZE;
+                memcpy(text, buf, len);
+                text[len] = 0;
+                have_text = 1;
+            }
+            
+            regmatch_t matches[MAX_GROUPS];
+            
+            if (regexec(&rule->regex, text, MAX_GROUPS, matches, 0) == 0 && matches[rule->group].rm_so >= 0) {
+                regmatch_t* match = &matches[rule->group];
+                set_value(rule->slot, &text[match->rm_so], (uint64_t) (match->rm_eo - match->rm_so));
+            }
+        }
+    }
+}
+
+/* Writes the content of a template with the captured values into out and returns its length */
+size_t capture_expand (const char* template, uint64_t size, char* out, size_t capacity) {
+    uint64_t cursor = 0;
+    size_t len = 0;
+    
+    while (cursor + sizeof(Segment) <= size) {
+        const Segment* segment = (const Segment*) &template[cursor];
+        
+        if (segment->size > size - cursor - sizeof(Segment)) {
+            break;
+        }
+        
+        const char* content = segment->content;
+        uint64_t content_size = segment->size;
+        
+        if (segment->kind == SEGMENT_REFERENCE && segment->slot < MAX_SLOTS && values[segment->slot].set) {
+            content = values[segment->slot].content;
+            content_size = values[segment->slot].size;
+        }
+        
+        if (content_size > capacity - len) {
+            content_size = capacity - len;
+        }
+        
+        memcpy(&out[len], content, content_size);
+        len += content_size;
+        cursor += sizeof(Segment) + segment->size;
+    }
+    
+    return len;
+}
@@ -0,0 +1,9 @@
+#pragma once
+
+#include <stddef.h>
+#include <stdint.h>
+
+void capture_init(void);
+void capture_add_rule(uint32_t conn, const char* buf, uint64_t size);
+void capture_response(size_t conn, const char* buf, size_t size);
+size_t capture_expand(const char* template, uint64_t size, char* out, size_t capacity);
@@ -4,6 +4,7 @@
 #include <stdint.h>
 #include <assert.h>
 #include <errno.h>
+#include <signal.h>
 #include <sys/shm.h>
 
 #include "desock.h"
@@ -12,6 +13,10 @@
 
 #include "conn_pool.h"
 #include "packet_channel.h"
+#include "response_channel.h"
+#include "trace_channel.h"
+#include "snapshot.h"
+#include "capture.h"
 
 static int active_channel = 0;
 static void* packet_channel = NULL;
@@ -20,33 +25,83 @@ static void* packet_channel = NULL;
 static unsigned char packet_buf[16 * 1024 * 1024];
 #endif
 
+static void* attach_shm (const char* name) {
+    char* shm_id = getenv(name);
+    
+    if (!shm_id) {
+        return NULL;
+    }
+    
+    char* endptr = NULL;
+    unsigned long id = strtoul(shm_id, &endptr, 0);
+    
+    if (endptr == NULL || *endptr != 0) {
+        _error("Invalid shm id in %s: %s\n", name, shm_id);
+    }
+    
+    void* shm = shmat(id, NULL, 0);
+    
+    if (shm == NULL || shm == (void*) -1) {
+        _error("Could not attach to shared memory: %ld\n", id);
+    }
+    
+    DEBUG_LOG("Attached to %s %lu @ %p\n", name, id, shm);
+    return shm;
+}
+
+/* Lets the fuzzer capture sanitizer reports */
+static void redirect_stderr (const char* value) {
+    char* endptr = NULL;
+    long fd = strtol(value, &endptr, 10);
+    
+    if (endptr == NULL || *endptr != 0 || fd == 2) {
+        return;
+    }
+    
+    syscall_cp (SYS_dup3, fd, 2, 0);
+    syscall_cp (SYS_close, fd);
+}
+
 __attribute__((constructor))
 static void attach_packet_channel (void) {
-    char* shm_id = getenv("__LIBDRAGONFLY_PACKET_CHANNEL");
+    packet_channel = attach_shm("__LIBDRAGONFLY_PACKET_CHANNEL");
     
-    if (shm_id) {
-        char* endptr = NULL;
-        unsigned long id = strtoul(shm_id, &endptr, 0);
-        
-        if (endptr == NULL || *endptr != 0) {
-            _error("Invalid shm id in __LIBDRAGONFLY_PACKET_CHANNEL: %s\n", shm_id);
-        }
-        
-        packet_channel = shmat(id, NULL, 0);
-        
-        if (packet_channel == NULL || packet_channel == (void*) -1) {
-            _error("Could not attach to shared memory: %ld\n", id);
-        }
-        
-        DEBUG_LOG("Attached to packet channel %lu @ %p\n", id, packet_channel);
-    }
 #ifdef DEBUG
-    else {
+    if (!packet_channel) {
         syscall_cp (SYS_read, 0, packet_buf, sizeof(packet_buf));
         packet_channel = (void*) packet_buf;
         DEBUG_LOG("Read packets from stdin\n");
     }
 #endif
+    
+    response_channel_init(attach_shm("__LIBDRAGONFLY_RESPONSE_CHANNEL"));
+    trace_channel_init(attach_shm("__LIBDRAGONFLY_TRACE_CHANNEL"));
+    
+    char* snapshot_fds = getenv("__LIBDRAGONFLY_SNAPSHOT_FDS");
+    
+    if (snapshot_fds) {
+        snapshot_init(snapshot_fds);
+        packet_channel_on_snapshot(snapshot_take);
+    }
+    
+    char* stderr_fd = getenv("__LIBDRAGONFLY_STDERR_FD");
+    
+    if (stderr_fd) {
+        redirect_stderr(stderr_fd);
+    }
+}
+
+/* In persistent mode, wait until the fuzzer has placed the next input into the packet channel */
+static void wait_for_next_input (void) {
+    uint64_t generation = packet_channel_generation(packet_channel);
+    
+    if (generation == 0) {
+        return;
+    }
+    
+    do {
+        raise(SIGSTOP);
+    } while (packet_channel_generation(packet_channel) == generation);
 }
 
 void hook_shutdown_write (int fd) {
@@ -58,6 +113,10 @@ void hook_shutdown_read (int fd) {
     
     if (active_channel) {
         active_channel = conn_pool_has_open_connections();
+        
+        if (!active_channel) {
+            wait_for_next_input();
+        }
     }
 }
 
@@ -78,6 +137,7 @@ ssize_t hook_input (int fd, char* buf, size_t size) {
         
         if (conn < MAX_CONNS) {
             size_t ret = packet_channel_read(conn, buf, size);
+            trace_channel_read(fd, conn, packet_channel_current_packet());
 #ifdef DEBUG
             fprintf(stderr, "\n< ");
             fwrite(buf, 1, ret, stderr);
@@ -92,8 +152,15 @@ ssize_t hook_input (int fd, char* buf, size_t size) {
 }
 
 ssize_t hook_output (int fd, char* buf, size_t size) {
-    (void) fd;
-    (void) buf;
+    if (active_channel) {
+        size_t conn = conn_pool_map_fd(fd);
+        
+        if (conn < MAX_CONNS) {
+            response_channel_write(conn, packet_channel_current_packet(), buf, size);
+            capture_response(conn, buf, size);
+        }
+    }
+    
 #ifdef DEBUG
     fprintf(stderr, "\n> ");
     fwrite(buf, 1, size, stderr);
@@ -6,14 +6,23 @@
 #include <assert.h>
 #include <string.h>
 
+#include "capture.h"
+
 #ifndef MAX_CONNS
 #error "MAX_CONNS has not been set"
 #endif
 
+#define NO_PACKET ((uint32_t) -1)
+#define MAX_TEMPLATE_SIZE (16 * 1024)
+
 typedef enum {
     TYPE_DATA = 1,
     TYPE_SEP = 2,
     TYPE_EOF = 3,
+    TYPE_SNAPSHOT = 4,
+    TYPE_INPUT = 5,
+    TYPE_CAPTURE = 6,
+    TYPE_TEMPLATE = 7,
 } PacketType;
 
 typedef struct {
@@ -26,11 +35,18 @@ typedef struct {
 typedef struct {
     uint64_t consumed;
     Packet* packet;
+    /* What the connection reads from the packet, which is the expansion for templates */
+    const char* content;
+    uint64_t size;
 } ConnState;
 
 static ConnState cursors[MAX_CONNS] = {0};
 static char conn_has_data[MAX_CONNS] = {0};
 static int signal_eof = 0;
+static Packet* channel_base = NULL;
+static uint32_t current_packet = NO_PACKET;
+static void (*snapshot_handler)(void) = NULL;
+static char expanded[MAX_CONNS][MAX_TEMPLATE_SIZE];
 
 static uint64_t align8 (uint64_t val) {
     uint64_t rem = val % 8;
@@ -42,9 +58,14 @@ static uint64_t align8 (uint64_t val) {
     }
 }
 
+static inline int is_data (Packet* packet) {
+    return packet->type == TYPE_DATA || packet->type == TYPE_TEMPLATE;
+}
+
 static inline size_t packet_size (Packet* packet) {
     switch (packet->type) {
-        case TYPE_SEP: {
+        case TYPE_SEP:
+        case TYPE_SNAPSHOT: {
             return sizeof(Packet);
         }
         
@@ -52,7 +73,9 @@ static inline size_t packet_size (Packet* packet) {
             return 0;
         }
         
-        case TYPE_DATA: {
+        case TYPE_DATA:
+        case TYPE_TEMPLATE:
+        case TYPE_CAPTURE: {
             return sizeof(Packet) + align8(packet->size);
         }
         
@@ -82,7 +105,8 @@ static Packet* next_packet_for_conn (Packet* start, size_t conn) {
                 return cursor;
             }
             
-            case TYPE_DATA: {
+            case TYPE_DATA:
+            case TYPE_TEMPLATE: {
                 if (cursor->conn == conn) {
                     return cursor;
                 }
@@ -90,6 +114,11 @@ static Packet* next_packet_for_conn (Packet* start, size_t conn) {
                 break;
             }
             
+            case TYPE_SNAPSHOT:
+            case TYPE_CAPTURE: {
+                break;
+            }
+            
             default: {
 #ifdef DEBUG
                 abort();
@@ -101,11 +130,32 @@ static Packet* next_packet_for_conn (Packet* start, size_t conn) {
     }
 }
 
+static uint32_t packet_index (Packet* packet) {
+    Packet* cursor = channel_base;
+    uint32_t index = 0;
+    
+    /* Count all data packets before the given one */
+    while (cursor < packet) {
+        if (is_data(cursor)) {
+            ++index;
+        }
+        
+        cursor = next_packet(cursor);
+    }
+    
+    return index;
+}
+
 static void select_group (Packet* group_separator) {
 #ifdef DEBUG
     assert(group_separator->type == TYPE_SEP);
 #endif
     
+    /* The fuzzer wants a snapshot of the target at the start of this group */
+    if (snapshot_handler && next_packet(group_separator)->type == TYPE_SNAPSHOT) {
+        snapshot_handler();
+    }
+    
     /* Reset global state */
     __builtin_memset(cursors, 0, sizeof(ConnState) * MAX_CONNS);
     
@@ -126,7 +176,8 @@ static void select_group (Packet* group_separator) {
                 return;
             }
             
-            case TYPE_DATA: {
+            case TYPE_DATA:
+            case TYPE_TEMPLATE: {
                 size_t conn = (size_t) cursor->conn;
                 
                 if (conn < MAX_CONNS && cursors[conn].packet == NULL) {
@@ -136,6 +187,11 @@ static void select_group (Packet* group_separator) {
                 break;
             }
             
+            case TYPE_SNAPSHOT:
+            case TYPE_CAPTURE: {
+                break;
+            }
+            
             default: {
 #ifdef DEBUG
                 abort();
@@ -148,7 +204,21 @@ static void select_group (Packet* group_separator) {
 }
 
 void packet_channel_init (void* buffer) {
+    /* In persistent mode the actual input follows an input header */
+    if (buffer && ((Packet*) buffer)->type == TYPE_INPUT) {
+        buffer = (void*) ((Packet*) buffer + 1);
+    }
+    
+    channel_base = (Packet*) buffer;
+    current_packet = NO_PACKET;
+    capture_init();
+    
     if (buffer) {
+        /* Capture rules directly follow the initial separator */
+        for (Packet* packet = next_packet(channel_base); packet->type == TYPE_CAPTURE; packet = next_packet(packet)) {
+            capture_add_rule(packet->conn, packet->content, packet->size);
+        }
+        
         select_group((Packet*) buffer);
     } else {
         __builtin_memset(cursors, 0, sizeof(ConnState) * MAX_CONNS);
@@ -163,6 +233,30 @@ int packet_channel_has_data (size_t conn) {
     return conn_has_data[conn];
 }
 
+/* Makes the content of the packet a connection points to available, expanding templates with the captured values */
+static void load_content (size_t conn, ConnState* cursor) {
+    Packet* packet = cursor->packet;
+    
+    if (packet->type == TYPE_TEMPLATE) {
+        cursor->size = capture_expand(packet->content, packet->size, expanded[conn], MAX_TEMPLATE_SIZE);
+        cursor->content = expanded[conn];
+    } else {
+        cursor->size = packet->size;
+        cursor->content = packet->content;
+    }
+}
+
+/* Templates that have not been expanded yet count as non-empty */
+static inline uint64_t content_size (ConnState* cursor) {
+    if (cursor->content) {
+        return cursor->size;
+    } else if (cursor->packet->type == TYPE_DATA) {
+        return cursor->packet->size;
+    } else {
+        return 1;
+    }
+}
+
 void packet_channel_check_available_data (void) {
     /* Check if each connection has a data packet in the current group */
     Packet* min_pointer = (Packet*)(size_t)-1LL;
@@ -172,12 +266,12 @@ void packet_channel_check_available_data (void) {
     for (int i = 0; i < MAX_CONNS; ++i) {
         Packet* packet = cursors[i].packet;
         
-        if (packet->type == TYPE_DATA && cursors[i].consumed >= packet->size) {
+        if (is_data(packet) && cursors[i].consumed >= content_size(&cursors[i])) {
             packet = next_packet_for_conn(packet, (size_t) packet->conn);
         }
         
         conn_has_data[i] = 0;
-        have_data |= packet->type == TYPE_DATA;
+        have_data |= is_data(packet);
         
         if (packet < min_pointer) {
             min_pointer = packet;
@@ -193,8 +287,13 @@ void packet_channel_check_available_data (void) {
             /* EOF done, signal that we can continue with next group */
             Packet* packet = next_packet(min_pointer);
             
+            while (packet->type == TYPE_SNAPSHOT || packet->type == TYPE_CAPTURE) {
+                packet = next_packet(packet);
+            }
+            
             switch (packet->type) {
-                case TYPE_DATA: {
+                case TYPE_DATA:
+                case TYPE_TEMPLATE: {
                     if (packet->conn < MAX_CONNS) {
                         conn_has_data[packet->conn] = 1;
                     }
@@ -206,7 +305,10 @@ void packet_channel_check_available_data (void) {
                     break;
                 }
                 
-                case TYPE_SEP: {
+                case TYPE_SEP:
+                case TYPE_SNAPSHOT:
+                case TYPE_INPUT:
+                case TYPE_CAPTURE: {
 #ifdef DEBUG
                     abort();
 #else
@@ -251,22 +353,29 @@ size_t packet_channel_read (size_t conn, char* buf, size_t size) {
                 return 0;
             }
             
-            case TYPE_DATA: {
-                if (cursor->consumed >= packet->size) {
+            case TYPE_DATA:
+            case TYPE_TEMPLATE: {
+                if (!cursor->content) {
+                    load_content(conn, cursor);
+                }
+                
+                if (cursor->consumed >= cursor->size) {
                     packet = next_packet_for_conn(packet, conn);
                     
                     cursor->consumed = 0;
                     cursor->packet = packet;
-                    
-                    if (packet->type != TYPE_DATA) {
-                        continue;
-                    }
+                    cursor->content = NULL;
+                    continue;
+                }
+                
+                if (cursor->consumed == 0) {
+                    current_packet = packet_index(packet);
                 }
                 
-                uint64_t rem_bytes = packet->size - cursor->consumed;
+                uint64_t rem_bytes = cursor->size - cursor->consumed;
                 uint64_t final_size = (size < rem_bytes) ? size : rem_bytes;
                 
-                memcpy(buf, (void*) &packet->content[cursor->consumed], final_size);
+                memcpy(buf, (void*) &cursor->content[cursor->consumed], final_size);
                 cursor->consumed += final_size;
                 return final_size;
             }
@@ -291,3 +400,21 @@ int packet_channel_eof (void) {
     
     return eof;
 }
+
+uint32_t packet_channel_current_packet (void) {
+    return current_packet;
+}
+
+void packet_channel_on_snapshot (void (*handler)(void)) {
+    snapshot_handler = handler;
+}
+
+uint64_t packet_channel_generation (void* buffer) {
+    Packet* header = (Packet*) buffer;
+    
+    if (header && header->type == TYPE_INPUT) {
+        return header->size;
+    } else {
+        return 0;
+    }
+}
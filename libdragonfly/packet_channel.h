@@ -1,9 +1,13 @@
 #pragma once
 
 #include <stddef.h>
+#include <stdint.h>
 
 void packet_channel_init(void* buffer);
 void packet_channel_check_available_data(void);
 int packet_channel_has_data(size_t conn);
 size_t packet_channel_read(size_t conn, char* buf, size_t size);
 int packet_channel_eof(void);
+uint32_t packet_channel_current_packet(void);
+void packet_channel_on_snapshot(void (*handler)(void));
+uint64_t packet_channel_generation(void* buffer);
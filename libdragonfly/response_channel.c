@@ -0,0 +1,58 @@
+#include <stddef.h>
+#include <stdint.h>
+#include <string.h>
+
+#include "response_channel.h"
+
+typedef struct {
+    uint64_t capacity;
+    uint64_t size;
+    char content[];
+} __attribute__((packed)) ResponseChannel;
+
+typedef struct {
+    uint32_t conn;
+    uint32_t packet;
+    uint64_t size;
+    char content[];
+} __attribute__((packed)) Response;
+
+static ResponseChannel* channel = NULL;
+
+static uint64_t align8 (uint64_t val) {
+    uint64_t rem = val % 8;
+    
+    if (rem == 0) {
+        return val;
+    } else {
+        return val + 8 - rem;
+    }
+}
+
+void response_channel_init (void* buffer) {
+    channel = (ResponseChannel*) buffer;
+}
+
+void response_channel_write (size_t conn, uint32_t packet, const char* buf, size_t size) {
+    if (!channel || !buf || !size || channel->capacity < sizeof(ResponseChannel)) {
+        return;
+    }
+    
+    uint64_t capacity = channel->capacity - sizeof(ResponseChannel);
+    
+    /* Silently drop responses that don't fit anymore */
+    if (channel->size + sizeof(Response) >= capacity) {
+        return;
+    }
+    
+    uint64_t rem_bytes = capacity - channel->size - sizeof(Response);
+    uint64_t final_size = (size < rem_bytes) ? size : rem_bytes;
+    Response* response = (Response*) &channel->content[channel->size];
+    
+    response->conn = (uint32_t) conn;
+    response->packet = packet;
+    response->size = final_size;
+    memcpy(response->content, buf, final_size);
+    
+    channel->size += sizeof(Response) + align8(final_size);
+}
@@ -0,0 +1,7 @@
+#pragma once
+
+#include <stddef.h>
+#include <stdint.h>
+
+void response_channel_init(void* buffer);
+void response_channel_write(size_t conn, uint32_t packet, const char* buf, size_t size);
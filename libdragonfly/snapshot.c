@@ -0,0 +1,87 @@
+#include <stdlib.h>
+#include <stdint.h>
+#include <unistd.h>
+#include <signal.h>
+#include <sys/wait.h>
+#include <sys/syscall.h>
+
+#include "snapshot.h"
+
+static int ctl_fd = -1;
+static int st_fd = -1;
+
+static int read_u32 (uint32_t* value) {
+    return syscall(SYS_read, ctl_fd, value, sizeof(uint32_t)) == sizeof(uint32_t);
+}
+
+static int write_u32 (uint32_t value) {
+    return syscall(SYS_write, st_fd, &value, sizeof(uint32_t)) == sizeof(uint32_t);
+}
+
+/* fds has the format "<ctl fd>,<status fd>" */
+void snapshot_init (const char* fds) {
+    char* endptr = NULL;
+    long ctl = strtol(fds, &endptr, 10);
+    
+    if (endptr == NULL || *endptr != ',') {
+        return;
+    }
+    
+    long st = strtol(endptr + 1, &endptr, 10);
+    
+    if (endptr == NULL || *endptr != 0) {
+        return;
+    }
+    
+    ctl_fd = (int) ctl;
+    st_fd = (int) st;
+}
+
+/* Forks a new child for every request of the fuzzer. Only returns in the children. */
+static void snapshot_serve (void) {
+    while (1) {
+        uint32_t cmd;
+        
+        if (!read_u32(&cmd)) {
+            _exit(0);
+        }
+        
+        pid_t child = fork();
+        
+        if (child < 0) {
+            _exit(1);
+        } else if (child == 0) {
+            /* Resume execution after the snapshot point */
+            ctl_fd = -1;
+            st_fd = -1;
+            return;
+        }
+        
+        int status = 0;
+        
+        if (!write_u32((uint32_t) child) || waitpid(child, &status, 0) < 0 || !write_u32((uint32_t) status)) {
+            kill(child, SIGKILL);
+            _exit(1);
+        }
+    }
+}
+
+void snapshot_take (void) {
+    if (ctl_fd < 0 || st_fd < 0) {
+        return;
+    }
+    
+    pid_t server = fork();
+    
+    if (server < 0) {
+        return;
+    } else if (server == 0) {
+        snapshot_serve();
+        return;
+    }
+    
+    /* Tell the fuzzer where to find the snapshot and continue with the current input */
+    write_u32((uint32_t) server);
+    ctl_fd = -1;
+    st_fd = -1;
+}
@@ -0,0 +1,4 @@
+#pragma once
+
+void snapshot_init(const char* fds);
+void snapshot_take(void);
@@ -0,0 +1,132 @@
+#include <stdio.h>
+#include <stdint.h>
+#include <string.h>
+#include <assert.h>
+#include "packet_channel.h"
+#include "capture.h"
+
+/* Builds an input with a capture rule and two templates that refer to the captured value
+   and checks that the templates expand to the placeholder first and to the captured value later */
+
+#define TYPE_SEP 2
+#define TYPE_EOF 3
+#define TYPE_CAPTURE 6
+#define TYPE_TEMPLATE 7
+
+#define CAPTURE_REGEX 1
+#define SEGMENT_BYTES 1
+#define SEGMENT_REFERENCE 2
+
+static uint64_t packet_buf[1024];
+static char* cursor = (char*) packet_buf;
+static char* content_start = NULL;
+
+static void put (const void* data, size_t size) {
+    memcpy(cursor, data, size);
+    cursor += size;
+}
+
+static void put_u32 (uint32_t value) {
+    put(&value, sizeof(value));
+}
+
+static void put_u64 (uint64_t value) {
+    put(&value, sizeof(value));
+}
+
+static void begin_packet (uint32_t type, uint32_t conn) {
+    put_u32(type);
+    put_u32(conn);
+    put_u64(0);
+    content_start = cursor;
+}
+
+/* Patches the size into the header and aligns the next packet */
+static void end_packet (void) {
+    uint64_t size = (uint64_t) (cursor - content_start);
+    memcpy(content_start - sizeof(uint64_t), &size, sizeof(size));
+    
+    while ((cursor - (char*) packet_buf) % 8) {
+        *cursor++ = 0;
+    }
+}
+
+static void put_segment (uint32_t kind, uint32_t slot, const char* content) {
+    put_u32(kind);
+    put_u32(slot);
+    put_u64(strlen(content));
+    put(content, strlen(content));
+}
+
+static void put_port_template (void) {
+    begin_packet(TYPE_TEMPLATE, 0);
+    put_segment(SEGMENT_BYTES, 0, "PORT ");
+    put_segment(SEGMENT_REFERENCE, 3, "21");
+    put_segment(SEGMENT_BYTES, 0, "\r\n");
+    end_packet();
+}
+
+static size_t read_packet (size_t conn, char* buf, size_t size) {
+    size_t len = 0;
+    
+    packet_channel_check_available_data();
+    assert(packet_channel_has_data(conn));
+    
+    /* Read bytewise to check that the expansion is kept while the packet is consumed */
+    while (len < size) {
+        size_t ret = packet_channel_read(conn, &buf[len], 1);
+        
+        if (ret == 0 || buf[len] == '\n') {
+            len += ret;
+            break;
+        }
+        
+        len += ret;
+    }
+    
+    buf[len] = 0;
+    return len;
+}
+
+int main (void) {
+    const char* pattern = "port ([0-9]+)";
+    char buf[64];
+    
+    begin_packet(TYPE_SEP, 0);
+    
+    begin_packet(TYPE_CAPTURE, 0);
+    put_u32(3);
+    put_u32(CAPTURE_REGEX);
+    put_u32(1);
+    put_u32(0);
+    put_u64(0);
+    put_u64(0);
+    put(pattern, strlen(pattern) + 1);
+    end_packet();
+    
+    put_port_template();
+    put_port_template();
+    
+    begin_packet(TYPE_SEP, 0);
+    begin_packet(TYPE_EOF, 0);
+    
+    packet_channel_init(packet_buf);
+    
+    /* Nothing captured yet, the placeholder is sent */
+    read_packet(0, buf, sizeof(buf) - 1);
+    assert(strcmp(buf, "PORT 21\r\n") == 0);
+    
+    /* Responses on other connections and responses that don't match are ignored */
+    capture_response(1, "port 1", 6);
+    capture_response(0, "200 ok", 6);
+    capture_response(0, "227 data port 4242 open", 23);
+    
+    read_packet(0, buf, sizeof(buf) - 1);
+    assert(strcmp(buf, "PORT 4242\r\n") == 0);
+    
+    packet_channel_check_available_data();
+    assert(packet_channel_read(0, buf, sizeof(buf)) == 0);
+    
+    printf("OK\n");
+    return 0;
+}
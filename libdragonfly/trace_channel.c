@@ -0,0 +1,55 @@
+#include <stddef.h>
+#include <stdint.h>
+#include <time.h>
+
+#include "trace_channel.h"
+
+#define NO_PACKET ((uint32_t) -1)
+
+typedef struct {
+    uint64_t capacity;
+    uint64_t size;
+    char content[];
+} __attribute__((packed)) TraceChannel;
+
+typedef struct {
+    uint32_t packet;
+    uint32_t conn;
+    int32_t fd;
+    uint32_t reserved;
+    uint64_t timestamp;
+} __attribute__((packed)) TraceEvent;
+
+static TraceChannel* channel = NULL;
+static uint32_t last_packet = NO_PACKET;
+
+void trace_channel_init (void* buffer) {
+    channel = (TraceChannel*) buffer;
+    last_packet = NO_PACKET;
+}
+
+/* Records when a data packet gets read for the first time and through which fd */
+void trace_channel_read (int fd, size_t conn, uint32_t packet) {
+    if (!channel || packet == NO_PACKET || packet == last_packet || channel->capacity < sizeof(TraceChannel)) {
+        return;
+    }
+    
+    last_packet = packet;
+    
+    /* Silently drop events that don't fit anymore */
+    if (channel->size + sizeof(TraceEvent) > channel->capacity - sizeof(TraceChannel)) {
+        return;
+    }
+    
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    
+    TraceEvent* event = (TraceEvent*) &channel->content[channel->size];
+    event->packet = packet;
+    event->conn = (uint32_t) conn;
+    event->fd = (int32_t) fd;
+    event->reserved = 0;
+    event->timestamp = (uint64_t) now.tv_sec * 1000000000ULL + (uint64_t) now.tv_nsec;
+    
+    channel->size += sizeof(TraceEvent);
+}
@@ -0,0 +1,7 @@
+#pragma once
+
+#include <stddef.h>
+#include <stdint.h>
+
+void trace_channel_init(void* buffer);
+void trace_channel_read(int fd, size_t conn, uint32_t packet);